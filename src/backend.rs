@@ -4,37 +4,63 @@ use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+use crate::backend::activation::ActivationTiming;
 use crate::backend::device::{new_input, new_output, Input, Output};
-use crate::backend::midi_handler::{EventBufferItem, Listener, QueueHandler, QueueItems};
+use crate::backend::id::Id;
+use crate::backend::midi_handler::{
+    connect_output, schedule, EventBufferItem, Listener, QueueHandler, QueueItems, QueueMessage,
+    RouteKey, RouteState,
+};
+use crate::backend::morph::{plan_activation, plan_release, ActivationPlan, MorphState};
+use crate::backend::preset::PresetFeedback;
 use crate::backend::properties::Properties;
 use crate::gui::state::State;
+use crate::utils::repaint_gui;
 use egui::Context;
-use midir::MidiIO;
+use midir::{MidiIO, MidiInput, MidiOutput};
 use midly::live::LiveEvent;
 use midly::num::{u4, u7};
 use once_cell::sync::Lazy;
 use regex::Regex;
 
+pub mod activation;
+pub mod autosave;
 pub mod background_functions;
 pub mod common_settings;
 mod device;
+pub mod device_alias;
+pub mod file_error;
+pub mod file_format;
+pub mod history;
+pub mod id;
 pub mod input_settings;
 pub mod midi_handler;
+mod morph;
 pub mod output_settings;
 pub mod pipewire_utils;
 pub mod preset;
+pub mod preset_file;
 pub mod properties;
+pub mod schema;
+pub mod setlist;
+pub mod triggers;
+pub mod validate;
 
 pub struct Backend {
     properties: Arc<Mutex<Properties>>,
     state: Arc<Mutex<State>>,
     gui_ctx: Arc<Mutex<Option<Context>>>,
 
-    input_listeners: Vec<Input>,
+    input_listeners: Vec<(Id, Input)>,
     output_handlers: Arc<Mutex<HashMap<String, Output>>>,
     event_buffer: Arc<Mutex<HashMap<LiveEvent<'static>, HashSet<EventBufferItem>>>>,
     held_pedals: Arc<Mutex<HashMap<(u4, u7), u7>>>, // (channel, controller): value
     queue: Arc<Mutex<BinaryHeap<Reverse<u64>>>>,
+    route_state: Arc<Mutex<HashMap<RouteKey, RouteState>>>,
+    /// Last feedback that was sent: (current preset, feedback output, feedback settings)
+    sent_feedback: Option<(usize, String, Vec<PresetFeedback>)>,
+    activated_preset: Arc<Mutex<Option<Id>>>,
+    morph_state: Arc<Mutex<MorphState>>,
 }
 
 impl Backend {
@@ -49,6 +75,10 @@ impl Backend {
             event_buffer: Arc::new(Mutex::new(HashMap::new())),
             held_pedals: Arc::new(Mutex::new(HashMap::new())),
             queue: Arc::new(Mutex::new(BinaryHeap::new())),
+            route_state: Arc::new(Mutex::new(HashMap::new())),
+            sent_feedback: None,
+            activated_preset: Arc::new(Mutex::new(None)),
+            morph_state: Arc::new(Mutex::new(MorphState::default())),
         }
     }
 
@@ -57,23 +87,25 @@ impl Backend {
         let midi_in = new_input();
         let midi_out = new_output();
 
-        let (event_sender, event_receiver) = mpsc::channel::<(u64, QueueItems)>();
+        let (event_sender, event_receiver) = mpsc::channel::<QueueMessage>();
 
         let mut queue_handler = QueueHandler::new(
             event_receiver,
             Arc::clone(&self.output_handlers),
             Arc::clone(&self.queue),
+            Arc::clone(&self.morph_state),
         );
         let _ = thread::spawn(move || queue_handler.run());
 
         loop {
             {
-                let properties = self.properties.lock().unwrap();
+                let mut properties = self.properties.lock().unwrap();
                 let mut state = self.state.lock().unwrap();
 
                 // Send available ports to frontend
                 state.available_inputs = get_ports(&midi_in);
                 state.available_outputs = get_ports(&midi_out);
+                state.resolve_aliases(&properties.aliases);
 
                 // New input factory:
                 let new_listener = |name, input_id| {
@@ -87,7 +119,12 @@ impl Backend {
                         event_buffer: Arc::clone(&self.event_buffer),
                         held_pedals: Arc::clone(&self.held_pedals),
                         queue: Arc::clone(&self.queue),
+                        route_state: Arc::clone(&self.route_state),
                         event_sender: event_sender.clone(),
+                        activated_preset: Arc::clone(&self.activated_preset),
+                        morph_state: Arc::clone(&self.morph_state),
+                        bank_select: HashMap::new(),
+                        clock_ticks: 0,
                     }
                     .create()
                 };
@@ -99,34 +136,56 @@ impl Backend {
                     .filter(|s| !s.port_name.is_empty())
                     .enumerate()
                     .for_each(|(i, new_input)| {
-                        let port = state
-                            .available_inputs
-                            .iter()
-                            .find(|p| p.readable == new_input.port_name);
+                        let port = state.input_port(&new_input.port_name);
 
-                        if let Some(input) = self.input_listeners.get_mut(i) {
-                            if input.port_name.readable != new_input.port_name {
+                        let id = new_input.id;
+                        if let Some((input_id, input)) = self.input_listeners.get_mut(i) {
+                            if port != Some(&input.port_name) || *input_id != id {
                                 // Input setting has changed, change connection
                                 if let Some(Ok(new_input)) =
-                                    port.map(|p| new_listener(p.clone(), i))
+                                    port.map(|p| new_listener(p.clone(), id))
                                 {
                                     *input = new_input;
+                                    *input_id = id;
                                 }
                             }
                         } else {
                             // New input, add new connection
-                            if let Some(Ok(new_input)) = port.map(|p| new_listener(p.clone(), i)) {
-                                self.input_listeners.push(new_input);
+                            if let Some(Ok(new_input)) = port.map(|p| new_listener(p.clone(), id)) {
+                                self.input_listeners.push((id, new_input));
                             }
                         }
                     });
                 // Remove disconnected and removed input listeners
-                self.input_listeners.retain(|input| {
+                self.input_listeners.retain(|(_, input)| {
                     // Remove input listeners that do not exist anymore
                     state.available_inputs.contains(&input.port_name) &&
                         // Remove input listeners that are not selected by the user anymore
-                        properties.inputs.iter().any(|i| i.port_name == input.port_name.readable)
+                        properties.inputs.iter().any(|i| state.input_port(&i.port_name) == Some(&input.port_name))
                 });
+
+                // Presets that wait for the next bar switch right away when the MIDI clock stops
+                if properties.apply_pending_preset(true, true, false) {
+                    repaint_gui(&self.gui_ctx);
+                }
+
+                // Release the previous preset, and activate presets that do not wait for the first event
+                activate_preset(
+                    &properties,
+                    &state,
+                    &self.output_handlers,
+                    &self.activated_preset,
+                    &self.morph_state,
+                    &event_sender,
+                );
+
+                // Light up the pad of the current preset
+                send_feedback(
+                    &properties,
+                    &state,
+                    &self.output_handlers,
+                    &mut self.sent_feedback,
+                );
             }
             thread::sleep(Duration::from_millis(100));
         }
@@ -145,6 +204,120 @@ impl Backend {
     }
 }
 
+fn activate_preset(
+    properties: &Properties,
+    state: &State,
+    output_handlers: &Arc<Mutex<HashMap<String, Output>>>,
+    activated_preset: &Arc<Mutex<Option<Id>>>,
+    morph_state: &Arc<Mutex<MorphState>>,
+    event_sender: &mpsc::Sender<QueueMessage>,
+) {
+    // Release the routes of the previous preset as soon as it is left
+    let plan = plan_release(properties, &mut morph_state.lock().unwrap());
+    send_plan(plan, state, output_handlers, event_sender);
+
+    let Some(preset) = properties.presets.get(properties.current_preset) else {
+        return;
+    };
+    if preset.activation.timing != ActivationTiming::Immediately {
+        return;
+    }
+    let previous = activated_preset.lock().unwrap().replace(preset.id);
+    if previous == Some(preset.id) {
+        return;
+    }
+
+    let plan = plan_activation(
+        properties,
+        previous,
+        preset.id,
+        &mut morph_state.lock().unwrap(),
+    );
+    send_plan(plan, state, output_handlers, event_sender);
+}
+
+fn send_plan(
+    plan: ActivationPlan,
+    state: &State,
+    output_handlers: &Arc<Mutex<HashMap<String, Output>>>,
+    event_sender: &mpsc::Sender<QueueMessage>,
+) {
+    let mut output_handlers = output_handlers.lock().unwrap();
+    let connected: HashSet<_> = plan
+        .ports()
+        .into_iter()
+        .filter(|port_name| connect_output(state, &mut output_handlers, port_name))
+        .collect();
+    schedule(event_sender, &plan);
+    let events: QueueItems = plan
+        .immediate
+        .into_iter()
+        .filter(|(port_name, _)| connected.contains(port_name))
+        .collect();
+    if events.is_empty() {
+        return;
+    }
+    if let Err(e) = event_sender.send(QueueMessage::Immediate(events)) {
+        eprintln!("Error sending events {e:?}");
+    }
+}
+
+fn send_feedback(
+    properties: &Properties,
+    state: &State,
+    output_handlers: &Arc<Mutex<HashMap<String, Output>>>,
+    sent_feedback: &mut Option<(usize, String, Vec<PresetFeedback>)>,
+) {
+    let port_name = &properties.feedback_output;
+    if port_name.is_empty() {
+        *sent_feedback = None;
+        return;
+    }
+    let feedback: Vec<_> = properties
+        .presets
+        .iter()
+        .map(|p| p.feedback.clone())
+        .collect();
+    let current = (properties.current_preset, port_name.clone(), feedback);
+    if sent_feedback.as_ref() == Some(&current) {
+        return;
+    }
+
+    let mut output_handlers = output_handlers.lock().unwrap();
+    if !connect_output(state, &mut output_handlers, port_name) {
+        // Send everything again when the output comes back
+        *sent_feedback = None;
+        return;
+    }
+    let output = output_handlers.get_mut(port_name).unwrap();
+    let (current_preset, _, feedback) = &current;
+    // Turn off the other presets first, in case they share a pad with the current one
+    feedback
+        .iter()
+        .enumerate()
+        .filter(|(i, f)| f.enabled && i != current_preset)
+        .chain(
+            feedback
+                .iter()
+                .enumerate()
+                .filter(|(i, f)| f.enabled && i == current_preset),
+        )
+        .for_each(|(i, f)| {
+            output
+                .connection
+                .send(&f.message(i == *current_preset))
+                .unwrap_or_else(|_| eprintln!("Failed to send feedback to {port_name}"));
+        });
+    *sent_feedback = Some(current);
+}
+
+/// Available input and output ports, without running the backend
+pub fn available_ports() -> Option<(Vec<MidiPort>, Vec<MidiPort>)> {
+    let midi_in = MidiInput::new("Live Midi Splitter ports").ok()?;
+    let midi_out = MidiOutput::new("Live Midi Splitter ports").ok()?;
+    Some((get_ports(&midi_in), get_ports(&midi_out)))
+}
+
 fn get_ports<T: MidiIO>(midi_io: &T) -> Vec<MidiPort> {
     midi_io
         .ports()
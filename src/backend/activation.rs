@@ -0,0 +1,202 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Longest morph or fade in seconds
+pub const MAX_MORPH_TIME: f32 = 60.0;
+
+/// Limit a morph or fade time from a file to 0 - `MAX_MORPH_TIME` seconds, NaN is no morph
+pub fn clamp_time(time: f32) -> f32 {
+    if time.is_nan() {
+        0.0
+    } else {
+        time.clamp(0.0, MAX_MORPH_TIME)
+    }
+}
+
+/// Messages that are sent to outputs when a preset becomes active
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct Activation {
+    pub timing: ActivationTiming,
+    pub messages: Vec<ActivationMessage>,
+    /// Seconds to morph from the previous preset to this one, 0 is no morph
+    #[serde(default)]
+    pub morph_time: f32,
+    /// Controller that fades routes in and out during a morph
+    #[serde(default)]
+    pub fade: MorphFade,
+}
+
+impl Activation {
+    /// Raw MIDI data of all messages, for each output
+    pub fn events(&self) -> Vec<(String, Vec<u8>)> {
+        self.messages
+            .iter()
+            .filter(|m| !m.port_name.is_empty())
+            .flat_map(|m| {
+                m.kind
+                    .data()
+                    .into_iter()
+                    .map(|data| (m.port_name.clone(), data))
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ActivationTiming {
+    /// Send before the first event that is sent to the new preset
+    #[default]
+    OnFirstEvent,
+    /// Send as soon as the preset is selected
+    Immediately,
+}
+
+impl ActivationTiming {
+    pub fn all() -> &'static [ActivationTiming; 2] {
+        &[
+            ActivationTiming::OnFirstEvent,
+            ActivationTiming::Immediately,
+        ]
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            ActivationTiming::OnFirstEvent => "On first event",
+            ActivationTiming::Immediately => "Immediately",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MorphFade {
+    Off,
+    Volume,
+    #[default]
+    Expression,
+}
+
+impl MorphFade {
+    pub fn all() -> &'static [MorphFade; 3] {
+        &[MorphFade::Off, MorphFade::Volume, MorphFade::Expression]
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            MorphFade::Off => "Don't fade",
+            MorphFade::Volume => "Fade with volume (CC 7)",
+            MorphFade::Expression => "Fade with expression (CC 11)",
+        }
+    }
+
+    pub fn controller(&self) -> Option<u8> {
+        match self {
+            MorphFade::Off => None,
+            MorphFade::Volume => Some(7),
+            MorphFade::Expression => Some(11),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct ActivationMessage {
+    pub port_name: String,
+    pub kind: ActivationKind,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub enum ActivationKind {
+    /// Bank select (-1 is not sent) and program change, channel 1 - 16
+    Program {
+        channel: u8,
+        bank_msb: i8,
+        bank_lsb: i8,
+        program: u8,
+    },
+    /// Channel 1 - 16
+    Controller {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// Hexadecimal bytes, i.e. "F0 7E 7F 09 01 F7"
+    SysEx(String),
+}
+
+impl ActivationKind {
+    pub fn all() -> [ActivationKind; 3] {
+        [
+            ActivationKind::default(),
+            ActivationKind::Controller {
+                channel: 1,
+                controller: 7,
+                value: 100,
+            },
+            ActivationKind::SysEx("F0 F7".to_string()),
+        ]
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            ActivationKind::Program { .. } => "Program change",
+            ActivationKind::Controller { .. } => "CC",
+            ActivationKind::SysEx(_) => "SysEx",
+        }
+    }
+
+    pub fn data(&self) -> Vec<Vec<u8>> {
+        match self {
+            ActivationKind::Program {
+                channel,
+                bank_msb,
+                bank_lsb,
+                program,
+            } => {
+                let channel = (*channel).clamp(1, 16) - 1;
+                let mut data = Vec::new();
+                if *bank_msb >= 0 {
+                    data.push(vec![0xB0 | channel, 0, *bank_msb as u8]);
+                }
+                if *bank_lsb >= 0 {
+                    data.push(vec![0xB0 | channel, 32, *bank_lsb as u8]);
+                }
+                data.push(vec![0xC0 | channel, program & 0x7F]);
+                data
+            }
+            ActivationKind::Controller {
+                channel,
+                controller,
+                value,
+            } => vec![vec![
+                0xB0 | ((*channel).clamp(1, 16) - 1),
+                controller & 0x7F,
+                value & 0x7F,
+            ]],
+            ActivationKind::SysEx(hex) => parse_sysex(hex).into_iter().collect(),
+        }
+    }
+}
+
+impl Default for ActivationKind {
+    fn default() -> Self {
+        ActivationKind::Program {
+            channel: 1,
+            bank_msb: -1,
+            bank_lsb: -1,
+            program: 0,
+        }
+    }
+}
+
+/// Parse a hexadecimal SysEx message, returns None if it is not a valid SysEx message
+pub fn parse_sysex(hex: &str) -> Option<Vec<u8>> {
+    let data = hex
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let valid = data.len() >= 2
+        && data.first() == Some(&0xF0)
+        && data.last() == Some(&0xF7)
+        && data[1..data.len() - 1].iter().all(|b| *b < 0x80);
+    valid.then_some(data)
+}
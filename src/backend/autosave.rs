@@ -0,0 +1,207 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use pro_serde_versioned::{VersionedDeserialize, VersionedUpgrade};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::backend::id::Id;
+use crate::backend::properties::{Properties, PropertiesVersioned};
+use crate::utils::{config_dir, serialise_properties};
+
+/// Time between snapshots of unsaved changes
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Amount of snapshots that are kept in the autosave directory
+const MAX_SNAPSHOTS: usize = 20;
+/// Followed by the instance, exists while it has unsaved changes and contains the file name of its latest snapshot
+const UNSAVED_MARKER: &str = "unsaved-";
+/// Followed by the instance and `.lock`, locked while the instance is running
+const INSTANCE_LOCK: &str = "instance-";
+
+/// This running program, so several instances do not recover each other's changes
+struct Instance {
+    id: Id,
+    /// Keeps the lock until the program ends
+    _lock: File,
+}
+
+static INSTANCE: OnceLock<Option<Instance>> = OnceLock::new();
+
+/// Autosaved properties with unsaved changes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    /// File that the changes were made to
+    pub file: Option<PathBuf>,
+    /// Seconds since the unix epoch
+    pub time: u64,
+    /// Versioned properties, as they are saved to a file
+    properties: Value,
+    /// Instance that made the snapshot
+    #[serde(skip)]
+    instance: String,
+}
+
+impl Snapshot {
+    pub fn properties(&self) -> Option<Properties> {
+        PropertiesVersioned::versioned_deserialize::<Value>(&self.properties)
+            .ok()
+            .map(|p| p.upgrade_to_latest())
+    }
+
+    /// How long ago this snapshot was made
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.time))
+    }
+
+    /// Do not offer these changes again, the snapshots themselves are kept
+    pub fn dismiss(&self) {
+        if let Some(dir) = autosave_dir() {
+            remove_instance(&dir, &self.instance);
+        }
+    }
+}
+
+/// Writes snapshots of unsaved changes, called regularly from the background functions
+pub struct Autosave {
+    last: Option<Value>,
+    last_time: Instant,
+    /// A snapshot has been written since the last save
+    unsaved: bool,
+}
+
+impl Autosave {
+    pub fn new() -> Self {
+        Self {
+            last: None,
+            last_time: Instant::now(),
+            unsaved: false,
+        }
+    }
+
+    pub fn update(&mut self, properties: &Properties, file: Option<PathBuf>) {
+        if !properties.changed {
+            // Everything is saved, no need to recover anything
+            if self.unsaved {
+                mark_saved();
+                self.unsaved = false;
+                self.last = None;
+            }
+            return;
+        }
+        if self.last_time.elapsed() < AUTOSAVE_INTERVAL {
+            return;
+        }
+        let serialised = serialise_properties(properties);
+        if self.last.as_ref() == Some(&serialised) {
+            return;
+        }
+        if write_snapshot(serialised.clone(), file).is_some() {
+            self.last = Some(serialised);
+            self.last_time = Instant::now();
+            self.unsaved = true;
+        }
+    }
+}
+
+/// Keep a last snapshot of unsaved changes when closing normally, without offering to recover it
+pub fn finish(properties: &Properties, file: Option<PathBuf>) {
+    if properties.changed {
+        write_snapshot(serialise_properties(properties), file);
+    }
+    if let Some((dir, Some(instance))) = autosave_dir().zip(INSTANCE.get()) {
+        remove_instance(&dir, &instance.id.to_string());
+    }
+}
+
+/// Latest snapshot of an instance that ended with unsaved changes, running instances are skipped
+pub fn recovery() -> Option<Snapshot> {
+    let dir = autosave_dir()?;
+    fs::read_dir(&dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let instance = name.strip_prefix(INSTANCE_LOCK)?.strip_suffix(".lock")?;
+            // The lock is released when an instance ends, in any way
+            File::open(entry.path()).ok()?.try_lock().ok()?;
+            let snapshot = fs::read_to_string(dir.join(format!("{UNSAVED_MARKER}{instance}")))
+                .ok()
+                .and_then(|name| fs::read_to_string(dir.join(name.trim())).ok())
+                .and_then(|contents| serde_json::from_str::<Snapshot>(&contents).ok());
+            if snapshot.is_none() {
+                // Ended without unsaved changes
+                remove_instance(&dir, instance);
+            }
+            Some(Snapshot {
+                instance: instance.to_string(),
+                ..snapshot?
+            })
+        })
+        .max_by_key(|snapshot| snapshot.time)
+}
+
+/// Forget about the unsaved changes of this instance, the snapshots themselves are kept
+pub fn mark_saved() {
+    if let Some((dir, Some(instance))) = autosave_dir().zip(INSTANCE.get()) {
+        let _ = fs::remove_file(dir.join(format!("{UNSAVED_MARKER}{}", instance.id)));
+    }
+}
+
+pub fn autosave_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("autosave"))
+}
+
+/// Lock file of this instance, created when it writes its first snapshot
+fn instance(dir: &Path) -> Option<&'static Instance> {
+    INSTANCE
+        .get_or_init(|| {
+            let id = Id::random();
+            let lock = File::create(dir.join(format!("{INSTANCE_LOCK}{id}.lock"))).ok()?;
+            lock.try_lock().ok()?;
+            Some(Instance { id, _lock: lock })
+        })
+        .as_ref()
+}
+
+fn remove_instance(dir: &Path, instance: &str) {
+    let _ = fs::remove_file(dir.join(format!("{UNSAVED_MARKER}{instance}")));
+    let _ = fs::remove_file(dir.join(format!("{INSTANCE_LOCK}{instance}.lock")));
+}
+
+fn write_snapshot(properties: Value, file: Option<PathBuf>) -> Option<()> {
+    let dir = autosave_dir()?;
+    fs::create_dir_all(&dir).ok()?;
+    let instance = instance(&dir)?;
+    let snapshot = Snapshot {
+        file,
+        time: now(),
+        properties,
+        instance: instance.id.to_string(),
+    };
+    let name = format!("snapshot-{}-{}.json", snapshot.time, instance.id);
+    fs::write(dir.join(&name), serde_json::to_string(&snapshot).ok()?).ok()?;
+    fs::write(dir.join(format!("{UNSAVED_MARKER}{}", instance.id)), &name).ok()?;
+
+    // Remove the oldest snapshots
+    let mut snapshots: Vec<_> = fs::read_dir(&dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("snapshot-"))
+        .collect();
+    snapshots.sort();
+    let excess = snapshots.len().saturating_sub(MAX_SNAPSHOTS);
+    snapshots.iter().take(excess).for_each(|name| {
+        let _ = fs::remove_file(dir.join(name));
+    });
+    Some(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -1,11 +1,14 @@
+use crate::backend::autosave::Autosave;
 use crate::backend::pipewire_utils::{Pipewire, PipewireError};
 use crate::backend::properties::Properties;
 use crate::gui::state::State;
-use crate::utils::{repaint_gui, serialise_properties};
+use crate::utils::{read_properties, reload, repaint_gui, serialise_properties};
 use egui::Context;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 pub fn run_background_functions(
     state: Arc<Mutex<State>>,
@@ -13,6 +16,11 @@ pub fn run_background_functions(
     properties: Arc<Mutex<Properties>>,
 ) {
     let mut serialised = serialise_properties(&properties.lock().unwrap());
+    // Properties when the undo history was last updated
+    let mut recorded = serialised.clone();
+    let mut autosave = Autosave::new();
+    // Open file and when it was last modified
+    let mut file_modified: Option<(PathBuf, SystemTime)> = None;
 
     loop {
         // Update Pipewire Info
@@ -35,10 +43,20 @@ pub fn run_background_functions(
         {
             let mut repaint = false;
             let serialised_new = serialise_properties(&properties.lock().unwrap());
+            // Add changes to the undo history, which only needs to look at them if something changed
+            if serialised_new != recorded || properties.lock().unwrap().loaded {
+                let mut properties = properties.lock().unwrap();
+                if state.lock().unwrap().history.record(&mut properties) {
+                    repaint = true;
+                }
+                recorded = serialised_new.clone();
+            }
             if properties.lock().unwrap().saved {
                 serialised = serialised_new;
                 properties.lock().unwrap().changed = false;
                 properties.lock().unwrap().saved = false;
+                // Saving or loading resolves a conflict with changes on disk
+                state.lock().unwrap().file_changed = false;
                 repaint = true;
             } else {
                 let changed = serialised != serialised_new;
@@ -53,10 +71,49 @@ pub fn run_background_functions(
                 repaint_gui(&gui_ctx);
             }
         }
+        // Check if the open file was changed by another program
+        {
+            let file_path = state.lock().unwrap().file_path().clone();
+            let modified = file_path
+                .as_ref()
+                .and_then(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+            match (file_path, modified) {
+                (Some(path), Some(modified)) => {
+                    let changed_on_disk = file_modified
+                        .as_ref()
+                        .is_some_and(|(p, m)| *p == path && *m != modified);
+                    if changed_on_disk && file_changed(&path, &properties) {
+                        if properties.lock().unwrap().changed {
+                            // Let the user decide what to keep
+                            state.lock().unwrap().file_changed = true;
+                        } else {
+                            // Try again on the next change if it is not readable yet
+                            let _ = reload(&path, &properties);
+                        }
+                        repaint_gui(&gui_ctx);
+                    }
+                    file_modified = Some((path, modified));
+                }
+                _ => file_modified = None,
+            }
+        }
+        // Keep a snapshot of unsaved changes in case we crash
+        {
+            let properties = properties.lock().unwrap();
+            let file_path = state.lock().unwrap().file_path().clone();
+            autosave.update(&properties, file_path);
+        }
         sleep(Duration::from_millis(500));
     }
 }
 
+/// Check if the contents of the file differ from the properties, i.e. it was not just saved by us
+fn file_changed(path: &PathBuf, properties: &Arc<Mutex<Properties>>) -> bool {
+    read_properties(path).is_ok_and(|loaded| {
+        serialise_properties(&loaded) != serialise_properties(&properties.lock().unwrap())
+    })
+}
+
 pub fn update_pipewire(state: &Arc<Mutex<State>>) -> Result<bool, PipewireError> {
     let update = if let Some(pipewire) = &mut state.lock().unwrap().pipewire_status {
         pipewire.update()?
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::f64::consts::E;
 
@@ -76,9 +77,10 @@ pub trait CommonSettings {
     }
 }
 
+/// (channel 1 - 16 or 0 for any, controller or -1 for any, mapping), the last entry matches everything else
 pub type CcMap = Vec<(u8, i8, CcMapping)>;
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq, Debug, Default)]
 pub enum CcMapping {
     #[default]
     PassThrough,
@@ -122,7 +124,7 @@ impl CcMapping {
 
 pub type ChannelMap = Vec<(u8, ChannelMapping)>;
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq, Debug, Default)]
 pub enum ChannelMapping {
     #[default]
     PassThrough,
@@ -156,7 +158,7 @@ impl ChannelMapping {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug, Default)]
 pub enum VelocityCurve {
     #[default]
     Linear,
@@ -166,7 +168,7 @@ pub enum VelocityCurve {
     SCurve(f64),
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
 pub struct VelocityRange {
     pub min: u8,
     pub max: u8,
@@ -185,15 +187,107 @@ impl Default for VelocityRange {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
 pub enum OutsideRange {
     Ignore,
     Clamp,
     Scale,
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Default, Clone, PartialEq, Debug)]
 pub struct Transpose {
     pub value: i8,
     pub ignore_global: bool,
 }
+
+/// Filters and maps that are applied to every input, before the input and route settings
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct GlobalSettings {
+    #[serde(default)]
+    pub key_filter_enabled: bool,
+    #[serde(default = "default_filter")]
+    pub key_filter: (u8, u8),
+    #[serde(default = "default_cc_map")]
+    pub cc_map: CcMap,
+    #[serde(default = "default_channel_map")]
+    pub channel_map: ChannelMap,
+    #[serde(default)]
+    pub velocity_curve: VelocityCurve,
+    #[serde(default)]
+    pub velocity_range: VelocityRange,
+    /// Unused, the global transpose is `Properties::transpose`
+    #[serde(skip)]
+    transpose: Transpose,
+}
+
+impl Default for GlobalSettings {
+    fn default() -> Self {
+        Self {
+            key_filter_enabled: false,
+            key_filter: default_filter(),
+            cc_map: default_cc_map(),
+            channel_map: default_channel_map(),
+            velocity_curve: VelocityCurve::default(),
+            velocity_range: VelocityRange::default(),
+            transpose: Transpose::default(),
+        }
+    }
+}
+
+impl CommonSettings for GlobalSettings {
+    fn key_filter_enabled_mut(&mut self) -> &mut bool {
+        &mut self.key_filter_enabled
+    }
+
+    fn key_filter_mut(&mut self) -> &mut (u8, u8) {
+        &mut self.key_filter
+    }
+
+    fn cc_map_mut(&mut self) -> &mut CcMap {
+        &mut self.cc_map
+    }
+
+    fn channel_map_mut(&mut self) -> &mut ChannelMap {
+        &mut self.channel_map
+    }
+
+    fn velocity_curve_mut(&mut self) -> &mut VelocityCurve {
+        &mut self.velocity_curve
+    }
+
+    fn velocity_range_mut(&mut self) -> &mut VelocityRange {
+        &mut self.velocity_range
+    }
+
+    fn transpose_mut(&mut self) -> &mut Transpose {
+        &mut self.transpose
+    }
+
+    fn key_filter_enabled(&self) -> bool {
+        self.key_filter_enabled
+    }
+
+    fn key_filter(&self) -> (u8, u8) {
+        self.key_filter
+    }
+
+    fn cc_map(&self) -> &CcMap {
+        &self.cc_map
+    }
+
+    fn channel_map(&self) -> &ChannelMap {
+        &self.channel_map
+    }
+
+    fn velocity_curve(&self) -> &VelocityCurve {
+        &self.velocity_curve
+    }
+
+    fn velocity_range(&self) -> &VelocityRange {
+        &self.velocity_range
+    }
+
+    fn transpose(&self) -> &Transpose {
+        &self.transpose
+    }
+}
@@ -1,3 +1,4 @@
+use crate::backend::id::Id;
 use crate::backend::MidiPort;
 use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
 use std::fmt::{Debug, Formatter};
@@ -5,13 +6,13 @@ use std::fmt::{Debug, Formatter};
 pub struct Input {
     pub port_name: MidiPort,
     #[allow(dead_code)] // TODO: do we ever want to use this?
-    pub connection: MidiInputConnection<usize>,
+    pub connection: MidiInputConnection<Option<Id>>,
 }
 
 impl Input {
     pub fn new<F>(port_name: MidiPort, callback: F) -> Result<Self, ConnectError>
     where
-        F: FnMut(u64, &[u8], &mut usize) + Send + 'static,
+        F: FnMut(u64, &[u8], &mut Option<Id>) + Send + 'static,
     {
         let input = new_input();
         let connection = Self::connect(input, &port_name.internal, callback)?;
@@ -26,9 +27,9 @@ impl Input {
         input: MidiInput,
         port_name: &String,
         callback: F,
-    ) -> Result<MidiInputConnection<usize>, ConnectError>
+    ) -> Result<MidiInputConnection<Option<Id>>, ConnectError>
     where
-        F: FnMut(u64, &[u8], &mut usize) + Send + 'static,
+        F: FnMut(u64, &[u8], &mut Option<Id>) + Send + 'static,
     {
         // Find port by name
         if let Some(port) = input
@@ -38,7 +39,7 @@ impl Input {
         {
             // Create connection
             input
-                .connect(port, "input", callback, 0)
+                .connect(port, "input", callback, None)
                 .or(Err(ConnectError {}))
         } else {
             Err(ConnectError {})
@@ -53,6 +54,8 @@ impl Debug for Input {
 }
 
 pub struct Output {
+    /// Port that we are connected to
+    pub port: MidiPort,
     pub connection: MidiOutputConnection,
 }
 
@@ -61,7 +64,10 @@ impl Output {
         let output = new_output();
         let connection = Self::connect(output, &port_name.internal)?;
 
-        Ok(Self { connection })
+        Ok(Self {
+            port: port_name.clone(),
+            connection,
+        })
     }
 
     fn connect(
@@ -0,0 +1,154 @@
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::MidiPort;
+
+/// Name for a device that can be used instead of a port name, so a file works on other machines
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct DeviceAlias {
+    pub name: String,
+    /// Regular expressions for the port name on each machine, the first one that matches is used
+    #[schemars(with = "Vec<String>")]
+    pub patterns: Vec<PortPattern>,
+}
+
+impl DeviceAlias {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            patterns: vec![PortPattern::default()],
+        }
+    }
+
+    /// Port that this alias refers to on this machine
+    pub fn find<'a>(&self, ports: &'a [MidiPort]) -> Option<&'a MidiPort> {
+        self.patterns
+            .iter()
+            .filter_map(|pattern| pattern.regex())
+            .find_map(|re| ports.iter().find(|port| re.is_match(&port.readable)))
+    }
+}
+
+/// Regular expression for a port name, compiled when it is loaded or edited
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(into = "String", from = "String")]
+pub struct PortPattern {
+    source: String,
+    regex: Result<Regex, String>,
+}
+
+impl PortPattern {
+    pub fn new(source: String) -> Self {
+        let regex = Regex::new(&source).map_err(|e| e.to_string());
+        Self { source, regex }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Why this is not a valid regular expression
+    pub fn error(&self) -> Option<&str> {
+        self.regex.as_ref().err().map(String::as_str)
+    }
+
+    /// Compiled expression, None if it is empty or invalid
+    fn regex(&self) -> Option<&Regex> {
+        self.regex.as_ref().ok().filter(|_| !self.source.is_empty())
+    }
+}
+
+impl Default for PortPattern {
+    fn default() -> Self {
+        Self::new(String::new())
+    }
+}
+
+impl PartialEq for PortPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl From<String> for PortPattern {
+    fn from(source: String) -> Self {
+        Self::new(source)
+    }
+}
+
+impl From<PortPattern> for String {
+    fn from(pattern: PortPattern) -> Self {
+        pattern.source
+    }
+}
+
+/// Alias names with the port they refer to, for the aliases that match an available port
+pub fn resolve_aliases(aliases: &[DeviceAlias], ports: &[MidiPort]) -> Vec<(String, MidiPort)> {
+    aliases
+        .iter()
+        .filter(|alias| !alias.name.is_empty())
+        .filter_map(|alias| Some((alias.name.clone(), alias.find(ports)?.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports(names: &[&str]) -> Vec<MidiPort> {
+        names
+            .iter()
+            .map(|name| MidiPort::new(name.to_string(), format!("internal {name}")))
+            .collect()
+    }
+
+    fn alias(name: &str, patterns: &[&str]) -> DeviceAlias {
+        DeviceAlias {
+            name: name.to_string(),
+            patterns: patterns
+                .iter()
+                .map(|p| PortPattern::new(p.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn first_matching_pattern_wins() {
+        let ports = ports(&["USB MIDI 1", "Stage Piano", "Stage Piano MIDI 2"]);
+        let aliases = [
+            alias("Piano", &["^Nord", "Stage Piano", "USB"]),
+            alias("Other", &["^Nord"]),
+        ];
+        let resolved = resolve_aliases(&aliases, &ports);
+        // Aliases without a matching port are left out
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].0, "Piano");
+        assert_eq!(resolved[0].1.readable, "Stage Piano");
+    }
+
+    #[test]
+    fn alias_matching_several_ports_takes_the_first() {
+        let ports = ports(&["Synth A", "Synth B"]);
+        let aliases = [alias("Synth", &["^Synth"])];
+        let resolved = resolve_aliases(&aliases, &ports);
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].1.internal, "internal Synth A");
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported_and_skipped() {
+        let ports = ports(&["Synth (1)"]);
+        let aliases = [alias("Synth", &["Synth (", "", "Synth \\(1\\)"])];
+        let patterns = &aliases[0].patterns;
+        assert!(patterns[0].error().is_some());
+        assert_eq!(patterns[0].source(), "Synth (");
+        // An empty pattern is valid, but does not match anything
+        assert!(patterns[1].error().is_none());
+        assert!(patterns[2].error().is_none());
+
+        let resolved = resolve_aliases(&aliases, &ports);
+        assert_eq!(resolved[0].1.readable, "Synth (1)");
+        assert!(resolve_aliases(&[alias("Synth", &["Synth (", ""])], &ports).is_empty());
+    }
+}
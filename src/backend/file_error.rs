@@ -0,0 +1,92 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use crate::backend::file_format::FileFormat;
+
+/// Why a config file could not be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file is not valid JSON or TOML
+    Syntax {
+        format: FileFormat,
+        message: String,
+        /// Line and column, starting at 1
+        position: Option<(usize, usize)>,
+    },
+    /// Made by a newer version of the splitter
+    UnknownVersion(u64),
+    /// The settings do not match the current version
+    Invalid(String),
+    /// The settings of an older version could not be converted, `None` is a file from before versioning
+    Upgrade {
+        version: Option<u64>,
+        message: String,
+    },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "Could not read the file: {e}"),
+            LoadError::Syntax {
+                format,
+                message,
+                position: Some((line, column)),
+            } => write!(
+                f,
+                "Invalid {} on line {line}, column {column}: {message}",
+                format.get_description()
+            ),
+            LoadError::Syntax {
+                format, message, ..
+            } => write!(f, "Invalid {}: {message}", format.get_description()),
+            LoadError::UnknownVersion(version) => write!(
+                f,
+                "The file has version {version}, which is newer than this version of Live MIDI splitter"
+            ),
+            LoadError::Invalid(message) => {
+                write!(f, "The file does not contain valid settings: {message}")
+            }
+            LoadError::Upgrade {
+                version: Some(version),
+                message,
+            } => write!(f, "Could not upgrade the file from version {version}: {message}"),
+            LoadError::Upgrade {
+                version: None,
+                message,
+            } => write!(f, "Could not upgrade the file from an older version: {message}"),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+/// Why a config file could not be saved
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    /// The settings could not be converted to the file format
+    Serialise(String),
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "Could not write the file: {e}"),
+            SaveError::Serialise(message) => {
+                write!(f, "Could not convert the settings: {message}")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
@@ -0,0 +1,287 @@
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::backend::file_error::LoadError;
+
+/// Format of a config file. Both go through `PropertiesVersioned`, TOML is converted from and to JSON
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum FileFormat {
+    #[default]
+    Json,
+    /// Human-editable, with named fields for the entries of the CC and channel maps
+    Toml,
+}
+
+/// Field names of the tuple-encoded settings, used in the TOML format
+const NAMED_TUPLES: &[(&str, &[&str])] = &[
+    ("cc_map", &["channel", "cc", "mapping"]),
+    ("channel_map", &["channel", "mapping"]),
+];
+
+impl FileFormat {
+    /// Detect the format from the contents of a file
+    pub fn detect(contents: &str) -> Self {
+        if strip_bom(contents).trim_start().starts_with('{') {
+            FileFormat::Json
+        } else {
+            FileFormat::Toml
+        }
+    }
+
+    /// Format of a file that is read, the contents are only looked at for unknown extensions
+    pub fn of_file(path: &Path, contents: &str) -> Self {
+        Self::from_path(path).unwrap_or_else(|| Self::detect(contents))
+    }
+
+    /// Format that belongs to the extension of a file: `.toml` is TOML and our own extensions are JSON.
+    /// Files are read and written in the same format, so saving never changes how a file is read.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(FileFormat::Toml),
+            "lmsc" | "lmsp" | "json" => Some(FileFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            FileFormat::Json => "JSON",
+            FileFormat::Toml => "TOML",
+        }
+    }
+
+    pub fn parse(&self, contents: &str) -> Result<Value, LoadError> {
+        let contents = strip_bom(contents);
+        match self {
+            FileFormat::Json => serde_json::from_str(contents).map_err(|e| {
+                // The message of serde_json ends with the position
+                let position = format!(" at line {} column {}", e.line(), e.column());
+                let message = e.to_string();
+                LoadError::Syntax {
+                    format: *self,
+                    message: message.strip_suffix(&position).unwrap_or(&message).into(),
+                    position: Some((e.line(), e.column())),
+                }
+            }),
+            FileFormat::Toml => {
+                let mut value: Value = toml::from_str(contents).map_err(|e| LoadError::Syntax {
+                    format: *self,
+                    message: e.message().to_string(),
+                    position: e.span().map(|span| line_column(contents, span.start)),
+                })?;
+                map_tuples(&mut value, None, false);
+                Ok(value)
+            }
+        }
+    }
+
+    pub fn write(&self, value: &Value) -> Result<String, String> {
+        match self {
+            FileFormat::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+            FileFormat::Toml => {
+                let mut value = value.clone();
+                // TOML has no null, missing fields are deserialised as None
+                remove_nulls(&mut value);
+                map_tuples(&mut value, None, true);
+                toml::to_string_pretty(&value).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// Some editors on Windows start files with a byte order mark, which neither parser accepts
+fn strip_bom(contents: &str) -> &str {
+    contents.strip_prefix('\u{feff}').unwrap_or(contents)
+}
+
+/// Line and column of a byte offset, starting at 1
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+fn remove_nulls(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            object.retain(|_, v| !v.is_null());
+            object.values_mut().for_each(remove_nulls);
+        }
+        Value::Array(array) => array.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+/// Convert the entries of the tuple-encoded settings to tables with named fields (or back)
+fn map_tuples(value: &mut Value, key: Option<&str>, to_named: bool) {
+    let fields = key.and_then(|key| {
+        NAMED_TUPLES
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, fields)| *fields)
+    });
+    match (value, fields) {
+        (Value::Array(entries), Some(fields)) => {
+            entries.iter_mut().for_each(|entry| {
+                if to_named {
+                    if let Some(tuple) = entry.as_array().filter(|t| t.len() == fields.len()) {
+                        let named: Map<_, _> = fields
+                            .iter()
+                            .map(|f| f.to_string())
+                            .zip(tuple.iter().cloned())
+                            .collect();
+                        *entry = Value::Object(named);
+                    }
+                } else if let Some(named) = entry.as_object() {
+                    if let Some(tuple) = fields.iter().map(|f| named.get(*f).cloned()).collect() {
+                        *entry = Value::Array(tuple);
+                    }
+                }
+            });
+        }
+        (Value::Object(object), _) => {
+            object
+                .iter_mut()
+                .for_each(|(key, value)| map_tuples(value, Some(key), to_named));
+        }
+        (Value::Array(array), None) => {
+            array
+                .iter_mut()
+                .for_each(|value| map_tuples(value, None, to_named));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::backend::common_settings::{CcMapping, ChannelMapping, VelocityCurve};
+    use crate::backend::input_settings::{InputOverride, InputSettings};
+    use crate::backend::output_settings::OutputSettings;
+    use crate::backend::preset::{Preset, PresetGroup, RouteOverride};
+    use crate::backend::properties::Properties;
+    use crate::utils::{parse_properties, serialise_properties};
+
+    #[test]
+    fn config_survives_toml() {
+        let mut properties = Properties::default();
+        let mut input = InputSettings::new("Keyboard".to_string());
+        input
+            .cc_map
+            .insert(0, (2, 7, CcMapping::MapToChannelCc(3, 11)));
+        let input_id = input.id;
+        properties.inputs.push(input);
+
+        let mut route = OutputSettings::new("Synth".to_string());
+        route.velocity_curve = VelocityCurve::Exponential(1.5);
+        route.channel_map.insert(0, (10, ChannelMapping::Ignore));
+        let mut group = PresetGroup::new("Song".to_string());
+        group.mapping.insert(input_id, vec![route.clone()]);
+        let group_id = group.id;
+        properties.groups.push(group);
+
+        let mut preset = Preset::new("Verse".to_string());
+        preset.group = Some(group_id);
+        preset.overrides.insert(route.id, RouteOverride::Disable);
+        // Only some of the input settings are overridden, the others are None
+        preset.input_overrides.insert(
+            input_id,
+            InputOverride {
+                transpose: Some(Default::default()),
+                ..Default::default()
+            },
+        );
+        properties.presets.push(preset);
+
+        let json = serialise_properties(&properties);
+        let toml = FileFormat::Toml.write(&json).unwrap();
+        assert_eq!(FileFormat::detect(&toml), FileFormat::Toml);
+        let loaded = parse_properties(&toml, FileFormat::Toml).unwrap();
+        assert_eq!(loaded.file_format, FileFormat::Toml);
+        assert_eq!(serialise_properties(&loaded), json);
+    }
+
+    #[test]
+    fn nulls_are_removed() {
+        let mut value = json!({"a": null, "b": [{"c": null, "d": 1}], "e": {"f": null}});
+        remove_nulls(&mut value);
+        assert_eq!(value, json!({"b": [{"d": 1}], "e": {}}));
+    }
+
+    #[test]
+    fn tuples_get_named_fields() {
+        let tuples = json!({
+            "cc_map": [[0, -1, "PassThrough"]],
+            "routes": [{"channel_map": [[1, {"Channel": 2}]]}],
+            "other": [[1, 2]],
+        });
+        let mut value = tuples.clone();
+        map_tuples(&mut value, None, true);
+        assert_eq!(
+            value,
+            json!({
+                "cc_map": [{"channel": 0, "cc": -1, "mapping": "PassThrough"}],
+                "routes": [{"channel_map": [{"channel": 1, "mapping": {"Channel": 2}}]}],
+                "other": [[1, 2]],
+            })
+        );
+        map_tuples(&mut value, None, false);
+        assert_eq!(value, tuples);
+    }
+
+    #[test]
+    fn detect_json_after_whitespace_and_bom() {
+        let json = "{\"version_number\": 3}";
+        for contents in [
+            json.to_string(),
+            format!("\n  \t{json}"),
+            format!("\u{feff}{json}"),
+            format!("\u{feff}\r\n{json}"),
+        ] {
+            let format = FileFormat::detect(&contents);
+            assert_eq!(format, FileFormat::Json, "{contents:?}");
+            let value = format.parse(&contents).unwrap();
+            assert_eq!(value["version_number"], 3);
+        }
+        assert_eq!(
+            FileFormat::detect("\u{feff}version_number = 3"),
+            FileFormat::Toml
+        );
+        assert!(FileFormat::Toml.parse("\u{feff}version_number = 3").is_ok());
+    }
+
+    #[test]
+    fn extension_decides_the_format_of_broken_files() {
+        // A config that lost its first line does not look like JSON anymore
+        let broken = "  \"version_number\": 3,\n  \"data\": {}\n}";
+        let format = FileFormat::of_file(Path::new("song.lmsc"), broken);
+        assert_eq!(format, FileFormat::Json);
+        match format.parse(broken) {
+            Err(LoadError::Syntax {
+                format: FileFormat::Json,
+                position: Some((1, _)),
+                ..
+            }) => {}
+            other => panic!("Expected a JSON syntax error on line 1, got {other:?}"),
+        }
+
+        let format = FileFormat::of_file(Path::new("song.toml"), "{\"version_number\": 3}");
+        assert_eq!(format, FileFormat::Toml);
+        assert_eq!(
+            FileFormat::of_file(Path::new("song"), "version_number = 3"),
+            FileFormat::Toml
+        );
+        // Saved in the format it is read with, whatever format was open before
+        assert_eq!(
+            FileFormat::from_path(Path::new("song.lmsc")),
+            Some(FileFormat::Json)
+        );
+        assert_eq!(FileFormat::from_path(Path::new("song")), None);
+    }
+}
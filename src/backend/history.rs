@@ -0,0 +1,220 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::backend::properties::Properties;
+
+/// Maximum amount of changes that can be undone
+const MAX_HISTORY: usize = 100;
+
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub description: String,
+    /// Properties on the other side of this change
+    snapshot: Value,
+}
+
+/// Undo / redo stack of the (serialised) properties
+#[derive(Default, Clone, Debug)]
+pub struct History {
+    /// Changes that can be undone, with the properties from before the change
+    undo: Vec<HistoryEntry>,
+    /// Changes that can be redone, with the properties from after the change
+    redo: Vec<HistoryEntry>,
+    /// Properties as they were last recorded
+    current: Option<Value>,
+}
+
+impl History {
+    /// Add a history entry if the properties have changed since the last call,
+    /// returns true if they did. A loaded file starts a new history.
+    pub fn record(&mut self, properties: &mut Properties) -> bool {
+        let Ok(new) = serde_json::to_value(&*properties) else {
+            return false;
+        };
+        if std::mem::take(&mut properties.loaded) {
+            self.undo.clear();
+            self.redo.clear();
+            self.current = Some(new);
+            return true;
+        }
+        let Some(old) = self.current.replace(new.clone()) else {
+            return false;
+        };
+        if old == new {
+            return false;
+        }
+        self.undo.push(HistoryEntry {
+            description: describe(&old, &new),
+            snapshot: old,
+        });
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+        true
+    }
+
+    pub fn undo(&mut self, properties: &mut Properties) -> bool {
+        // Make sure the latest change can be undone as well
+        self.record(properties);
+        let Some(entry) = self.undo.pop() else {
+            return false;
+        };
+        let Some(current) = self.current.replace(entry.snapshot.clone()) else {
+            return false;
+        };
+        restore(properties, &entry.snapshot);
+        self.redo.push(HistoryEntry {
+            description: entry.description,
+            snapshot: current,
+        });
+        true
+    }
+
+    pub fn redo(&mut self, properties: &mut Properties) -> bool {
+        // Redoing after another change would overwrite that change
+        if self.record(properties) {
+            return false;
+        }
+        let Some(entry) = self.redo.pop() else {
+            return false;
+        };
+        let Some(current) = self.current.replace(entry.snapshot.clone()) else {
+            return false;
+        };
+        restore(properties, &entry.snapshot);
+        self.undo.push(HistoryEntry {
+            description: entry.description,
+            snapshot: current,
+        });
+        true
+    }
+
+    /// Changes that can be undone, oldest first
+    pub fn undo_entries(&self) -> &[HistoryEntry] {
+        &self.undo
+    }
+
+    /// Changes that can be redone, next first
+    pub fn redo_entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.redo.iter().rev()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+/// Replace the properties with a snapshot, keeping the runtime state where possible
+fn restore(properties: &mut Properties, snapshot: &Value) {
+    let Ok(restored) = Properties::deserialize(snapshot) else {
+        eprintln!("Could not restore properties from history");
+        return;
+    };
+    let current_preset = properties
+        .current_preset
+        .min(restored.presets.len().saturating_sub(1));
+    let setlist_position = properties
+        .setlist_position
+        .filter(|p| *p < restored.setlist.len());
+    *properties = Properties {
+        current_preset,
+        setlist_position,
+        pending_preset: None,
+        changed: properties.changed,
+        saved: false,
+        loaded: false,
+        file_format: properties.file_format,
+        last_clock: properties.last_clock,
+        ..restored
+    };
+}
+
+/// Short description of what changed between two snapshots, i.e. "Edited preset Piano"
+fn describe(old: &Value, new: &Value) -> String {
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return "Changed properties".to_string();
+    };
+    let changes: Vec<_> = new
+        .iter()
+        .filter(|(key, value)| old.get(*key) != Some(value))
+        .map(|(key, value)| match key.as_str() {
+            "presets" => describe_list(old.get(key), value, "preset", "name"),
+            "groups" => describe_list(old.get(key), value, "group", "name"),
+            "inputs" => describe_list(old.get(key), value, "input", "port_name"),
+            "setlist" => describe_list(old.get(key), value, "song", "title"),
+            "setlist_keys" => "Changed setlist keys".to_string(),
+            "feedback_output" => "Changed feedback output".to_string(),
+            "global_settings" => "Changed global filters".to_string(),
+            "aliases" => "Changed device aliases".to_string(),
+            key => format!("Changed {key}"),
+        })
+        .collect();
+    if changes.is_empty() {
+        "Changed properties".to_string()
+    } else {
+        changes.join(", ")
+    }
+}
+
+fn describe_list(old: Option<&Value>, new: &Value, item: &str, name_key: &str) -> String {
+    let (Some(old), Some(new)) = (old.and_then(|v| v.as_array()), new.as_array()) else {
+        return format!("Changed {item}s");
+    };
+    let name = |value: &Value| {
+        value
+            .get(name_key)
+            .and_then(|n| n.as_str())
+            .map(|n| format!(" {n}"))
+            .unwrap_or_default()
+    };
+    if new.len() > old.len() {
+        let added = new.iter().find(|v| !old.contains(v)).map(name);
+        format!("Added {item}{}", added.unwrap_or_default())
+    } else if new.len() < old.len() {
+        let removed = old.iter().find(|v| !new.contains(v)).map(name);
+        format!("Removed {item}{}", removed.unwrap_or_default())
+    } else {
+        let changed = old.iter().zip(new).filter(|(o, n)| o != n).count();
+        match old.iter().zip(new).find(|(o, n)| o != n) {
+            Some((_, value)) if changed == 1 => format!("Edited {item}{}", name(value)),
+            _ => format!("Edited {item}s"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::backend::preset::Preset;
+    use crate::utils::{reload, serialise_properties};
+
+    #[test]
+    fn undo_after_loading_does_nothing() {
+        let mut file = Properties::default();
+        file.presets.push(Preset::new("Loaded".to_string()));
+        let path = std::env::temp_dir().join("live-midi-splitter-history-test.lmsc");
+        fs::write(&path, serialise_properties(&file).to_string()).unwrap();
+
+        // The defaults at startup are recorded before the file is loaded
+        let properties = Arc::new(Mutex::new(Properties::default()));
+        let mut history = History::default();
+        history.record(&mut properties.lock().unwrap());
+        reload(&path, &properties).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut properties = properties.lock().unwrap();
+        history.record(&mut properties);
+        assert!(!history.can_undo());
+        assert!(!history.undo(&mut properties));
+        assert_eq!(properties.presets.len(), file.presets.len());
+        assert_eq!(properties.presets.last().unwrap().name, "Loaded");
+    }
+}
@@ -0,0 +1,52 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{Deserialize, Serialize};
+
+/// Persistent unique identifier of a preset, group, input or route.
+/// Stored as a hexadecimal string, so it can be used as a key in the config file.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[serde(into = "String", try_from = "String")]
+pub struct Id(u64);
+
+impl Id {
+    pub fn random() -> Self {
+        // Zero is the default, which does not refer to anything
+        Self(fastrand::u64(1..))
+    }
+}
+
+impl Display for Id {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl From<Id> for String {
+    fn from(id: Id) -> Self {
+        id.to_string()
+    }
+}
+
+impl TryFrom<String> for Id {
+    type Error = std::num::ParseIntError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        u64::from_str_radix(&value, 16).map(Id)
+    }
+}
+
+impl JsonSchema for Id {
+    fn schema_name() -> Cow<'static, str> {
+        "Id".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Hexadecimal string, unique within the file",
+            "type": "string",
+            "pattern": "^[0-9a-fA-F]{1,16}$"
+        })
+    }
+}
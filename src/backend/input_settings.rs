@@ -1,14 +1,32 @@
+use midly::num::{u4, u7};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::backend::common_settings::{
     default_cc_map, default_channel_map, default_filter, CcMap, ChannelMap, CommonSettings,
     Transpose, VelocityCurve, VelocityRange,
 };
+use crate::backend::id::Id;
+use crate::backend::preset::Preset;
+use crate::backend::triggers::PresetTrigger;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct InputSettings {
+    #[serde(default = "Id::random")]
+    pub id: Id,
     pub port_name: String,
     pub use_program_change: bool,
+    /// Also send the program change to the outputs
+    #[serde(default)]
+    pub forward_program_change: bool,
+    /// Do not send bank select to the outputs, unless program changes are forwarded
+    #[serde(default)]
+    pub consume_bank_select: bool,
+    /// Rules for selecting presets with program changes. If empty, program N selects preset N
+    #[serde(default)]
+    pub program_map: Vec<ProgramMapping>,
+    #[serde(default)]
+    pub triggers: Vec<PresetTrigger>,
     #[serde(default)]
     pub key_filter_enabled: bool,
     #[serde(default = "default_filter")]
@@ -28,8 +46,13 @@ pub struct InputSettings {
 impl InputSettings {
     pub fn new(port_name: String) -> Self {
         Self {
+            id: Id::random(),
             port_name,
             use_program_change: false,
+            forward_program_change: false,
+            consume_bank_select: false,
+            program_map: Vec::new(),
+            triggers: Vec::new(),
             key_filter_enabled: false,
             key_filter: default_filter(),
             cc_map: default_cc_map(),
@@ -41,6 +64,26 @@ impl InputSettings {
     }
 }
 
+impl InputSettings {
+    /// Preset index that this program change selects, if any
+    pub fn program_preset(
+        &self,
+        channel: u4,
+        bank: (u7, u7),
+        program: u7,
+        presets: &[Preset],
+    ) -> Option<usize> {
+        if self.program_map.is_empty() {
+            return Some(program.as_int() as usize).filter(|p| *p < presets.len());
+        }
+        let rule = self
+            .program_map
+            .iter()
+            .find(|m| m.matches(channel, bank, program))?;
+        presets.iter().position(|p| p.id == rule.preset)
+    }
+}
+
 impl CommonSettings for InputSettings {
     fn key_filter_enabled_mut(&mut self) -> &mut bool {
         &mut self.key_filter_enabled
@@ -104,3 +147,177 @@ impl Default for InputSettings {
         Self::new("".to_string())
     }
 }
+
+/// Input settings that a preset changes for one input, `None` means the input's own setting is used.
+/// Only the filter and mapping settings can be changed. The program change options, program map
+/// and triggers decide which preset is active, so they always come from the input itself.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct InputOverride {
+    pub key_filter_enabled: Option<bool>,
+    pub key_filter: Option<(u8, u8)>,
+    pub cc_map: Option<CcMap>,
+    pub channel_map: Option<ChannelMap>,
+    pub velocity_curve: Option<VelocityCurve>,
+    pub velocity_range: Option<VelocityRange>,
+    pub transpose: Option<Transpose>,
+}
+
+impl InputOverride {
+    /// Input settings with the overrides applied
+    pub fn apply(&self, input: &InputSettings) -> InputSettings {
+        let input = input.clone();
+        InputSettings {
+            key_filter_enabled: self.key_filter_enabled.unwrap_or(input.key_filter_enabled),
+            key_filter: self.key_filter.unwrap_or(input.key_filter),
+            cc_map: self.cc_map.clone().unwrap_or(input.cc_map),
+            channel_map: self.channel_map.clone().unwrap_or(input.channel_map),
+            velocity_curve: self.velocity_curve.clone().unwrap_or(input.velocity_curve),
+            velocity_range: self.velocity_range.clone().unwrap_or(input.velocity_range),
+            transpose: self.transpose.clone().unwrap_or(input.transpose),
+            ..input
+        }
+    }
+
+    /// Overrides for the settings in `edited` that differ from `input`
+    pub fn from_changes(input: &InputSettings, edited: &InputSettings) -> Self {
+        fn changed<T: PartialEq + Clone>(input: &T, edited: &T) -> Option<T> {
+            (input != edited).then(|| edited.clone())
+        }
+        Self {
+            key_filter_enabled: changed(&input.key_filter_enabled, &edited.key_filter_enabled),
+            key_filter: changed(&input.key_filter, &edited.key_filter),
+            cc_map: changed(&input.cc_map, &edited.cc_map),
+            channel_map: changed(&input.channel_map, &edited.channel_map),
+            velocity_curve: changed(&input.velocity_curve, &edited.velocity_curve),
+            velocity_range: changed(&input.velocity_range, &edited.velocity_range),
+            transpose: changed(&input.transpose, &edited.transpose),
+        }
+    }
+
+    /// Names of the settings that are overridden
+    pub fn get_descriptions(&self) -> Vec<&'static str> {
+        [
+            (self.key_filter_enabled.is_some(), "note filter"),
+            (self.key_filter.is_some(), "note range"),
+            (self.cc_map.is_some(), "CC map"),
+            (self.channel_map.is_some(), "channel map"),
+            (self.velocity_curve.is_some(), "velocity curve"),
+            (self.velocity_range.is_some(), "velocity range"),
+            (self.transpose.is_some(), "transpose"),
+        ]
+        .into_iter()
+        .filter(|(overridden, _)| *overridden)
+        .map(|(_, description)| description)
+        .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ProgramMapping {
+    /// 1 - 16, 0 is any channel
+    pub channel: u8,
+    /// -1 is any bank
+    pub bank_msb: i8,
+    /// -1 is any bank
+    pub bank_lsb: i8,
+    pub program: u8,
+    /// Preset to select
+    pub preset: Id,
+}
+
+impl ProgramMapping {
+    pub fn matches(&self, channel: u4, (msb, lsb): (u7, u7), program: u7) -> bool {
+        (self.channel == 0 || self.channel == channel.as_int() + 1)
+            && (self.bank_msb < 0 || self.bank_msb as u8 == msb.as_int())
+            && (self.bank_lsb < 0 || self.bank_lsb as u8 == lsb.as_int())
+            && self.program == program.as_int()
+    }
+}
+
+impl Default for ProgramMapping {
+    fn default() -> Self {
+        Self {
+            channel: 0,
+            bank_msb: -1,
+            bank_lsb: -1,
+            program: 0,
+            preset: Id::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_map_matches_bank_and_program() {
+        let presets: Vec<_> = (0..3).map(Preset::new_numbered).collect();
+        let mut input = InputSettings::new("Keyboard".to_string());
+        input.program_map = vec![
+            ProgramMapping {
+                channel: 2,
+                bank_msb: 1,
+                bank_lsb: 3,
+                program: 5,
+                preset: presets[2].id,
+            },
+            // Any bank and channel, but only reached when the rule above does not match
+            ProgramMapping {
+                program: 5,
+                preset: presets[1].id,
+                ..Default::default()
+            },
+        ];
+        let preset = |channel: u8, msb: u8, lsb: u8, program: u8| {
+            input.program_preset(
+                channel.into(),
+                (msb.into(), lsb.into()),
+                program.into(),
+                &presets,
+            )
+        };
+
+        // Bank select (CC 0 and CC 32) followed by a program change on channel 2
+        assert_eq!(preset(1, 1, 3, 5), Some(2));
+        assert_eq!(preset(1, 1, 4, 5), Some(1));
+        assert_eq!(preset(1, 0, 3, 5), Some(1));
+        assert_eq!(preset(0, 1, 3, 5), Some(1));
+    }
+
+    #[test]
+    fn program_without_rule_selects_nothing() {
+        let presets: Vec<_> = (0..3).map(Preset::new_numbered).collect();
+        let mut input = InputSettings::new("Keyboard".to_string());
+        let bank = (0.into(), 0.into());
+        // Without a program map, the program number is the preset index
+        assert_eq!(
+            input.program_preset(0.into(), bank, 2.into(), &presets),
+            Some(2)
+        );
+        assert_eq!(
+            input.program_preset(0.into(), bank, 3.into(), &presets),
+            None
+        );
+
+        input.program_map = vec![ProgramMapping {
+            program: 1,
+            preset: presets[0].id,
+            ..Default::default()
+        }];
+        assert_eq!(
+            input.program_preset(0.into(), bank, 2.into(), &presets),
+            None
+        );
+        // A rule for a preset that no longer exists does not match either
+        input.program_map[0].preset = Id::random();
+        assert_eq!(
+            input.program_preset(0.into(), bank, 1.into(), &presets),
+            None
+        );
+    }
+}
@@ -1,25 +1,75 @@
 use crate::backend::device::{ConnectError, Input, Output};
+use crate::backend::id::Id;
 use crate::backend::midi_handler::filter_map::apply_filter_map;
+use crate::backend::midi_handler::mpe::{apply_mpe, MpeState};
+use crate::backend::midi_handler::voices::{distribute_voices, VoiceState};
+use crate::backend::morph::{
+    controller_key, plan_activation, plan_release, ActivationPlan, MorphState,
+};
 use crate::backend::properties::Properties;
+use crate::backend::triggers::TriggerResult;
 use crate::backend::MidiPort;
 use crate::gui::state::State;
 use crate::utils::repaint_gui;
 use egui::Context;
+use midly::live::SystemRealtime;
 use midly::num::{u4, u7};
 use midly::{live::LiveEvent, MidiMessage};
 use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use std::sync::{mpsc, Arc, Mutex};
 use std::time::Instant;
 use tracing::warn;
 
 mod filter_map;
+mod mpe;
+mod voices;
 
 pub type QueueItems = Vec<(String, Vec<u8>)>;
 
+pub enum QueueMessage {
+    /// Events caused by an incoming event, sent in order of the timestamps
+    Events(u64, QueueItems),
+    /// Events that do not have to wait for the priority queue
+    Immediate(QueueItems),
+    /// Events that are sent at a certain time. Replaces the scheduled events for the given
+    /// (output, channel, controller), events for other controllers are still sent
+    Schedule(Vec<(Instant, QueueItems)>, HashSet<(String, u8, u8)>),
+}
+
+/// Send the scheduled events of a plan after some time (i.e. the steps of a morph)
+pub fn schedule(event_sender: &mpsc::Sender<QueueMessage>, plan: &ActivationPlan) {
+    let replace = plan.controllers();
+    if replace.is_empty() {
+        return;
+    }
+    let now = Instant::now();
+    let events = plan
+        .scheduled
+        .iter()
+        .map(|(delay, items)| (now + *delay, items.clone()))
+        .collect();
+    if let Err(e) = event_sender.send(QueueMessage::Schedule(events, replace)) {
+        eprintln!("Error sending events {e:?}");
+    }
+}
+
+/// MIDI clock runs at 24 ticks per quarter note, we assume 4/4
+const TICKS_PER_BAR: u32 = 24 * 4;
+
+/// (preset id, input id, route id)
+pub type RouteKey = (Id, Id, Id);
+
+/// Runtime state of an output mapping
+#[derive(Default)]
+pub struct RouteState {
+    mpe: MpeState,
+    voices: VoiceState,
+}
+
 pub struct Listener {
     pub name: MidiPort,
-    pub input_id: usize,
+    pub input_id: Id,
     pub properties: Arc<Mutex<Properties>>,
     pub state: Arc<Mutex<State>>,
     pub gui_ctx: Arc<Mutex<Option<Context>>>,
@@ -27,322 +77,390 @@ pub struct Listener {
     pub event_buffer: Arc<Mutex<HashMap<LiveEvent<'static>, HashSet<EventBufferItem>>>>,
     pub held_pedals: Arc<Mutex<HashMap<(u4, u7), u7>>>, // (channel, controller): value
     pub queue: Arc<Mutex<BinaryHeap<Reverse<u64>>>>,
-    pub event_sender: mpsc::Sender<(u64, QueueItems)>,
+    pub route_state: Arc<Mutex<HashMap<RouteKey, RouteState>>>,
+    pub event_sender: mpsc::Sender<QueueMessage>,
+    /// Preset of which the activation messages have been sent last
+    pub activated_preset: Arc<Mutex<Option<Id>>>,
+    pub morph_state: Arc<Mutex<MorphState>>,
+    /// Last bank select (msb, lsb) per channel
+    pub bank_select: HashMap<u4, (u7, u7)>,
+    /// MIDI clock ticks since the last start message
+    pub clock_ticks: u32,
 }
 
 impl Listener {
-    pub fn create(self) -> Result<Input, ConnectError> {
+    pub fn create(mut self) -> Result<Input, ConnectError> {
         // TODO this should really be cleaned up
-        Input::new(self.name, move |timestamp, data, previous_preset| {
-            // Add the timestamp of this event to the queue (do not add queue items yet)
-            self.queue.lock().unwrap().push(Reverse(timestamp));
+        Input::new(
+            self.name.clone(),
+            move |timestamp, data, previous_preset| {
+                // Add the timestamp of this event to the queue (do not add queue items yet)
+                self.queue.lock().unwrap().push(Reverse(timestamp));
 
-            // Parse midi data
-            let event = LiveEvent::parse(data);
-            if let Err(error) = event {
-                eprintln!("Midi parse error: {error}");
-                cancel_event(&self.queue, timestamp);
-                return;
-            }
-            let event = event.unwrap();
-            let mut send_events = Vec::new();
+                // Parse midi data
+                let event = LiveEvent::parse(data);
+                if let Err(error) = event {
+                    eprintln!("Midi parse error: {error}");
+                    cancel_event(&self.queue, timestamp);
+                    return;
+                }
+                let event = event.unwrap();
+                let mut send_events = Vec::new();
 
-            {
-                let mut properties = self.properties.lock().unwrap();
-                if let Some(input_settings) = properties.inputs.get(self.input_id) {
-                    // Handle program change, if enabled
-                    if input_settings.use_program_change {
-                        if let LiveEvent::Midi {
-                            message: MidiMessage::ProgramChange { program },
-                            ..
-                        } = event
-                        {
-                            // Set preset
-                            properties.current_preset = program.as_int() as usize;
-                            // Redraw frontend
-                            repaint_gui(&self.gui_ctx);
-                            // Don't send this data to the mappings
-                            cancel_event(&self.queue, timestamp);
-                            return;
-                        }
+                // Count MIDI clock ticks, to switch presets at the start of a bar
+                let mut at_bar = false;
+                match event {
+                    LiveEvent::Realtime(SystemRealtime::TimingClock) => {
+                        at_bar = self.clock_ticks.is_multiple_of(TICKS_PER_BAR);
+                        self.clock_ticks = self.clock_ticks.wrapping_add(1);
+                        self.properties.lock().unwrap().last_clock = Some(Instant::now());
                     }
-                } else {
-                    eprintln!("Could not get input settings for input {}", self.input_id)
+                    LiveEvent::Realtime(SystemRealtime::Start) => self.clock_ticks = 0,
+                    _ => {}
                 }
-            }
+                let (notes_held, pedal_held) = self.held();
 
-            // Get preset
-            let preset = {
-                let properties = self.properties.lock().unwrap();
-                properties.presets.get(properties.current_preset).cloned()
-            };
-            if let Some(mapping) = preset
-                .as_ref()
-                .and_then(|p| p.mapping.get(&self.input_id).cloned())
-            {
-                // Check if we changed presets
-                let changed_preset = preset.as_ref().unwrap().id != *previous_preset;
-                if changed_preset {
-                    *previous_preset = preset.as_ref().unwrap().id
+                // Publish the event to the GUI if something is waiting for MIDI learn
+                if self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .midi_learn
+                    .learn(self.input_id, &event, data)
+                {
+                    repaint_gui(&self.gui_ctx);
                 }
 
-                // Loop through mappings
-                mapping.iter().for_each(|output| {
-                    // Clone data so we can modify it separately for each output mapping
-                    let mut data = data.to_owned();
-                    {
-                        let state = self.state.lock().unwrap();
-                        let mut output_handlers = self.output_handlers.lock().unwrap();
-                        // Check if the output target has disconnected
-                        let output_port = state
-                            .available_outputs
-                            .iter()
-                            .find(|p| p.readable == output.port_name);
-                        if output_port.is_none() {
-                            output_handlers.remove(&output.port_name);
-                            cancel_event(&self.queue, timestamp);
-                            return;
+                {
+                    let mut properties = self.properties.lock().unwrap();
+                    let properties = &mut *properties;
+                    let input_index = properties.inputs.iter().position(|i| i.id == self.input_id);
+                    if let Some(input_index) = input_index {
+                        let input_settings = &properties.inputs[input_index];
+                        // Handle program change, if enabled
+                        if input_settings.use_program_change {
+                            if let LiveEvent::Midi { channel, message } = event {
+                                match message {
+                                    MidiMessage::Controller { controller, value }
+                                        if controller.as_int() == 0
+                                            || controller.as_int() == 32 =>
+                                    {
+                                        // Remember bank select for the next program change
+                                        let bank = self
+                                            .bank_select
+                                            .entry(channel)
+                                            .or_insert((0.into(), 0.into()));
+                                        if controller.as_int() == 0 {
+                                            bank.0 = value;
+                                        } else {
+                                            bank.1 = value;
+                                        }
+                                        if input_settings.consume_bank_select
+                                            && !input_settings.forward_program_change
+                                        {
+                                            cancel_event(&self.queue, timestamp);
+                                            return;
+                                        }
+                                    }
+                                    MidiMessage::ProgramChange { program } => {
+                                        let bank = self
+                                            .bank_select
+                                            .get(&channel)
+                                            .cloned()
+                                            .unwrap_or((0.into(), 0.into()));
+                                        let preset = input_settings.program_preset(
+                                            channel,
+                                            bank,
+                                            program,
+                                            &properties.presets,
+                                        );
+                                        let forward = input_settings.forward_program_change;
+                                        let mut state = self.state.lock().unwrap();
+                                        if let Some(preset) = preset {
+                                            // Set preset (possibly after the notes are released)
+                                            properties
+                                                .request_preset(preset, notes_held, pedal_held);
+                                            state.program_change_warning = None;
+                                        } else {
+                                            state.program_change_warning = Some(format!(
+                                                "Program change {} (bank {}/{}, channel {}) on {} does not select a preset",
+                                                program,
+                                                bank.0,
+                                                bank.1,
+                                                channel.as_int() + 1,
+                                                self.name.readable,
+                                            ));
+                                        }
+                                        // Redraw frontend
+                                        repaint_gui(&self.gui_ctx);
+                                        // Don't send this data to the mappings
+                                        if !forward {
+                                            cancel_event(&self.queue, timestamp);
+                                            return;
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                            }
                         }
-                        let output_port = output_port.unwrap(); // safe because of if above
-
-                        // Find output_handler or create new
-                        if !output_handlers.contains_key(&output.port_name) {
-                            // Try to connect
-                            let new_handler = Output::new(output_port);
-                            match new_handler {
-                                Ok(handler) => {
-                                    output_handlers.insert(output.port_name.clone(), handler);
+                        // Handle preset triggers (notes or CC)
+                        let result = properties.inputs[input_index]
+                            .triggers
+                            .iter()
+                            .map(|trigger| trigger.handle(event, properties))
+                            .find(|result| *result != TriggerResult::Ignore);
+                        if let Some(result) = result {
+                            match result {
+                                TriggerResult::Switch(preset) => {
+                                    properties.request_preset(preset, notes_held, pedal_held);
+                                    repaint_gui(&self.gui_ctx);
                                 }
-                                Err(_) => {
-                                    cancel_event(&self.queue, timestamp);
-                                    return;
+                                TriggerResult::Setlist(entry) => {
+                                    let preset = properties
+                                        .setlist
+                                        .get(entry)
+                                        .and_then(|song| properties.preset_index(song.preset));
+                                    if let Some(preset) = preset {
+                                        properties.setlist_position = Some(entry);
+                                        properties.request_preset(preset, notes_held, pedal_held);
+                                    }
+                                    repaint_gui(&self.gui_ctx);
                                 }
+                                _ => {}
                             }
+                            // Don't send this data to the mappings
+                            cancel_event(&self.queue, timestamp);
+                            return;
                         }
+                    } else {
+                        eprintln!("Could not get input settings for input {}", self.input_id)
                     }
+                }
 
-                    // If we just changed presets, send any held pedal events
-                    if changed_preset && output.buffer_pedals {
-                        self.held_pedals.lock().unwrap().iter().for_each(
-                            |(&(channel, controller), &value)| {
-                                let pedal_event = LiveEvent::Midi {
-                                    channel,
-                                    message: MidiMessage::Controller { controller, value },
-                                };
-                                let mut data = Vec::new();
-                                if pedal_event.write(&mut data).is_ok() {
-                                    send_events.push((output.port_name.clone(), data.clone()));
-                                }
-                            },
-                        );
-                    }
+                // Get preset (with the routes inherited from its group) and its input settings
+                let (preset_id, mapping, input_settings) = {
+                    let properties = self.properties.lock().unwrap();
+                    let preset_id = properties
+                        .presets
+                        .get(properties.current_preset)
+                        .map(|p| p.id);
+                    (
+                        preset_id,
+                        properties.mapping(properties.current_preset, self.input_id),
+                        properties.input_settings(properties.current_preset, self.input_id),
+                    )
+                };
 
-                    let mut send = true;
-                    let mut ignore_transpose = output.transpose.ignore_global;
-                    {
-                        let properties = self.properties.lock().unwrap();
-                        if let Some(input_settings) = properties.inputs.get(self.input_id) {
-                            apply_filter_map(&mut data, &mut send, input_settings);
-                            ignore_transpose |= input_settings.transpose.ignore_global;
-                        }
-                        apply_filter_map(&mut data, &mut send, output);
-
-                        // Apply global transpose
-                        if properties.transpose != 0 && !ignore_transpose {
-                            if let LiveEvent::Midi {
-                                message:
-                                    MidiMessage::NoteOff { .. }
-                                    | MidiMessage::NoteOn { .. }
-                                    | MidiMessage::Aftertouch { .. },
-                                ..
-                            } = event
-                            {
-                                // Change raw data directly. data[1] is the key value. set to 0 at underflow
-                                data[1] = data[1]
-                                    .checked_add_signed(properties.transpose)
-                                    .unwrap_or(0);
-                            }
-                        }
-                    }
+                // Release the routes of the previous preset, if the background loop has not done so yet
+                let plan = {
+                    let properties = self.properties.lock().unwrap();
+                    let mut morph_state = self.morph_state.lock().unwrap();
+                    plan_release(&properties, &mut morph_state)
+                };
+                self.send_plan(plan, &mut send_events);
 
-                    if send {
-                        send_events.push((output.port_name.clone(), data.clone()));
+                // Send the activation messages of a new preset before its first event
+                if let Some(preset_id) = preset_id {
+                    let previous = self.activated_preset.lock().unwrap().replace(preset_id);
+                    if previous != Some(preset_id) {
+                        let plan = {
+                            let properties = self.properties.lock().unwrap();
+                            let mut morph_state = self.morph_state.lock().unwrap();
+                            plan_activation(&properties, previous, preset_id, &mut morph_state)
+                        };
+                        self.send_plan(plan, &mut send_events);
                     }
+                }
 
-                    // Parse midi data (again, after filter/mapping)
-                    let event_after = LiveEvent::parse(&data);
-                    if let Err(error) = event_after {
-                        eprintln!("Midi parse error: {error}");
-                        cancel_event(&self.queue, timestamp);
-                        return;
+                if let (Some(preset_id), Some(mapping)) = (preset_id, mapping) {
+                    // Check if we changed presets
+                    let changed_preset = Some(preset_id) != *previous_preset;
+                    if changed_preset {
+                        *previous_preset = Some(preset_id);
+                        // Forget MPE channels and voices of the previous preset
+                        self.route_state
+                            .lock()
+                            .unwrap()
+                            .retain(|(p, i, _), _| *i != self.input_id || *p == preset_id);
                     }
-                    let event_after = event_after.unwrap();
-
-                    // If this is a note-on or pedal event, save it
-                    // If this is a note-off or pedal release event, remove previously saved event
-                    if let LiveEvent::Midi { channel, message } = event {
-                        match message {
-                            MidiMessage::NoteOn { key, .. } => {
-                                // Save corresponding note off event to listen for and to send
-                                let off_listen_event = LiveEvent::Midi {
-                                    channel,
-                                    message: MidiMessage::NoteOff { key, vel: 0.into() },
-                                };
-                                let off_send_event = {
-                                    if let LiveEvent::Midi {
+
+                    // Loop through mappings
+                    mapping.iter().for_each(|output| {
+                        // Clone data so we can modify it separately for each output mapping
+                        let mut data = data.to_owned();
+                        let connected: Vec<bool> = if output.voices.enabled {
+                            output
+                                .voices
+                                .voices
+                                .iter()
+                                .map(|voice| self.connect_output(&voice.port_name))
+                                .collect()
+                        } else {
+                            if !self.connect_output(&output.port_name) {
+                                cancel_event(&self.queue, timestamp);
+                                return;
+                            }
+                            vec![true]
+                        };
+
+                        // If we just changed presets, send any held pedal events
+                        if changed_preset && output.buffer_pedals {
+                            self.held_pedals.lock().unwrap().iter().for_each(
+                                |(&(channel, controller), &value)| {
+                                    let pedal_event = LiveEvent::Midi {
                                         channel,
-                                        message: MidiMessage::NoteOn { key, .. },
-                                    } = event_after
-                                    {
-                                        LiveEvent::Midi {
-                                            channel,
-                                            message: MidiMessage::NoteOff { key, vel: 0.into() },
+                                        message: MidiMessage::Controller { controller, value },
+                                    };
+                                    let mut data = Vec::new();
+                                    if pedal_event.write(&mut data).is_ok() {
+                                        if output.voices.enabled {
+                                            output.voices.targets(&connected).for_each(
+                                                |(port_name, channel)| {
+                                                    let mut data = data.clone();
+                                                    set_channel(&mut data, channel);
+                                                    send_events.push((port_name.clone(), data));
+                                                },
+                                            );
+                                        } else {
+                                            send_events
+                                                .push((output.port_name.clone(), data.clone()));
                                         }
-                                    } else {
-                                        eprintln!("Event before and after don' t match");
-                                        cancel_event(&self.queue, timestamp);
-                                        return;
                                     }
-                                };
-                                let mut event_buffer = self.event_buffer.lock().unwrap();
-                                event_buffer.entry(off_listen_event).or_default().insert(
-                                    EventBufferItem {
-                                        output_name: output.port_name.clone(),
-                                        off_event: off_send_event,
-                                    },
-                                );
+                                },
+                            );
+                        }
+
+                        let mut send = true;
+                        let mut ignore_transpose = output.transpose.ignore_global;
+                        {
+                            let properties = self.properties.lock().unwrap();
+                            if !output.ignore_global_settings {
+                                apply_filter_map(&mut data, &mut send, &properties.global_settings);
                             }
-                            MidiMessage::NoteOff { key, .. } => {
-                                // Remove previously saved event (saved on note-on)
-                                let off_event = LiveEvent::Midi {
-                                    channel,
-                                    message: MidiMessage::NoteOff { key, vel: 0.into() },
-                                };
-                                let mut event_buffer = self.event_buffer.lock().unwrap();
-                                if let Some(outputs) = event_buffer.get_mut(&off_event) {
-                                    if let Some(item) = outputs
-                                        .iter()
-                                        .find(|i| i.output_name == output.port_name)
-                                        .cloned()
-                                    {
-                                        // Only remove event if it was meant to go to the same output channel as us
-                                        if same_channel(event_after, item.off_event) {
-                                            outputs.remove(&item);
-                                        }
-                                    }
-                                }
+                            if let Some(input_settings) = &input_settings {
+                                apply_filter_map(&mut data, &mut send, input_settings);
+                                ignore_transpose |= input_settings.transpose.ignore_global;
                             }
-                            MidiMessage::Controller { controller, value } => {
-                                match controller.as_int() {
-                                    64 | 66 | 69 => {
-                                        if output.buffer_pedals {
-                                            if value >= 64 {
-                                                // Save corresponding note off event to listen for and to send
-                                                let off_listen_event = LiveEvent::Midi {
-                                                    channel,
-                                                    message: MidiMessage::Controller {
-                                                        controller,
-                                                        value: 0.into(),
-                                                    },
-                                                };
-                                                let off_send_event = {
-                                                    if let LiveEvent::Midi {
-                                                        channel,
-                                                        message:
-                                                            MidiMessage::Controller {
-                                                                controller, ..
-                                                            },
-                                                    } = event_after
-                                                    {
-                                                        LiveEvent::Midi {
-                                                            channel,
-                                                            message: MidiMessage::Controller {
-                                                                controller,
-                                                                value: 0.into(),
-                                                            },
-                                                        }
-                                                    } else {
-                                                        eprintln!(
-                                                            "Event before and after don' t match"
-                                                        );
-                                                        cancel_event(&self.queue, timestamp);
-                                                        return;
-                                                    }
-                                                };
-                                                let mut event_buffer =
-                                                    self.event_buffer.lock().unwrap();
-                                                event_buffer
-                                                    .entry(off_listen_event)
-                                                    .or_default()
-                                                    .insert(EventBufferItem {
-                                                        output_name: output.port_name.clone(),
-                                                        off_event: off_send_event,
-                                                    });
-                                                // Mark pedal as held (so it can be sent on preset switch)
-                                                self.held_pedals
-                                                    .lock()
-                                                    .unwrap()
-                                                    .insert((channel, controller), value);
-                                            } else {
-                                                // Remove previously saved event (saved on note-on)
-                                                let off_event = LiveEvent::Midi {
-                                                    channel,
-                                                    message: MidiMessage::Controller {
-                                                        controller,
-                                                        value: 0.into(),
-                                                    },
-                                                };
-                                                let mut event_buffer =
-                                                    self.event_buffer.lock().unwrap();
-                                                if let Some(outputs) =
-                                                    event_buffer.get_mut(&off_event)
-                                                {
-                                                    if let Some(item) = outputs
-                                                        .iter()
-                                                        .find(|i| i.output_name == output.port_name)
-                                                        .cloned()
-                                                    {
-                                                        // Only remove event if it was meant to go to the same output channel as us
-                                                        if same_channel(event_after, item.off_event)
-                                                        {
-                                                            outputs.remove(&item);
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                    _ => {}
+                            apply_filter_map(&mut data, &mut send, output);
+
+                            // Apply global transpose
+                            if properties.transpose != 0 && !ignore_transpose {
+                                if let LiveEvent::Midi {
+                                    message:
+                                        MidiMessage::NoteOff { .. }
+                                        | MidiMessage::NoteOn { .. }
+                                        | MidiMessage::Aftertouch { .. },
+                                    ..
+                                } = event
+                                {
+                                    // Change raw data directly. data[1] is the key value. set to 0 at underflow
+                                    data[1] = data[1]
+                                        .checked_add_signed(properties.transpose)
+                                        .unwrap_or(0);
                                 }
                             }
-                            _ => {}
                         }
-                    }
-                });
-            } else {
-                let properties = self.properties.lock().unwrap();
-                eprintln!(
-                    "Could not get output mapping for preset {} input {}",
-                    properties.current_preset, self.input_id
-                )
-            }
 
-            // Send note-off, after-touch and pedal release events to outputs that are no longer active
-            let mut off_event = None;
-            if let LiveEvent::Midi { channel, message } = event {
-                match message {
-                    MidiMessage::NoteOff { key, .. } | MidiMessage::Aftertouch { key, .. } => {
-                        off_event = Some(LiveEvent::Midi {
-                            channel,
-                            message: MidiMessage::NoteOff { key, vel: 0.into() },
-                        });
-                        // NOTE: after-touch events for held notes should also get sent to previous outputs
-                        // I have not tested this, since I do not own a keyboard with after-touch
-                    }
-                    MidiMessage::Controller { controller, value } => {
-                        match controller.as_int() {
-                            64 | 66 | 69 => {
-                                if value < 64 {
+                        // Keep per-note expression with the notes that were sent to this output
+                        if output.mpe.enabled {
+                            let mut route_state = self.route_state.lock().unwrap();
+                            let route_state = route_state
+                                .entry((preset_id, self.input_id, output.id))
+                                .or_default();
+                            let mpe = apply_mpe(
+                                &mut data,
+                                &mut send,
+                                event,
+                                &output.mpe,
+                                &mut route_state.mpe,
+                            );
+                            // Stop notes that had their channel taken over
+                            let stolen =
+                                mpe.stolen.into_iter().map(|(off_listen_event, off_event)| {
+                                    let item = EventBufferItem {
+                                        route: output.id,
+                                        output_name: output.port_name.clone(),
+                                        off_event,
+                                    };
+                                    (off_listen_event, item)
+                                });
+                            stop_stolen(
+                                &mut self.event_buffer.lock().unwrap(),
+                                stolen,
+                                &mut send_events,
+                            );
+                            mpe.before.into_iter().for_each(|before| {
+                                send_events.push((output.port_name.clone(), before));
+                            });
+                        }
+
+                        // Get the output(s) this event should go to
+                        let targets = if output.voices.enabled {
+                            let mut route_state = self.route_state.lock().unwrap();
+                            let route_state = route_state
+                                .entry((preset_id, self.input_id, output.id))
+                                .or_default();
+                            let distributed = distribute_voices(
+                                &data,
+                                send,
+                                event,
+                                output.id,
+                                &output.voices,
+                                &mut route_state.voices,
+                                &connected,
+                            );
+                            // Stop notes that had their voice taken over
+                            stop_stolen(
+                                &mut self.event_buffer.lock().unwrap(),
+                                distributed.stolen,
+                                &mut send_events,
+                            );
+                            distributed.targets
+                        } else {
+                            vec![(output.port_name.clone(), data)]
+                        };
+
+                        for (port_name, data) in targets {
+                            if send {
+                                send_events.push((port_name.clone(), data.clone()));
+                            }
+
+                            // Remember which note-off and pedal release events this output needs
+                            if !self.buffer_off_events(
+                                event,
+                                &data,
+                                output.id,
+                                &port_name,
+                                output.buffer_pedals,
+                            ) {
+                                cancel_event(&self.queue, timestamp);
+                                return;
+                            }
+                        }
+                    });
+                } else {
+                    let properties = self.properties.lock().unwrap();
+                    eprintln!(
+                        "Could not get output mapping for preset {} input {}",
+                        properties.current_preset, self.input_id
+                    )
+                }
+
+                // Send note-off, after-touch and pedal release events to outputs that are no longer active
+                let mut off_event = None;
+                if let LiveEvent::Midi { channel, message } = event {
+                    match message {
+                        MidiMessage::NoteOff { key, .. } | MidiMessage::Aftertouch { key, .. } => {
+                            off_event = Some(LiveEvent::Midi {
+                                channel,
+                                message: MidiMessage::NoteOff { key, vel: 0.into() },
+                            });
+                            // NOTE: after-touch events for held notes should also get sent to previous outputs
+                            // I have not tested this, since I do not own a keyboard with after-touch
+                        }
+                        MidiMessage::Controller { controller, value } => {
+                            match controller.as_int() {
+                                64 | 66 | 69 if value < 64 => {
                                     off_event = Some(LiveEvent::Midi {
                                         channel,
                                         message: MidiMessage::Controller {
@@ -356,33 +474,283 @@ impl Listener {
                                         .unwrap()
                                         .remove(&(channel, controller));
                                 }
+                                _ => {}
                             }
-                            _ => {}
                         }
+                        _ => {}
+                    }
+                };
+                if let Some(off_event) = off_event {
+                    // Get outputs that need this off event _and_ remove it from the buffer.
+                    let mut event_buffer = self.event_buffer.lock().unwrap();
+                    if let Some(outputs) = event_buffer.remove(&off_event) {
+                        // Send to outputs that still need note-off events
+                        outputs.iter().for_each(|item| {
+                            let mut buf = Vec::new();
+                            if let Err(e) = item.off_event.write(&mut buf) {
+                                eprintln!("{e}");
+                            }
+                            send_events.push((item.output_name.clone(), buf.clone()));
+                        });
                     }
-                    _ => {}
                 }
-            };
-            if let Some(off_event) = off_event {
-                // Get outputs that need this off event _and_ remove it from the buffer.
+
+                // Switch to a pending preset once the current preset allows it
+                let (notes_held, pedal_held) = self.held();
+                if self
+                    .properties
+                    .lock()
+                    .unwrap()
+                    .apply_pending_preset(notes_held, pedal_held, at_bar)
+                {
+                    repaint_gui(&self.gui_ctx);
+                }
+
+                // Send events to the queue handler thread
+                if let Err(e) = self
+                    .event_sender
+                    .send(QueueMessage::Events(timestamp, send_events))
+                {
+                    eprintln!("Error sending events {e:?}");
+                }
+            },
+        )
+    }
+}
+
+impl Listener {
+    /// Check if any notes are held down on an output and if the sustain pedal is held down
+    fn held(&self) -> (bool, bool) {
+        let notes = self
+            .event_buffer
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(event, outputs)| {
+                matches!(
+                    event,
+                    LiveEvent::Midi {
+                        message: MidiMessage::NoteOff { .. },
+                        ..
+                    }
+                ) && !outputs.is_empty()
+            });
+        let pedal = self
+            .held_pedals
+            .lock()
+            .unwrap()
+            .keys()
+            .any(|(_, controller)| controller.as_int() == 64);
+        (notes, pedal)
+    }
+
+    fn connect_output(&self, port_name: &String) -> bool {
+        let state = self.state.lock().unwrap();
+        let mut output_handlers = self.output_handlers.lock().unwrap();
+        connect_output(&state, &mut output_handlers, port_name)
+    }
+
+    /// Send the immediate events of a plan before the current event, and schedule the others
+    fn send_plan(&self, plan: ActivationPlan, send_events: &mut QueueItems) {
+        let connected: HashSet<_> = plan
+            .ports()
+            .into_iter()
+            .filter(|port_name| self.connect_output(port_name))
+            .collect();
+        schedule(&self.event_sender, &plan);
+        send_events.extend(
+            plan.immediate
+                .into_iter()
+                .filter(|(port_name, _)| connected.contains(port_name)),
+        );
+    }
+
+    /// If this is a note-on or pedal event, save it.
+    /// If this is a note-off or pedal release event, remove previously saved event.
+    /// `event` is the received event, `data` is the event as it is sent to `port_name` by `route`.
+    fn buffer_off_events(
+        &self,
+        event: LiveEvent,
+        data: &[u8],
+        route: Id,
+        port_name: &String,
+        buffer_pedals: bool,
+    ) -> bool {
+        // Parse midi data (again, after filter/mapping)
+        let event_after = match LiveEvent::parse(data) {
+            Ok(event_after) => event_after,
+            Err(error) => {
+                eprintln!("Midi parse error: {error}");
+                return false;
+            }
+        };
+
+        let LiveEvent::Midi { channel, message } = event else {
+            return true;
+        };
+        match message {
+            MidiMessage::NoteOn { key, .. } => {
+                // Save corresponding note off event to listen for and to send
+                let off_listen_event = LiveEvent::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff { key, vel: 0.into() },
+                };
+                let LiveEvent::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn { key, .. },
+                } = event_after
+                else {
+                    eprintln!("Event before and after don' t match");
+                    return false;
+                };
+                let off_send_event = LiveEvent::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff { key, vel: 0.into() },
+                };
                 let mut event_buffer = self.event_buffer.lock().unwrap();
-                if let Some(outputs) = event_buffer.remove(&off_event) {
-                    // Send to outputs that still need note-off events
-                    outputs.iter().for_each(|item| {
-                        let mut buf = Vec::new();
-                        if let Err(e) = item.off_event.write(&mut buf) {
-                            eprintln!("{e}");
-                        }
-                        send_events.push((item.output_name.clone(), buf.clone()));
+                event_buffer
+                    .entry(off_listen_event)
+                    .or_default()
+                    .insert(EventBufferItem {
+                        route,
+                        output_name: port_name.clone(),
+                        off_event: off_send_event,
                     });
+            }
+            MidiMessage::NoteOff { key, .. } => {
+                // Remove previously saved event (saved on note-on)
+                let off_event = LiveEvent::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff { key, vel: 0.into() },
+                };
+                self.remove_buffered(off_event, event_after, route, port_name);
+            }
+            MidiMessage::Controller { controller, value } => match controller.as_int() {
+                64 | 66 | 69 if buffer_pedals => {
+                    let off_listen_event = LiveEvent::Midi {
+                        channel,
+                        message: MidiMessage::Controller {
+                            controller,
+                            value: 0.into(),
+                        },
+                    };
+                    if value >= 64 {
+                        // Save corresponding note off event to listen for and to send
+                        let LiveEvent::Midi {
+                            channel,
+                            message: MidiMessage::Controller { controller, .. },
+                        } = event_after
+                        else {
+                            eprintln!("Event before and after don' t match");
+                            return false;
+                        };
+                        let off_send_event = LiveEvent::Midi {
+                            channel,
+                            message: MidiMessage::Controller {
+                                controller,
+                                value: 0.into(),
+                            },
+                        };
+                        let mut event_buffer = self.event_buffer.lock().unwrap();
+                        event_buffer
+                            .entry(off_listen_event)
+                            .or_default()
+                            .insert(EventBufferItem {
+                                route,
+                                output_name: port_name.clone(),
+                                off_event: off_send_event,
+                            });
+                        // Mark pedal as held (so it can be sent on preset switch)
+                        self.held_pedals
+                            .lock()
+                            .unwrap()
+                            .insert((channel, controller), value);
+                    } else {
+                        // Remove previously saved event (saved on pedal press)
+                        self.remove_buffered(off_listen_event, event_after, route, port_name);
+                    }
                 }
+                _ => {}
+            },
+            _ => {}
+        }
+        true
+    }
+
+    fn remove_buffered(
+        &self,
+        off_event: LiveEvent<'static>,
+        event_after: LiveEvent,
+        route: Id,
+        port_name: &String,
+    ) {
+        let mut event_buffer = self.event_buffer.lock().unwrap();
+        if let Some(outputs) = event_buffer.get_mut(&off_event) {
+            // Only remove event if it was sent by this route to the same output channel as us
+            if let Some(item) = outputs
+                .iter()
+                .find(|i| {
+                    i.route == route
+                        && i.output_name == *port_name
+                        && same_channel(event_after, i.off_event)
+                })
+                .cloned()
+            {
+                outputs.remove(&item);
             }
-            // Send events to the queue handler thread
-            if let Err(e) = self.event_sender.send((timestamp, send_events)) {
-                eprintln!("Error sending events {e:?}");
+        }
+    }
+}
+
+/// Send the note-offs of notes that were stopped early, and forget their buffered note-offs,
+/// so releasing their keys later does not stop the note that took their place
+fn stop_stolen(
+    event_buffer: &mut HashMap<LiveEvent<'static>, HashSet<EventBufferItem>>,
+    stolen: impl IntoIterator<Item = (LiveEvent<'static>, EventBufferItem)>,
+    send_events: &mut QueueItems,
+) {
+    stolen.into_iter().for_each(|(off_listen_event, item)| {
+        let mut buf = Vec::new();
+        if item.off_event.write(&mut buf).is_ok() {
+            send_events.push((item.output_name.clone(), buf));
+        }
+        if let Some(outputs) = event_buffer.get_mut(&off_listen_event) {
+            outputs.remove(&item);
+        }
+    });
+}
+
+/// Connect to an output if needed, returns false if the output is not available
+pub fn connect_output(
+    state: &State,
+    output_handlers: &mut HashMap<String, Output>,
+    port_name: &String,
+) -> bool {
+    // Check if the output target has disconnected
+    let Some(output_port) = state.output_port(port_name) else {
+        output_handlers.remove(port_name);
+        return false;
+    };
+
+    // Find output_handler or create new (or reconnect if an alias now refers to another port)
+    if output_handlers
+        .get(port_name)
+        .is_none_or(|h| h.port != *output_port)
+    {
+        // Try to connect
+        match Output::new(output_port) {
+            Ok(handler) => {
+                output_handlers.insert(port_name.clone(), handler);
             }
-        })
+            Err(_) => return false,
+        }
     }
+    true
+}
+
+/// Set the channel (0..=15) of a raw channel message
+fn set_channel(data: &mut [u8], channel: u8) {
+    data[0] = (data[0] & 0xF0) | (channel & 0x0F);
 }
 
 fn cancel_event(queue: &Arc<Mutex<BinaryHeap<Reverse<u64>>>>, timestamp: u64) {
@@ -392,6 +760,8 @@ fn cancel_event(queue: &Arc<Mutex<BinaryHeap<Reverse<u64>>>>, timestamp: u64) {
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 pub struct EventBufferItem {
+    /// Route that sent the event, so routes to the same port do not release each other's notes
+    route: Id,
     output_name: String,
     off_event: LiveEvent<'static>,
 }
@@ -406,11 +776,35 @@ fn same_channel(event_a: LiveEvent, event_b: LiveEvent) -> bool {
     false
 }
 
+/// Add scheduled events, replacing the earlier ones for the controllers in `replace`.
+/// Other events are kept, i.e. the all-notes-off at the end of a fade on the same channel.
+fn replace_scheduled(
+    scheduled: VecDeque<(Instant, QueueItems)>,
+    events: Vec<(Instant, QueueItems)>,
+    replace: &HashSet<(String, u8, u8)>,
+) -> VecDeque<(Instant, QueueItems)> {
+    let mut scheduled: Vec<_> = scheduled
+        .into_iter()
+        .filter_map(|(time, mut items)| {
+            items.retain(|(port_name, data)| {
+                controller_key(port_name, data).is_none_or(|key| !replace.contains(&key))
+            });
+            (!items.is_empty()).then_some((time, items))
+        })
+        .chain(events)
+        .collect();
+    scheduled.sort_by_key(|(time, _)| *time);
+    scheduled.into()
+}
+
 pub struct QueueHandler {
-    rx: mpsc::Receiver<(u64, QueueItems)>,
+    rx: mpsc::Receiver<QueueMessage>,
     priority_queue: Arc<Mutex<BinaryHeap<Reverse<u64>>>>,
     output_handlers: Arc<Mutex<HashMap<String, Output>>>,
+    morph_state: Arc<Mutex<MorphState>>,
     event_queue: HashMap<u64, QueueItems>,
+    /// Events that should be sent at a certain time, in order
+    scheduled: VecDeque<(Instant, QueueItems)>,
     last_sent: Instant,
 }
 
@@ -418,15 +812,36 @@ impl QueueHandler {
     pub fn run(&mut self) {
         loop {
             // Receive incoming events
-            if let Some((timestamp, events)) = self.receive() {
-                // If this is the next event in the priority queue, send it
-                if self.should_send_event(timestamp) {
-                    self.priority_queue.lock().unwrap().pop();
-                    self.last_sent = Instant::now();
+            match self.receive() {
+                Some(QueueMessage::Events(timestamp, events)) => {
+                    // If this is the next event in the priority queue, send it
+                    if self.should_send_event(timestamp) {
+                        self.priority_queue.lock().unwrap().pop();
+                        self.last_sent = Instant::now();
+                        self.send_events(&events);
+                    } else {
+                        // If this is not the next event, cache it
+                        self.event_queue.insert(timestamp, events);
+                    }
+                }
+                Some(QueueMessage::Immediate(events)) => {
+                    self.send_events(&events);
+                }
+                Some(QueueMessage::Schedule(events, replace)) => {
+                    let scheduled = std::mem::take(&mut self.scheduled);
+                    self.scheduled = replace_scheduled(scheduled, events, &replace);
+                }
+                None => {}
+            }
+
+            // Send scheduled events that are due
+            while self
+                .scheduled
+                .front()
+                .is_some_and(|(time, _)| *time <= Instant::now())
+            {
+                if let Some((_, events)) = self.scheduled.pop_front() {
                     self.send_events(&events);
-                } else {
-                    // If this is not the next event, cache it
-                    self.event_queue.insert(timestamp, events);
                 }
             }
 
@@ -448,10 +863,17 @@ impl QueueHandler {
         }
     }
 
-    fn receive(&self) -> Option<(u64, QueueItems)> {
+    fn receive(&self) -> Option<QueueMessage> {
         if self.priority_queue.lock().unwrap().is_empty() {
-            // If the queue is empty: block until something is sent on the channel
-            Some(self.rx.recv().unwrap())
+            // If the queue is empty: block until something is sent on the channel,
+            // or until the next scheduled event
+            if let Some((time, _)) = self.scheduled.front() {
+                self.rx
+                    .recv_timeout(time.saturating_duration_since(Instant::now()))
+                    .ok()
+            } else {
+                Some(self.rx.recv().unwrap())
+            }
         } else {
             // If the queue has some items: do not block, only check if there is channel activity
             self.rx.try_recv().ok()
@@ -469,10 +891,14 @@ impl QueueHandler {
 
     fn send_events(&self, events: &QueueItems) {
         let mut output_handlers = self.output_handlers.lock().unwrap();
+        let mut morph_state = self.morph_state.lock().unwrap();
         for (port_name, data) in events {
-            output_handlers
-                .get_mut(port_name)
-                .unwrap()
+            morph_state.record(port_name, data);
+            let Some(output) = output_handlers.get_mut(port_name) else {
+                eprintln!("Output {port_name} is not connected");
+                continue;
+            };
+            output
                 .connection
                 .send(data)
                 .unwrap_or_else(|_| eprintln!("Failed to send to {port_name}"));
@@ -480,16 +906,159 @@ impl QueueHandler {
     }
 
     pub fn new(
-        rx: mpsc::Receiver<(u64, QueueItems)>,
+        rx: mpsc::Receiver<QueueMessage>,
         output_handlers: Arc<Mutex<HashMap<String, Output>>>,
         priority_queue: Arc<Mutex<BinaryHeap<Reverse<u64>>>>,
+        morph_state: Arc<Mutex<MorphState>>,
     ) -> Self {
         Self {
             rx,
             priority_queue,
             output_handlers,
+            morph_state,
             event_queue: HashMap::new(),
+            scheduled: VecDeque::new(),
             last_sent: Instant::now(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::output_settings::MpeSettings;
+
+    /// Buffers note-offs and sends them on release, like the listener does for an MPE route
+    struct MpeRoute {
+        route: Id,
+        settings: MpeSettings,
+        state: MpeState,
+        event_buffer: HashMap<LiveEvent<'static>, HashSet<EventBufferItem>>,
+    }
+
+    impl MpeRoute {
+        fn play(&mut self, received: &[u8]) -> Vec<Vec<u8>> {
+            let event = LiveEvent::parse(received).unwrap().to_static();
+            let mut data = received.to_vec();
+            let mut send = true;
+            let mpe = apply_mpe(&mut data, &mut send, event, &self.settings, &mut self.state);
+            let mut send_events = Vec::new();
+            let stolen = mpe.stolen.into_iter().map(|(off_listen_event, off_event)| {
+                let item = EventBufferItem {
+                    route: self.route,
+                    output_name: "Synth".to_string(),
+                    off_event,
+                };
+                (off_listen_event, item)
+            });
+            stop_stolen(&mut self.event_buffer, stolen, &mut send_events);
+
+            let LiveEvent::Midi { channel, message } = event else {
+                unreachable!()
+            };
+            match message {
+                MidiMessage::NoteOn { key, .. } => {
+                    let off_listen_event = LiveEvent::Midi {
+                        channel,
+                        message: MidiMessage::NoteOff { key, vel: 0.into() },
+                    };
+                    let off_event = LiveEvent::Midi {
+                        channel: u4::new(data[0] & 0x0F),
+                        message: MidiMessage::NoteOff {
+                            key: u7::new(data[1]),
+                            vel: 0.into(),
+                        },
+                    };
+                    self.event_buffer
+                        .entry(off_listen_event)
+                        .or_default()
+                        .insert(EventBufferItem {
+                            route: self.route,
+                            output_name: "Synth".to_string(),
+                            off_event,
+                        });
+                    send_events.push(("Synth".to_string(), data));
+                }
+                MidiMessage::NoteOff { key, .. } => {
+                    let off_listen_event = LiveEvent::Midi {
+                        channel,
+                        message: MidiMessage::NoteOff { key, vel: 0.into() },
+                    };
+                    // The route sends its own note-off, other buffered note-offs are sent on release
+                    let buffered = self.event_buffer.remove(&off_listen_event);
+                    if send {
+                        send_events.push(("Synth".to_string(), data));
+                    } else {
+                        buffered.into_iter().flatten().for_each(|item| {
+                            let mut buf = Vec::new();
+                            item.off_event.write(&mut buf).unwrap();
+                            send_events.push((item.output_name, buf));
+                        });
+                    }
+                }
+                _ => {}
+            }
+            send_events.into_iter().map(|(_, data)| data).collect()
+        }
+    }
+
+    #[test]
+    fn new_schedule_keeps_the_end_of_a_fade() {
+        let now = Instant::now();
+        let at = |ms| now + std::time::Duration::from_millis(ms);
+        let item = |data: &[u8]| vec![("Synth".to_string(), data.to_vec())];
+        // A release fade of volume on channel 1, which stops the notes at its end
+        let scheduled = VecDeque::from([
+            (at(10), item(&[0xB0, 7, 64])),
+            (at(20), item(&[0xB0, 7, 0])),
+            (at(20), item(&[0xB0, 123, 0])),
+        ]);
+        // The next preset morphs expression on the same channel
+        let events = vec![(at(15), item(&[0xB0, 11, 100]))];
+        let replace = HashSet::from([("Synth".to_string(), 0, 11)]);
+        let scheduled = replace_scheduled(scheduled, events, &replace);
+        assert_eq!(scheduled.len(), 4);
+        assert!(scheduled.contains(&(at(20), item(&[0xB0, 123, 0]))));
+
+        // Another fade of volume replaces the first one, but not its all-notes-off
+        let events = vec![(at(30), item(&[0xB0, 7, 127]))];
+        let replace = HashSet::from([("Synth".to_string(), 0, 7)]);
+        let scheduled = replace_scheduled(scheduled, events, &replace);
+        let sent: Vec<_> = scheduled.into_iter().map(|(_, items)| items).collect();
+        assert_eq!(
+            sent,
+            [
+                item(&[0xB0, 11, 100]),
+                item(&[0xB0, 123, 0]),
+                item(&[0xB0, 7, 127])
+            ]
+        );
+    }
+
+    #[test]
+    fn releasing_a_stolen_note_keeps_the_new_note() {
+        let mut route = MpeRoute {
+            route: Id::random(),
+            settings: MpeSettings {
+                enabled: true,
+                reallocate: true,
+                output_member_channels: 2,
+                ..Default::default()
+            },
+            state: MpeState::default(),
+            event_buffer: HashMap::new(),
+        };
+
+        // Both output channels are in use, the third note takes the channel of the first
+        assert_eq!(route.play(&[0x91, 60, 100]), [[0x91, 60, 100]]);
+        assert_eq!(route.play(&[0x92, 62, 100]), [[0x92, 62, 100]]);
+        assert_eq!(
+            route.play(&[0x93, 64, 100]),
+            [[0x81, 60, 0], [0x91, 64, 100]]
+        );
+
+        // Releasing the first key does not send anything to the channel of the new note
+        assert!(route.play(&[0x81, 60, 0]).is_empty());
+        assert_eq!(route.play(&[0x83, 64, 0]), [[0x81, 64, 0]]);
+    }
+}
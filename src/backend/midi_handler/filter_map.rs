@@ -62,7 +62,7 @@ pub fn apply_filter_map(data: &mut [u8], send: &mut bool, settings: &impl Common
                         .cc_map()
                         .iter()
                         .find(|(ch, cc, _)| {
-                            *ch == channel.as_int() && *cc as u8 == controller.as_int()
+                            *ch == channel.as_int() + 1 && *cc as u8 == controller.as_int()
                         })
                         .or(settings
                             .cc_map()
@@ -71,7 +71,7 @@ pub fn apply_filter_map(data: &mut [u8], send: &mut bool, settings: &impl Common
                         .or(settings
                             .cc_map()
                             .iter()
-                            .find(|(ch, cc, _)| *ch == channel.as_int() && *cc == -1))
+                            .find(|(ch, cc, _)| *ch == channel.as_int() + 1 && *cc == -1))
                         .or(settings.cc_map().last());
                     if let Some((_, _, map)) = map {
                         match map {
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use midly::live::LiveEvent;
+use midly::num::u4;
+use midly::MidiMessage;
+
+use crate::backend::midi_handler::set_channel;
+use crate::backend::output_settings::MpeSettings;
+
+/// Runtime state of a single MPE route
+#[derive(Default, Debug)]
+pub struct MpeState {
+    /// Member channel on the input -> member channel on the output, for notes that passed the filters
+    channels: HashMap<u8, u8>,
+    /// (received key, sent key) of the held notes per input member channel
+    held: HashMap<u8, Vec<(u8, u8)>>,
+    /// Output member channels, least recently used first
+    lru: Vec<u8>,
+    /// Expression messages that arrived before the note-on on a member channel
+    pending: HashMap<u8, Vec<Vec<u8>>>,
+    /// Selected RPN (msb, lsb) per channel, used to detect MPE Configuration Messages
+    rpn: HashMap<u8, (u8, u8)>,
+    /// Amount of member channels as announced by the controller
+    announced_members: Option<u8>,
+}
+
+impl MpeState {
+    /// Output channel for a note on `channel`, and note-offs for the notes of a stolen channel
+    fn allocate(&mut self, channel: u8, settings: &MpeSettings) -> (u8, Vec<StolenNote>) {
+        let mut stolen = Vec::new();
+        if let Some(&out) = self.channels.get(&channel) {
+            return (out, stolen);
+        }
+        let out = if settings.reallocate {
+            let members = settings
+                .output_zone
+                .members(settings.output_member_channels);
+            self.lru.retain(|c| members.contains(c));
+            members.iter().for_each(|c| {
+                if !self.lru.contains(c) {
+                    self.lru.push(*c);
+                }
+            });
+            // Take the least recently used free channel, or steal the least recently used one
+            let free = self
+                .lru
+                .iter()
+                .find(|c| !self.channels.values().any(|used| used == *c))
+                .or(self.lru.first())
+                .cloned()
+                .unwrap_or(settings.output_zone.master());
+            // Stop the notes that used this channel, their own note-offs will not be sent anymore
+            let held = &mut self.held;
+            self.channels.retain(|input, used| {
+                if *used == free {
+                    stolen.extend(held.remove(input).unwrap_or_default().into_iter().map(
+                        |(received, sent)| (note_off(*input, received), note_off(free, sent)),
+                    ));
+                }
+                *used != free
+            });
+            self.lru.retain(|c| *c != free);
+            self.lru.push(free);
+            free
+        } else {
+            channel
+        };
+        self.channels.insert(channel, out);
+        (out, stolen)
+    }
+
+    fn release(&mut self, channel: u8, key: u8) {
+        let held = self.held.entry(channel).or_default();
+        if let Some(i) = held.iter().position(|(_, sent)| *sent == key) {
+            held.remove(i);
+        }
+        if held.is_empty() {
+            self.held.remove(&channel);
+            self.channels.remove(&channel);
+        }
+    }
+}
+
+/// (note-off to listen for, note-off to send) of a note that lost its channel
+type StolenNote = (LiveEvent<'static>, LiveEvent<'static>);
+
+fn note_off(channel: u8, key: u8) -> LiveEvent<'static> {
+    LiveEvent::Midi {
+        channel: u4::new(channel),
+        message: MidiMessage::NoteOff {
+            key: key.into(),
+            vel: 0.into(),
+        },
+    }
+}
+
+#[derive(Default)]
+pub struct MpeEvents {
+    /// Notes that had their channel taken over, their note-offs should be sent first
+    pub stolen: Vec<StolenNote>,
+    /// Messages that should be sent before the event
+    pub before: Vec<Vec<u8>>,
+}
+
+/// Keep per-note expression on member channels attached to the notes that passed this route.
+/// `event` is the event as it was received.
+pub fn apply_mpe(
+    data: &mut [u8],
+    send: &mut bool,
+    event: LiveEvent,
+    settings: &MpeSettings,
+    state: &mut MpeState,
+) -> MpeEvents {
+    let mut events = MpeEvents::default();
+    let LiveEvent::Midi { channel, message } = event else {
+        return events;
+    };
+    let channel = channel.as_int();
+
+    if channel == settings.zone.master() {
+        // Detect MPE Configuration Messages (RPN 6) to follow the size of the zone
+        if let MidiMessage::Controller { controller, value } = message {
+            let rpn = state.rpn.entry(channel).or_insert((127, 127));
+            match controller.as_int() {
+                101 => rpn.0 = value.as_int(),
+                100 => rpn.1 = value.as_int(),
+                6 if *rpn == (0, 6) => {
+                    state.announced_members = Some(value.as_int());
+                }
+                _ => {}
+            }
+        }
+        // Zone-wide messages go to the master channel of the output zone
+        if settings.reallocate {
+            set_channel(data, settings.output_zone.master());
+        }
+        return events;
+    }
+
+    let members = state.announced_members.unwrap_or(settings.member_channels);
+    if !settings.zone.members(members).contains(&channel) {
+        return events;
+    }
+
+    match message {
+        MidiMessage::NoteOn { key, vel } if vel > 0 => {
+            let pending = state.pending.remove(&channel).unwrap_or_default();
+            if *send {
+                let (out, stolen) = state.allocate(channel, settings);
+                state
+                    .held
+                    .entry(channel)
+                    .or_default()
+                    .push((key.as_int(), data[1]));
+                set_channel(data, out);
+                // Send the initial expression of this note first
+                events.stolen = stolen;
+                events.before = pending
+                    .into_iter()
+                    .map(|mut pending| {
+                        set_channel(&mut pending, out);
+                        pending
+                    })
+                    .collect();
+            }
+        }
+        MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. } => {
+            if let Some(&out) = state.channels.get(&channel) {
+                set_channel(data, out);
+                state.release(channel, data[1]);
+            } else {
+                *send = false;
+            }
+        }
+        _ => {
+            if let Some(&out) = state.channels.get(&channel) {
+                set_channel(data, out);
+            } else {
+                // No note on this channel (yet), keep it until we know if the note passes
+                if *send {
+                    let pending = state.pending.entry(channel).or_default();
+                    // Only the latest value of each kind of message is relevant
+                    let is_cc = data[0] & 0xF0 == 0xB0;
+                    pending.retain(|p| p[0] != data[0] || (is_cc && p[1] != data[1]));
+                    pending.push(data.to_vec());
+                }
+                *send = false;
+            }
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(data: &[u8], settings: &MpeSettings, state: &mut MpeState) -> Vec<Vec<u8>> {
+        let mut data = data.to_vec();
+        let mut send = true;
+        let event = LiveEvent::parse(&data).unwrap().to_static();
+        let events = apply_mpe(&mut data, &mut send, event, settings, state);
+        let mut sent: Vec<_> = events
+            .stolen
+            .into_iter()
+            .map(|(_, off)| {
+                let mut buf = Vec::new();
+                off.write(&mut buf).unwrap();
+                buf
+            })
+            .chain(events.before)
+            .collect();
+        if send {
+            sent.push(data);
+        }
+        sent
+    }
+
+    #[test]
+    fn stolen_channel_stops_its_note() {
+        let settings = MpeSettings {
+            enabled: true,
+            reallocate: true,
+            output_member_channels: 1,
+            ..Default::default()
+        };
+        let mut state = MpeState::default();
+
+        // Channel 2 keeps its channel, then channel 3 has to take it over
+        assert_eq!(
+            handle(&[0x91, 60, 100], &settings, &mut state),
+            [[0x91, 60, 100]]
+        );
+        assert_eq!(
+            handle(&[0x92, 64, 100], &settings, &mut state),
+            [[0x81, 60, 0], [0x91, 64, 100]]
+        );
+        // The note-off of the stolen note has already been sent
+        assert!(handle(&[0x81, 60, 0], &settings, &mut state).is_empty());
+        assert_eq!(
+            handle(&[0x82, 64, 0], &settings, &mut state),
+            [[0x81, 64, 0]]
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use midly::live::LiveEvent;
+use midly::num::{u4, u7};
+use midly::MidiMessage;
+
+use crate::backend::id::Id;
+use crate::backend::midi_handler::{set_channel, EventBufferItem};
+use crate::backend::output_settings::{VoiceDistributor, VoiceMode};
+
+/// Runtime state of a voice distributor
+#[derive(Default, Debug)]
+pub struct VoiceState {
+    /// Note played by each voice: (input channel, input key, output key)
+    held: HashMap<usize, (u4, u7, u7)>,
+    /// Voices in the order they were last taken, oldest first
+    order: Vec<usize>,
+    /// Next voice for round robin
+    next: usize,
+}
+
+/// Note played by a voice that got taken by another note
+type Stolen = (usize, (u4, u7, u7));
+
+impl VoiceState {
+    fn take(
+        &mut self,
+        note: (u4, u7, u7),
+        mode: VoiceMode,
+        connected: &[bool],
+    ) -> Option<(usize, Option<Stolen>)> {
+        let candidates: Vec<usize> = (0..connected.len()).filter(|&v| connected[v]).collect();
+        // Voices that have not been used yet count as the oldest
+        let age = |v: &usize| self.order.iter().position(|o| o == v).map_or(0, |p| p + 1);
+        let oldest_held = || {
+            candidates
+                .iter()
+                .filter(|v| self.held.contains_key(v))
+                .min_by_key(|v| age(v))
+                .cloned()
+        };
+
+        let voice = match mode {
+            VoiceMode::RoundRobin => {
+                let voice = candidates
+                    .iter()
+                    .find(|&&v| v >= self.next)
+                    .or(candidates.first())
+                    .cloned()?;
+                self.next = voice + 1;
+                voice
+            }
+            VoiceMode::FirstFree => candidates
+                .iter()
+                .find(|v| !self.held.contains_key(v))
+                .cloned()
+                .or_else(oldest_held)?,
+            VoiceMode::StealOldest => candidates
+                .iter()
+                .filter(|v| !self.held.contains_key(v))
+                .min_by_key(|v| age(v))
+                .cloned()
+                .or_else(oldest_held)?,
+        };
+
+        let stolen = self.held.insert(voice, note).map(|n| (voice, n));
+        self.order.retain(|&v| v != voice);
+        self.order.push(voice);
+        Some((voice, stolen))
+    }
+
+    fn holder(&self, channel: u4, key: u7) -> Option<usize> {
+        self.held
+            .iter()
+            .find(|(_, &(c, k, _))| c == channel && k == key)
+            .map(|(&voice, _)| voice)
+    }
+}
+
+pub struct Distributed {
+    /// Note-offs for notes that lost their voice: (note-off to listen for, buffered item)
+    pub stolen: Vec<(LiveEvent<'static>, EventBufferItem)>,
+    /// (port name, data) to send to
+    pub targets: Vec<(String, Vec<u8>)>,
+}
+
+/// Pick the voice(s) that should play this event.
+/// `event` is the event as it was received, `data` is the event after filters and maps
+/// and `route` is the route that the voices belong to.
+pub fn distribute_voices(
+    data: &[u8],
+    send: bool,
+    event: LiveEvent,
+    route: Id,
+    settings: &VoiceDistributor,
+    state: &mut VoiceState,
+    connected: &[bool],
+) -> Distributed {
+    let mut distributed = Distributed {
+        stolen: Vec::new(),
+        targets: Vec::new(),
+    };
+    if !send {
+        return distributed;
+    }
+    let to_voice = |voice: usize| {
+        let voice = &settings.voices[voice];
+        let mut data = data.to_vec();
+        set_channel(&mut data, voice.channel.saturating_sub(1));
+        (voice.port_name.clone(), data)
+    };
+
+    let LiveEvent::Midi { channel, message } = event else {
+        // System messages go to every output once
+        distributed.targets = settings
+            .targets(connected)
+            .unique_by(|(port_name, _)| *port_name)
+            .map(|(port_name, _)| (port_name.clone(), data.to_vec()))
+            .collect();
+        return distributed;
+    };
+
+    match message {
+        MidiMessage::NoteOn { key, vel } if vel > 0 => {
+            let key_out = u7::from(data[1]);
+            let voice = match state.holder(channel, key) {
+                Some(voice) => Some(voice),
+                None => state
+                    .take((channel, key, key_out), settings.mode, connected)
+                    .map(|(voice, stolen)| {
+                        if let Some((voice, (channel_in, key_in, key_out))) = stolen {
+                            let off_listen_event = LiveEvent::Midi {
+                                channel: channel_in,
+                                message: MidiMessage::NoteOff {
+                                    key: key_in,
+                                    vel: 0.into(),
+                                },
+                            };
+                            let voice_settings = &settings.voices[voice];
+                            let item = EventBufferItem {
+                                route,
+                                output_name: voice_settings.port_name.clone(),
+                                off_event: LiveEvent::Midi {
+                                    channel: voice_settings.channel.saturating_sub(1).into(),
+                                    message: MidiMessage::NoteOff {
+                                        key: key_out,
+                                        vel: 0.into(),
+                                    },
+                                },
+                            };
+                            distributed.stolen.push((off_listen_event, item));
+                        }
+                        voice
+                    }),
+            };
+            distributed.targets.extend(voice.map(to_voice));
+        }
+        MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+            if let Some(voice) = state.holder(channel, key) {
+                state.held.remove(&voice);
+                distributed.targets.push(to_voice(voice));
+            }
+        }
+        MidiMessage::Aftertouch { key, .. } => {
+            distributed
+                .targets
+                .extend(state.holder(channel, key).map(to_voice));
+        }
+        _ => {
+            // Pedals and other channel messages go to every voice
+            distributed.targets = settings
+                .targets(connected)
+                .map(|(port_name, channel)| {
+                    let mut data = data.to_vec();
+                    set_channel(&mut data, channel);
+                    (port_name.clone(), data)
+                })
+                .collect();
+        }
+    }
+    distributed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::output_settings::Voice;
+
+    fn distributor(mode: VoiceMode) -> VoiceDistributor {
+        VoiceDistributor {
+            enabled: true,
+            mode,
+            voices: (1..=3)
+                .map(|channel| Voice {
+                    port_name: "Synth".to_string(),
+                    channel,
+                })
+                .collect(),
+        }
+    }
+
+    /// Send a note to the voices, returns the output channels it was sent to and the result
+    fn play(
+        data: [u8; 3],
+        settings: &VoiceDistributor,
+        state: &mut VoiceState,
+        connected: &[bool],
+    ) -> (Vec<u8>, Distributed) {
+        let event = LiveEvent::parse(&data).unwrap();
+        let distributed = distribute_voices(
+            &data,
+            true,
+            event,
+            Id::default(),
+            settings,
+            state,
+            connected,
+        );
+        let channels = distributed
+            .targets
+            .iter()
+            .map(|(_, data)| data[0] & 0x0F)
+            .collect();
+        (channels, distributed)
+    }
+
+    #[test]
+    fn round_robin_takes_the_voices_in_order() {
+        let settings = distributor(VoiceMode::RoundRobin);
+        let mut state = VoiceState::default();
+        let connected = [true, false, true];
+        let channels: Vec<_> = [60, 62, 64]
+            .into_iter()
+            .flat_map(|key| play([0x90, key, 100], &settings, &mut state, &connected).0)
+            .collect();
+        // The voice that is not connected is skipped, and the first voice is taken again
+        assert_eq!(channels, [0, 2, 0]);
+    }
+
+    #[test]
+    fn busy_voices_are_stolen() {
+        let settings = distributor(VoiceMode::StealOldest);
+        let mut state = VoiceState::default();
+        let connected = [true; 3];
+        for key in [60, 62, 64] {
+            let (_, distributed) = play([0x90, key, 100], &settings, &mut state, &connected);
+            assert!(distributed.stolen.is_empty());
+        }
+
+        let (channels, distributed) = play([0x90, 67, 100], &settings, &mut state, &connected);
+        assert_eq!(channels, [0]);
+        let [(listen, item)] = distributed.stolen.as_slice() else {
+            panic!("expected one stolen note");
+        };
+        // The stolen note is turned off on its voice once its own note-off arrives
+        assert!(matches!(
+            listen,
+            LiveEvent::Midi { message: MidiMessage::NoteOff { key, .. }, .. } if *key == 60
+        ));
+        assert!(matches!(
+            item.off_event,
+            LiveEvent::Midi { channel, message: MidiMessage::NoteOff { key, .. } }
+                if channel == 0 && key == 60
+        ));
+        // The note-off of the stolen note does not stop the note that took its voice
+        let (channels, _) = play([0x80, 60, 0], &settings, &mut state, &connected);
+        assert!(channels.is_empty());
+        let (channels, _) = play([0x80, 67, 0], &settings, &mut state, &connected);
+        assert_eq!(channels, [0]);
+    }
+
+    #[test]
+    fn note_off_frees_the_voice() {
+        let settings = distributor(VoiceMode::FirstFree);
+        let mut state = VoiceState::default();
+        let connected = [true; 3];
+        play([0x90, 60, 100], &settings, &mut state, &connected);
+        let (channels, _) = play([0x90, 62, 100], &settings, &mut state, &connected);
+        assert_eq!(channels, [1]);
+
+        // A note-on with velocity 0 is a note-off as well
+        let (channels, _) = play([0x90, 60, 0], &settings, &mut state, &connected);
+        assert_eq!(channels, [0]);
+        let (channels, distributed) = play([0x90, 64, 100], &settings, &mut state, &connected);
+        assert_eq!(channels, [0]);
+        assert!(distributed.stolen.is_empty());
+    }
+}
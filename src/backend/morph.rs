@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::backend::activation::{clamp_time, ActivationKind, ActivationMessage};
+use crate::backend::id::Id;
+use crate::backend::midi_handler::QueueItems;
+use crate::backend::output_settings::ReleasePolicy;
+use crate::backend::properties::Properties;
+
+/// Time between two steps of a morph
+const STEP: f32 = 0.02;
+
+#[derive(Default, Debug)]
+pub struct MorphState {
+    /// Channels (0 - 15) that have been used on each output
+    pub used_channels: HashMap<String, HashSet<u8>>,
+    /// (output, channel, controller) that have been faded out by a morph
+    faded: HashSet<(String, u8, u8)>,
+    /// Preset of which the release policies are applied when it is left
+    current: Option<Id>,
+    /// Controllers that are being faded out by a release, which the next activation should not morph
+    released: HashSet<(String, u8, u8)>,
+    /// Last value sent to each (output, channel, controller) that is not faded out
+    levels: Controllers,
+}
+
+impl MorphState {
+    /// Remember which channels are used, so we know where to fade
+    pub fn record(&mut self, port_name: &String, data: &[u8]) {
+        if let Some(&status) = data.first() {
+            if (0x80..0xF0).contains(&status) {
+                if let Some(channels) = self.used_channels.get_mut(port_name) {
+                    channels.insert(status & 0x0F);
+                } else {
+                    self.used_channels
+                        .insert(port_name.clone(), HashSet::from([status & 0x0F]));
+                }
+            }
+            // Fades should end where the performer left the controller, not at the fade itself
+            if let [0xB0..=0xBF, controller, value] = *data {
+                let key = (port_name.clone(), status & 0x0F, controller);
+                if !self.faded.contains(&key) {
+                    self.levels.insert(key, value);
+                }
+            }
+        }
+    }
+
+    /// Level to restore a faded controller to, full if nothing has been sent to it
+    fn level(&self, key: &(String, u8, u8)) -> u8 {
+        self.levels.get(key).cloned().unwrap_or(127)
+    }
+}
+
+#[derive(Default)]
+pub struct ActivationPlan {
+    /// Events to send right away
+    pub immediate: QueueItems,
+    /// Events to send after some time
+    pub scheduled: Vec<(Duration, QueueItems)>,
+}
+
+impl ActivationPlan {
+    /// All outputs that this plan sends to
+    pub fn ports(&self) -> HashSet<String> {
+        self.immediate
+            .iter()
+            .chain(self.scheduled.iter().flat_map(|(_, items)| items))
+            .map(|(port_name, _)| port_name.clone())
+            .collect()
+    }
+
+    /// (output, channel, controller) of the controllers that this plan changes
+    pub fn controllers(&self) -> HashSet<(String, u8, u8)> {
+        self.immediate
+            .iter()
+            .chain(self.scheduled.iter().flat_map(|(_, items)| items))
+            .filter_map(|(port_name, data)| controller_key(port_name, data))
+            .collect()
+    }
+}
+
+/// (output, channel, controller) of a CC message
+pub fn controller_key(port_name: &str, data: &[u8]) -> Option<(String, u8, u8)> {
+    match *data {
+        [status @ 0xB0..=0xBF, controller, _] => {
+            Some((port_name.to_string(), status & 0x0F, controller))
+        }
+        _ => None,
+    }
+}
+
+/// (output, channel, controller) -> value
+type Controllers = HashMap<(String, u8, u8), u8>;
+/// (output, channel, controller), from value, to value
+type Ramp = ((String, u8, u8), u8, u8);
+
+/// Get the events for activating a preset, morphing from the previous one if enabled
+pub fn plan_activation(
+    properties: &Properties,
+    previous: Option<Id>,
+    preset: Id,
+    state: &mut MorphState,
+) -> ActivationPlan {
+    let mut plan = ActivationPlan::default();
+    let Some(index) = properties.preset_index(preset) else {
+        return plan;
+    };
+    let activation = &properties.presets[index].activation;
+    let new_ports = properties.preset_ports(index);
+    let old_index = previous
+        .filter(|p| *p != preset)
+        .and_then(|p| properties.preset_index(p));
+    let old = old_index.map(|i| &properties.presets[i]);
+    let released = std::mem::take(&mut state.released);
+
+    let Some((old, old_index)) = old.zip(old_index).filter(|_| activation.morph_time > 0.0) else {
+        let levels = &state.levels;
+        // No morph: turn faded outputs back up and send everything at once
+        state.faded.retain(|(port_name, channel, controller)| {
+            if !new_ports.contains(port_name) {
+                return true;
+            }
+            let key = (port_name.clone(), *channel, *controller);
+            let restore = vec![
+                0xB0 | channel,
+                *controller,
+                levels.get(&key).cloned().unwrap_or(127),
+            ];
+            plan.immediate.push((port_name.clone(), restore));
+            false
+        });
+        plan.immediate.extend(activation.events());
+        return plan;
+    };
+
+    // Interpolate controllers that are in both snapshots, send other messages right away
+    let old_controllers = controllers(old.activation.messages.iter());
+    let mut ramps: Vec<Ramp> = Vec::new();
+    activation.messages.iter().for_each(|message| {
+        if let Some((key, value)) = controller(&message.port_name, &message.kind) {
+            if let Some(&from) = old_controllers.get(&key) {
+                ramps.push((key, from, value));
+                return;
+            }
+        }
+        message
+            .kind
+            .data()
+            .into_iter()
+            .for_each(|data| plan.immediate.push((message.port_name.clone(), data)));
+    });
+
+    // Fade out routes that are not in the new preset, fade in routes that are new
+    if let Some(fade_controller) = activation.fade.controller() {
+        let old_ports = properties.preset_ports(old_index);
+        let new_controllers = controllers(activation.messages.iter());
+        for port_name in old_ports.union(&new_ports) {
+            let channels = state.used_channels.get(port_name).cloned();
+            for channel in channels.into_iter().flatten() {
+                let key = (port_name.clone(), channel, fade_controller);
+                if released.contains(&key) || ramps.iter().any(|(k, _, _)| *k == key) {
+                    continue;
+                }
+                if !new_ports.contains(port_name) {
+                    ramps.push((key.clone(), state.level(&key), 0));
+                    state.faded.insert(key);
+                } else if !old_ports.contains(port_name) {
+                    let data = vec![0xB0 | channel, fade_controller, 0];
+                    plan.immediate.push((port_name.clone(), data));
+                    let level = new_controllers.get(&key).cloned();
+                    ramps.push((key.clone(), 0, level.unwrap_or(state.level(&key))));
+                    state.faded.remove(&key);
+                }
+            }
+        }
+    }
+    // Outputs that are used by the new preset, but have been faded out earlier
+    let levels = &state.levels;
+    state.faded.retain(|key| {
+        if !new_ports.contains(&key.0) {
+            return true;
+        }
+        if !ramps.iter().any(|(k, _, _)| k == key) {
+            let level = levels.get(key).cloned().unwrap_or(127);
+            plan.immediate
+                .push((key.0.clone(), vec![0xB0 | key.1, key.2, level]));
+        }
+        false
+    });
+
+    plan.scheduled
+        .extend(ramp_events(&ramps, activation.morph_time));
+    plan
+}
+
+/// Get the events for the release policies of the previous preset, once the current preset has changed
+pub fn plan_release(properties: &Properties, state: &mut MorphState) -> ActivationPlan {
+    let mut plan = ActivationPlan::default();
+    let Some(preset) = properties.presets.get(properties.current_preset) else {
+        return plan;
+    };
+    let previous = state.current.replace(preset.id);
+    if let Some(old) = previous
+        .filter(|p| *p != preset.id)
+        .and_then(|p| properties.preset_index(p))
+    {
+        let new_ports = properties.preset_ports(properties.current_preset);
+        let released = release(properties, old, &new_ports, state, &mut plan);
+        state.released.extend(released);
+    }
+    plan
+}
+
+/// Apply the release policy of the routes of the preset that is left,
+/// returns the controllers that are faded out
+fn release(
+    properties: &Properties,
+    old: usize,
+    new_ports: &HashSet<String>,
+    state: &mut MorphState,
+    plan: &mut ActivationPlan,
+) -> HashSet<(String, u8, u8)> {
+    let mut immediate = HashSet::new();
+    let mut fades: HashMap<(String, u8, u8), f32> = HashMap::new();
+    properties
+        .inputs
+        .iter()
+        .filter_map(|input| properties.mapping(old, input.id))
+        .flatten()
+        .for_each(|route| {
+            for port_name in route.ports() {
+                let channels = state.used_channels.get(port_name).cloned();
+                for channel in channels.into_iter().flatten() {
+                    match route.release {
+                        ReleasePolicy::Ring => {}
+                        ReleasePolicy::Cut => {
+                            immediate.insert((port_name.clone(), vec![0xB0 | channel, 123, 0]));
+                        }
+                        ReleasePolicy::SustainOff => {
+                            immediate.insert((port_name.clone(), vec![0xB0 | channel, 64, 0]));
+                        }
+                        // Only fade outputs that the new preset does not use
+                        ReleasePolicy::FadeOut { time } if !new_ports.contains(port_name) => {
+                            let fade = fades.entry((port_name.clone(), channel, 7)).or_default();
+                            *fade = fade.max(clamp_time(time));
+                        }
+                        ReleasePolicy::FadeOut { .. } => {}
+                    }
+                }
+            }
+        });
+    plan.immediate.extend(immediate);
+
+    fades.iter().for_each(|(key, time)| {
+        plan.scheduled
+            .extend(ramp_events(&[(key.clone(), state.level(key), 0)], *time));
+        // Stop the notes once they are silent
+        let (port_name, channel, _) = key;
+        plan.scheduled.push((
+            Duration::from_secs_f32(*time),
+            vec![(port_name.clone(), vec![0xB0 | channel, 123, 0])],
+        ));
+        state.faded.insert(key.clone());
+    });
+    fades.into_keys().collect()
+}
+
+/// Calculate the intermediate values of controllers that change over `time` seconds
+fn ramp_events(ramps: &[Ramp], time: f32) -> Vec<(Duration, QueueItems)> {
+    let time = clamp_time(time);
+    let steps = ((time / STEP).ceil() as u32).max(1);
+    let mut last_values: Vec<u8> = ramps.iter().map(|(_, from, _)| *from).collect();
+    (1..=steps)
+        .filter_map(|step| {
+            let progress = step as f32 / steps as f32;
+            let items: QueueItems = ramps
+                .iter()
+                .zip(last_values.iter_mut())
+                .filter_map(|(((port_name, channel, controller), from, to), last)| {
+                    let value =
+                        (*from as f32 + (*to as f32 - *from as f32) * progress).round() as u8;
+                    if value == *last {
+                        return None;
+                    }
+                    *last = value;
+                    Some((port_name.clone(), vec![0xB0 | channel, *controller, value]))
+                })
+                .collect();
+            (!items.is_empty()).then(|| (Duration::from_secs_f32(time * progress), items))
+        })
+        .collect()
+}
+
+fn controller(port_name: &str, kind: &ActivationKind) -> Option<((String, u8, u8), u8)> {
+    if let ActivationKind::Controller {
+        channel,
+        controller,
+        value,
+    } = kind
+    {
+        let channel = (*channel).clamp(1, 16) - 1;
+        Some((
+            (port_name.to_string(), channel, controller & 0x7F),
+            value & 0x7F,
+        ))
+    } else {
+        None
+    }
+}
+
+fn controllers<'a>(messages: impl Iterator<Item = &'a ActivationMessage>) -> Controllers {
+    messages
+        .filter_map(|m| controller(&m.port_name, &m.kind))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::backend::output_settings::OutputSettings;
+    use crate::backend::preset::Preset;
+
+    #[test]
+    fn released_fade_restores_the_last_level() {
+        let mut properties = Properties::default();
+        let input = properties.inputs[0].id;
+        let mut route = OutputSettings::new("Synth".to_string());
+        route.release = ReleasePolicy::FadeOut { time: 1.0 };
+        properties.presets[0].mapping = HashMap::from([(input, vec![route])]);
+        properties.presets.push(Preset::new_numbered(1));
+        let (first, second) = (properties.presets[0].id, properties.presets[1].id);
+
+        let mut state = MorphState::default();
+        plan_release(&properties, &mut state);
+        state.record(&"Synth".to_string(), &[0xB0, 7, 90]);
+
+        properties.current_preset = 1;
+        let release = plan_release(&properties, &mut state);
+        let first_step = &release.scheduled[0].1[0].1;
+        assert!(first_step[2] < 90 && first_step[2] > 80);
+        // The fade itself is not the level of the performer
+        state.record(&"Synth".to_string(), &[0xB0, 7, 0]);
+
+        properties.current_preset = 0;
+        plan_release(&properties, &mut state);
+        let activation = plan_activation(&properties, Some(second), first, &mut state);
+        assert!(activation
+            .immediate
+            .contains(&("Synth".to_string(), vec![0xB0, 7, 90])));
+    }
+
+    #[test]
+    fn morph_stays_within_midi_range() {
+        let mut properties = Properties::default();
+        properties.presets.push(Preset::new_numbered(1));
+        let (first, second) = (properties.presets[0].id, properties.presets[1].id);
+        for (preset, value) in [(0, 255), (1, 0)] {
+            properties.presets[preset].activation.messages = vec![ActivationMessage {
+                port_name: "Synth".to_string(),
+                kind: ActivationKind::Controller {
+                    channel: 1,
+                    controller: 135,
+                    value,
+                },
+            }];
+        }
+        properties.presets[1].activation.morph_time = 1.0;
+
+        let mut state = MorphState::default();
+        let plan = plan_activation(&properties, Some(first), second, &mut state);
+        assert!(!plan.scheduled.is_empty());
+        let data = plan.scheduled.iter().flat_map(|(_, items)| items);
+        for (_, data) in data {
+            assert_eq!(data[1], 7);
+            assert!(data[2] < 0x80);
+        }
+    }
+}
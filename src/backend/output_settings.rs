@@ -1,19 +1,24 @@
-use std::hash::{Hash, Hasher};
-
+use itertools::Itertools;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::backend::activation::clamp_time;
 use crate::backend::common_settings::{
     default_cc_map, default_channel_map, default_filter, CcMap, ChannelMap, CommonSettings,
     Transpose, VelocityCurve, VelocityRange,
 };
+use crate::backend::id::Id;
 
 // Serde does not accept default = true, so we make it more stupid to make it work
 fn get_true() -> bool {
     true
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct OutputSettings {
+    /// Unique within the preset (or group) that this route belongs to
+    #[serde(default = "Id::random")]
+    pub id: Id,
     pub port_name: String,
     #[serde(default = "get_true")]
     pub buffer_pedals: bool,
@@ -31,11 +36,176 @@ pub struct OutputSettings {
     pub velocity_range: VelocityRange,
     #[serde(default)]
     pub transpose: Transpose,
+    /// Skip the global filters and maps for this route
+    #[serde(default)]
+    pub ignore_global_settings: bool,
+    #[serde(default)]
+    pub mpe: MpeSettings,
+    #[serde(default)]
+    pub voices: VoiceDistributor,
+    #[serde(default)]
+    pub release: ReleasePolicy,
+}
+
+/// What happens to the sound of a route when its preset is left
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug, Default)]
+pub enum ReleasePolicy {
+    /// Let notes ring, they get their note-off when the key is released
+    #[default]
+    Ring,
+    /// Send all-notes-off
+    Cut,
+    /// Send sustain pedal off
+    SustainOff,
+    /// Fade out with volume (CC 7) in the given amount of seconds
+    FadeOut { time: f32 },
+}
+
+impl ReleasePolicy {
+    pub fn all() -> &'static [ReleasePolicy; 4] {
+        &[
+            ReleasePolicy::Ring,
+            ReleasePolicy::Cut,
+            ReleasePolicy::SustainOff,
+            ReleasePolicy::FadeOut { time: 1.0 },
+        ]
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            ReleasePolicy::Ring => "Let notes ring",
+            ReleasePolicy::Cut => "Cut (all notes off)",
+            ReleasePolicy::SustainOff => "Release sustain pedal",
+            ReleasePolicy::FadeOut { .. } => "Fade out (CC 7)",
+        }
+    }
+
+    /// Limit the fade time to what can be scheduled (`validate` reports values outside it)
+    pub fn clamp_fade_time(&mut self) {
+        if let ReleasePolicy::FadeOut { time } = self {
+            *time = clamp_time(*time);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct MpeSettings {
+    pub enabled: bool,
+    pub zone: MpeZone,
+    pub member_channels: u8,
+    /// Assign the member channels of the output zone per note, instead of keeping input channels
+    pub reallocate: bool,
+    pub output_zone: MpeZone,
+    pub output_member_channels: u8,
+}
+
+impl Default for MpeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            zone: MpeZone::Lower,
+            member_channels: 15,
+            reallocate: false,
+            output_zone: MpeZone::Lower,
+            output_member_channels: 15,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MpeZone {
+    Lower,
+    Upper,
+}
+
+impl MpeZone {
+    /// Master channel of this zone (0..=15)
+    pub fn master(&self) -> u8 {
+        match self {
+            MpeZone::Lower => 0,
+            MpeZone::Upper => 15,
+        }
+    }
+
+    /// Member channels of this zone (0..=15), starting next to the master channel
+    pub fn members(&self, count: u8) -> Vec<u8> {
+        let count = count.clamp(1, 15);
+        match self {
+            MpeZone::Lower => (1..=count).collect(),
+            MpeZone::Upper => (15 - count..15).rev().collect(),
+        }
+    }
+}
+
+/// Spreads notes over a group of outputs, instead of sending everything to `port_name`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct VoiceDistributor {
+    pub enabled: bool,
+    pub mode: VoiceMode,
+    pub voices: Vec<Voice>,
+}
+
+impl VoiceDistributor {
+    /// Distinct (port, channel) pairs of the connected voices, channel is 0..=15
+    pub fn targets<'a>(&'a self, connected: &'a [bool]) -> impl Iterator<Item = (&'a String, u8)> {
+        self.voices
+            .iter()
+            .zip(connected)
+            .filter(|(_, &connected)| connected)
+            .map(|(voice, _)| (&voice.port_name, voice.channel.saturating_sub(1)))
+            .unique()
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum VoiceMode {
+    /// Take the next voice, even if it is still playing
+    #[default]
+    RoundRobin,
+    /// Take the first voice that is not playing
+    FirstFree,
+    /// Take the voice that has been free the longest, or the one playing the longest
+    StealOldest,
+}
+
+impl VoiceMode {
+    pub fn all() -> &'static [VoiceMode; 3] {
+        &[
+            VoiceMode::RoundRobin,
+            VoiceMode::FirstFree,
+            VoiceMode::StealOldest,
+        ]
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            VoiceMode::RoundRobin => "Round robin",
+            VoiceMode::FirstFree => "First free",
+            VoiceMode::StealOldest => "Steal oldest",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Voice {
+    pub port_name: String,
+    /// 1..=16
+    pub channel: u8,
+}
+
+impl Default for Voice {
+    fn default() -> Self {
+        Self {
+            port_name: String::new(),
+            channel: 1,
+        }
+    }
 }
 
 impl OutputSettings {
     pub fn new(port_name: String) -> Self {
         Self {
+            id: Id::random(),
             port_name,
             buffer_pedals: true,
             key_filter_enabled: false,
@@ -45,6 +215,25 @@ impl OutputSettings {
             velocity_curve: VelocityCurve::default(),
             velocity_range: VelocityRange::default(),
             transpose: Transpose::default(),
+            ignore_global_settings: false,
+            mpe: MpeSettings::default(),
+            voices: VoiceDistributor::default(),
+            release: ReleasePolicy::default(),
+        }
+    }
+
+    /// All output ports of this route, including the voices that are not used
+    pub fn ports_mut(&mut self) -> Vec<&mut String> {
+        let voices = self.voices.voices.iter_mut().map(|v| &mut v.port_name);
+        std::iter::once(&mut self.port_name).chain(voices).collect()
+    }
+
+    /// Outputs that this route sends to
+    pub fn ports(&self) -> Vec<&String> {
+        if self.voices.enabled {
+            self.voices.voices.iter().map(|v| &v.port_name).collect()
+        } else {
+            vec![&self.port_name]
         }
     }
 }
@@ -112,15 +301,3 @@ impl Default for OutputSettings {
         Self::new("".to_string())
     }
 }
-
-impl Hash for OutputSettings {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        Hash::hash(&self.port_name, state)
-    }
-}
-
-impl PartialEq<Self> for OutputSettings {
-    fn eq(&self, other: &Self) -> bool {
-        self.port_name == other.port_name
-    }
-}
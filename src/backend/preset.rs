@@ -1,31 +1,278 @@
+use crate::backend::activation::{clamp_time, Activation};
+use crate::backend::id::Id;
+use crate::backend::input_settings::InputOverride;
 use crate::backend::output_settings::OutputSettings;
+use crate::backend::triggers::TriggerEvent;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Preset {
-    pub id: usize,
+    #[serde(default = "Id::random")]
+    pub id: Id,
     pub name: String,
-    pub mapping: HashMap<usize, Vec<OutputSettings>>, // [list of outputs for each input]
+    pub mapping: HashMap<Id, Vec<OutputSettings>>, // [list of outputs for each input]
+    #[serde(default)]
+    pub feedback: PresetFeedback,
+    /// Messages that are sent to outputs when this preset becomes active
+    #[serde(default)]
+    pub activation: Activation,
+    /// When switching away from this preset with MIDI
+    #[serde(default)]
+    pub switch_policy: SwitchPolicy,
+    /// Group (song) that this preset is a variant of
+    #[serde(default)]
+    pub group: Option<Id>,
+    /// Changes to the routes of the group, by route id. Routes without an entry are inherited
+    #[serde(default)]
+    pub overrides: HashMap<Id, RouteOverride>,
+    /// Changes to the settings of the inputs, by input id
+    #[serde(default)]
+    pub input_overrides: HashMap<Id, InputOverride>,
 }
 
 impl Preset {
-    pub fn new(id: usize, name: String) -> Self {
+    pub fn new(name: String) -> Self {
         Self {
-            id,
+            id: Id::random(),
             name,
             mapping: HashMap::new(),
+            feedback: PresetFeedback::default(),
+            activation: Activation::default(),
+            switch_policy: SwitchPolicy::default(),
+            group: None,
+            overrides: HashMap::new(),
+            input_overrides: HashMap::new(),
         }
     }
 
-    pub fn new_from_id(id: usize) -> Self {
-        Self::new(id, format!("Preset {}", id + 1))
+    /// New preset at this position in the list
+    pub fn new_numbered(index: usize) -> Self {
+        Self::new(format!("Preset {}", index + 1))
+    }
+
+    /// Limit the morph and fade times to what can be scheduled (`validate` reports values outside it)
+    pub fn clamp_times(&mut self) {
+        self.activation.morph_time = clamp_time(self.activation.morph_time);
+        let overrides = self.overrides.values_mut().filter_map(|o| match o {
+            RouteOverride::Replace(route) => Some(route),
+            _ => None,
+        });
+        self.mapping
+            .values_mut()
+            .flatten()
+            .chain(overrides)
+            .for_each(|route| route.release.clamp_fade_time());
+    }
+
+    /// Output ports of the routes, changed group routes and activation messages
+    pub fn ports_mut(&mut self) -> Vec<&mut String> {
+        let overrides = self.overrides.values_mut().filter_map(|o| match o {
+            RouteOverride::Replace(route) => Some(route),
+            _ => None,
+        });
+        let messages = self
+            .activation
+            .messages
+            .iter_mut()
+            .map(|m| &mut m.port_name);
+        self.mapping
+            .values_mut()
+            .flatten()
+            .chain(overrides)
+            .flat_map(|route| route.ports_mut())
+            .chain(messages)
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SwitchPolicy {
+    #[default]
+    Immediately,
+    /// Wait until all notes (and optionally the sustain pedal) are released
+    AfterRelease { include_pedal: bool },
+    /// Wait for the next bar of the incoming MIDI clock (4/4), or switch right away without clock
+    NextBar,
+}
+
+impl SwitchPolicy {
+    pub fn all() -> &'static [SwitchPolicy; 3] {
+        &[
+            SwitchPolicy::Immediately,
+            SwitchPolicy::AfterRelease {
+                include_pedal: false,
+            },
+            SwitchPolicy::NextBar,
+        ]
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            SwitchPolicy::Immediately => "Immediately",
+            SwitchPolicy::AfterRelease { .. } => "After all notes are released",
+            SwitchPolicy::NextBar => "At the next bar (MIDI clock)",
+        }
+    }
+
+    /// Check if we can switch now
+    pub fn ready(&self, notes_held: bool, pedal_held: bool, at_bar: bool) -> bool {
+        match self {
+            SwitchPolicy::Immediately => true,
+            SwitchPolicy::AfterRelease { include_pedal } => {
+                !(notes_held || *include_pedal && pedal_held)
+            }
+            SwitchPolicy::NextBar => at_bar,
+        }
+    }
+}
+
+/// Group of presets (i.e. a song), of which the presets are the variants (i.e. verse, chorus)
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PresetGroup {
+    #[serde(default = "Id::random")]
+    pub id: Id,
+    pub name: String,
+    /// Routes that are shared by all variants
+    pub mapping: HashMap<Id, Vec<OutputSettings>>,
+}
+
+impl PresetGroup {
+    pub fn new(name: String) -> Self {
+        Self {
+            id: Id::random(),
+            name,
+            mapping: HashMap::new(),
+        }
+    }
+
+    pub fn ports_mut(&mut self) -> Vec<&mut String> {
+        self.mapping
+            .values_mut()
+            .flatten()
+            .flat_map(|route| route.ports_mut())
+            .collect()
+    }
+
+    /// Limit the fade times of the routes, like `Preset::clamp_times`
+    pub fn clamp_times(&mut self) {
+        self.mapping
+            .values_mut()
+            .flatten()
+            .for_each(|route| route.release.clamp_fade_time());
+    }
+}
+
+/// How a variant uses a route of its group
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub enum RouteOverride {
+    #[default]
+    Inherit,
+    Replace(OutputSettings),
+    Disable,
+}
+
+impl RouteOverride {
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            RouteOverride::Inherit => "Inherit",
+            RouteOverride::Replace(_) => "Change",
+            RouteOverride::Disable => "Disable",
+        }
+    }
+}
+
+/// Index of the next (or previous) variant in the same group as `current`
+pub fn next_variant(presets: &[Preset], current: usize, forward: bool) -> Option<usize> {
+    let group = presets.get(current)?.group?;
+    let next = if forward {
+        current + 1
+    } else {
+        current.checked_sub(1)?
+    };
+    presets
+        .get(next)
+        .filter(|p| p.group == Some(group))
+        .map(|_| next)
+}
+
+/// Index of the first variant of the next (or previous) group.
+/// Presets without a group count as a group of their own.
+pub fn next_group(presets: &[Preset], current: usize, forward: bool) -> Option<usize> {
+    let group = presets.get(current)?.group;
+    if forward {
+        let mut next = current + 1;
+        while group.is_some() && presets.get(next).is_some_and(|p| p.group == group) {
+            next += 1;
+        }
+        (next < presets.len()).then_some(next)
+    } else {
+        let previous = group_start(presets, current).checked_sub(1)?;
+        Some(group_start(presets, previous))
     }
 }
 
+/// Index of the first variant in the group of this preset
+fn group_start(presets: &[Preset], index: usize) -> usize {
+    let group = presets[index].group;
+    let mut start = index;
+    while group.is_some() && start > 0 && presets[start - 1].group == group {
+        start -= 1;
+    }
+    start
+}
+
 impl Hash for Preset {
     fn hash<H: Hasher>(&self, state: &mut H) {
         Hash::hash(&self.id, state)
     }
 }
+
+/// Message that is sent to the feedback output to light up the pad of this preset
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct PresetFeedback {
+    pub enabled: bool,
+    pub event: TriggerEvent,
+    /// 1 - 16
+    pub channel: u8,
+    /// Key or controller number
+    pub number: u8,
+    /// Velocity or CC value when the preset is active
+    pub on_value: u8,
+    /// Velocity or CC value when the preset is not active
+    pub off_value: u8,
+}
+
+impl PresetFeedback {
+    pub fn message(&self, active: bool) -> Vec<u8> {
+        let status = match self.event {
+            TriggerEvent::Note => 0x90,
+            TriggerEvent::Controller => 0xB0,
+        };
+        let value = if active {
+            self.on_value
+        } else {
+            self.off_value
+        };
+        vec![
+            status | (self.channel.clamp(1, 16) - 1),
+            self.number & 0x7F,
+            value & 0x7F,
+        ]
+    }
+}
+
+impl Default for PresetFeedback {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            event: TriggerEvent::Note,
+            channel: 1,
+            number: 36,
+            on_value: 127,
+            off_value: 0,
+        }
+    }
+}
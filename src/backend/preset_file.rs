@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use pro_serde_versioned::{VersionedDeserialize, VersionedSerialize, VersionedUpgrade};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::id::Id;
+use crate::backend::input_settings::InputSettings;
+use crate::backend::output_settings::OutputSettings;
+use crate::backend::preset::{Preset, PresetGroup};
+use crate::backend::MidiPort;
+
+/// Presets exported from one file, to be imported in another
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PresetFile {
+    /// Inputs that the routes of the presets belong to
+    pub inputs: Vec<ExportedInput>,
+    /// Groups of the presets, which `Preset::group` refers to
+    #[serde(default)]
+    pub groups: Vec<PresetGroup>,
+    pub presets: Vec<Preset>,
+}
+
+/// Versions of the preset file, like `PropertiesVersioned` for configs
+#[derive(VersionedSerialize, VersionedDeserialize, VersionedUpgrade, Clone, Debug)]
+pub enum PresetFileVersioned {
+    V1(PresetFile),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExportedInput {
+    pub id: Id,
+    pub port_name: String,
+}
+
+/// Presets of a file that are being imported, with the inputs and outputs they should use here
+#[derive(Clone, Debug, Default)]
+pub struct PresetImport {
+    pub file: PresetFile,
+    /// Presets that will be imported
+    pub selected: HashSet<Id>,
+    /// Input here for each input of the file, `None` leaves out the routes of that input
+    pub inputs: HashMap<Id, Option<Id>>,
+    /// New port for each output port of the file that is not available here
+    pub outputs: BTreeMap<String, String>,
+}
+
+impl PresetImport {
+    pub fn new(file: PresetFile, inputs: &[InputSettings], available_outputs: &[MidiPort]) -> Self {
+        let selected = file.presets.iter().map(|p| p.id).collect();
+        // Use the input with the same port, or else the one at the same position
+        let input_map = file
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let local = inputs
+                    .iter()
+                    .find(|l| !l.port_name.is_empty() && l.port_name == input.port_name)
+                    .or(inputs.get(i))
+                    .map(|l| l.id);
+                (input.id, local)
+            })
+            .collect();
+
+        let mut ports = file.clone();
+        let outputs = ports
+            .presets
+            .iter_mut()
+            .flat_map(|p| p.ports_mut())
+            .chain(ports.groups.iter_mut().flat_map(|g| g.ports_mut()))
+            .filter(|port| !port.is_empty())
+            .filter(|port| !available_outputs.iter().any(|p| p.readable == **port))
+            .map(|port| (port.clone(), port.clone()))
+            .collect();
+
+        Self {
+            file,
+            selected,
+            inputs: input_map,
+            outputs,
+        }
+    }
+
+    /// Change the inputs and outputs of a preset or group to the ones chosen for this import
+    pub fn remap_mapping(
+        &self,
+        mapping: HashMap<Id, Vec<OutputSettings>>,
+    ) -> HashMap<Id, Vec<OutputSettings>> {
+        let mut remapped: HashMap<Id, Vec<OutputSettings>> = HashMap::new();
+        mapping.into_iter().for_each(|(input, routes)| {
+            if let Some(Some(local)) = self.inputs.get(&input) {
+                remapped.entry(*local).or_default().extend(routes);
+            }
+        });
+        remapped
+    }
+
+    pub fn remap_port(&self, port: &mut String) {
+        if let Some(new) = self.outputs.get(port) {
+            *port = new.clone();
+        }
+    }
+}
@@ -1,46 +1,155 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use midly::live::LiveEvent;
+use midly::MidiMessage;
 use pro_serde_versioned::{Upgrade, VersionedDeserialize, VersionedSerialize, VersionedUpgrade};
 use regex::{Captures, Regex};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
+use crate::backend::common_settings::GlobalSettings;
+use crate::backend::device_alias::DeviceAlias;
+use crate::backend::file_format::FileFormat;
+use crate::backend::id::Id;
 use crate::backend::input_settings::InputSettings;
-use crate::backend::preset::Preset;
+use crate::backend::output_settings::OutputSettings;
+use crate::backend::preset::{Preset, PresetGroup, RouteOverride};
+use crate::backend::preset_file::{ExportedInput, PresetFile, PresetImport};
+use crate::backend::setlist::{SetlistEntry, SetlistKeys};
+
+/// Which incoming events a MIDI learn target is waiting for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LearnKind {
+    Note,
+    Controller,
+    Program,
+    Channel,
+}
+
+impl LearnKind {
+    /// Check if a message from input `source` is what a target of input `input` is waiting for.
+    /// Targets without an input accept messages from any input.
+    pub fn accepts(&self, message: &MidiMessage, input: Option<Id>, source: Id) -> bool {
+        if input.is_some_and(|input| input != source) {
+            return false;
+        }
+        match self {
+            // The channel map only applies to notes
+            LearnKind::Note | LearnKind::Channel => {
+                matches!(message, MidiMessage::NoteOn { vel, .. } if *vel > 0)
+            }
+            LearnKind::Controller => matches!(message, MidiMessage::Controller { .. }),
+            LearnKind::Program => matches!(message, MidiMessage::ProgramChange { .. }),
+        }
+    }
+}
 
 #[derive(Default, Clone, Debug)]
 pub struct MidiLearn {
-    pub target: Option<usize>,
-    pub result: Option<Vec<u8>>,
+    /// Unique id of the widget that is waiting for an event, and the input that it belongs to
+    pub target: Option<(String, LearnKind, Option<Id>)>,
+    /// Learned event and the input it came from
+    pub result: Option<(Id, Vec<u8>)>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl MidiLearn {
+    pub fn start(&mut self, target: String, kind: LearnKind, input: Option<Id>) {
+        self.target = Some((target, kind, input));
+        self.result = None;
+    }
+
+    pub fn cancel(&mut self) {
+        self.target = None;
+        self.result = None;
+    }
+
+    pub fn is_learning(&self, target: &str) -> bool {
+        self.target.as_ref().is_some_and(|(t, _, _)| t == target)
+    }
+
+    /// Store the event from this input if a target is waiting for it, returns true if it was stored
+    pub fn learn(&mut self, source: Id, event: &LiveEvent, data: &[u8]) -> bool {
+        let Some((_, kind, input)) = &self.target else {
+            return false;
+        };
+        if self.result.is_some() {
+            return false;
+        }
+        let matches = match event {
+            LiveEvent::Midi { message, .. } => kind.accepts(message, *input, source),
+            _ => false,
+        };
+        if matches {
+            self.result = Some((source, data.to_vec()));
+        }
+        matches
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Properties {
     pub inputs: Vec<InputSettings>,
     pub presets: Vec<Preset>,
+    #[serde(default)]
+    pub groups: Vec<PresetGroup>,
+    #[serde(default)]
+    pub setlist: Vec<SetlistEntry>,
+    /// Keys that go to the next or previous song in the setlist
+    #[serde(default)]
+    pub setlist_keys: SetlistKeys,
+    /// Song in the setlist that was selected last
+    #[serde(skip)]
+    pub setlist_position: Option<usize>,
     #[serde(skip)]
     pub current_preset: usize,
+    /// Preset that will be selected once the switch policy of the current preset allows it
+    #[serde(skip)]
+    pub pending_preset: Option<Id>,
     #[serde(default)]
     pub transpose: i8,
     #[serde(default)]
+    pub global_settings: GlobalSettings,
+    #[serde(default)]
     pub shortcuts: Vec<String>,
+    /// Names for devices that are used instead of their port names
+    #[serde(default)]
+    pub aliases: Vec<DeviceAlias>,
+    /// Output that receives the feedback messages of the presets
+    #[serde(default)]
+    pub feedback_output: String,
     #[serde(skip)]
     pub changed: bool,
     #[serde(skip)]
     pub saved: bool,
+    /// Set when a file is loaded, so the undo history starts at the loaded file
+    #[serde(skip)]
+    pub loaded: bool,
+    /// Format of the file that was loaded, which is kept when saving
+    #[serde(skip)]
+    pub file_format: FileFormat,
+    /// When a MIDI clock tick was received last, on any input
+    #[serde(skip)]
+    pub last_clock: Option<Instant>,
 }
 
 impl Properties {
-    pub fn remove_preset(&mut self, id: usize) {
-        self.presets.remove(id);
-        // Update "internal" ids to match position in list
-        self.presets
-            .iter_mut()
-            .enumerate()
-            .for_each(|(i, p)| p.id = i);
-        self.current_preset = if id > 0 { id - 1 } else { 0 };
+    pub fn remove_preset(&mut self, index: usize) {
+        self.presets.remove(index);
+        self.current_preset = index.saturating_sub(1);
     }
 
-    pub fn duplicate_preset(&mut self, id: usize) {
-        let new_preset = self.presets.get(id).cloned();
+    pub fn duplicate_preset(&mut self, index: usize) {
+        let new_preset = self.presets.get(index).cloned();
         if let Some(mut preset) = new_preset {
+            preset.id = Id::random();
+            // Routes of the copy are separate from the original
+            preset
+                .mapping
+                .values_mut()
+                .flatten()
+                .for_each(|route| route.id = Id::random());
             let re = Regex::new(r"\((\d+)\)$").unwrap();
             if re.find(&preset.name).is_some() {
                 preset.name = re
@@ -52,13 +161,353 @@ impl Properties {
             } else {
                 preset.name += " (2)";
             }
-            self.presets.insert(id + 1, preset);
-            // Update "internal" ids to match position in list
-            self.presets
-                .iter_mut()
-                .enumerate()
-                .for_each(|(i, p)| p.id = i);
-            self.current_preset = id + 1;
+            self.presets.insert(index + 1, preset);
+            self.current_preset = index + 1;
+        }
+    }
+
+    /// Current index of the preset with this id
+    pub fn preset_index(&self, id: Id) -> Option<usize> {
+        self.presets.iter().position(|p| p.id == id)
+    }
+
+    pub fn group(&self, id: Id) -> Option<&PresetGroup> {
+        self.groups.iter().find(|g| g.id == id)
+    }
+
+    pub fn group_mut(&mut self, id: Id) -> Option<&mut PresetGroup> {
+        self.groups.iter_mut().find(|g| g.id == id)
+    }
+
+    /// Remove an input and the routes that belong to it
+    pub fn remove_input(&mut self, index: usize) {
+        if index >= self.inputs.len() {
+            return;
+        }
+        let input = self.inputs.remove(index).id;
+        self.presets.iter_mut().for_each(|p| {
+            p.mapping.remove(&input);
+            p.input_overrides.remove(&input);
+        });
+        self.groups.iter_mut().for_each(|g| {
+            g.mapping.remove(&input);
+        });
+    }
+
+    /// Settings of an input, with the overrides of a preset applied
+    pub fn input_settings(&self, preset: usize, input: Id) -> Option<InputSettings> {
+        let input_settings = self.inputs.iter().find(|i| i.id == input)?;
+        match self
+            .presets
+            .get(preset)
+            .and_then(|p| p.input_overrides.get(&input))
+        {
+            Some(input_override) => Some(input_override.apply(input_settings)),
+            None => Some(input_settings.clone()),
+        }
+    }
+
+    /// Routes of a preset for an input, including the ones it inherits from its group
+    pub fn mapping(&self, preset: usize, input: Id) -> Option<Vec<OutputSettings>> {
+        let preset = self.presets.get(preset)?;
+        let own = preset.mapping.get(&input);
+        let inherited = preset
+            .group
+            .and_then(|g| self.group(g))
+            .and_then(|g| g.mapping.get(&input));
+        if own.is_none() && inherited.is_none() {
+            return None;
+        }
+        let mut mapping: Vec<_> = inherited
+            .into_iter()
+            .flatten()
+            .filter_map(|route| match preset.overrides.get(&route.id) {
+                None | Some(RouteOverride::Inherit) => Some(route.clone()),
+                Some(RouteOverride::Replace(route)) => Some(route.clone()),
+                Some(RouteOverride::Disable) => None,
+            })
+            .collect();
+        mapping.extend(own.into_iter().flatten().cloned());
+        Some(mapping)
+    }
+
+    /// Switch presets from MIDI, respecting the switch policy of the current preset
+    pub fn request_preset(&mut self, preset: usize, notes_held: bool, pedal_held: bool) {
+        self.pending_preset = self.presets.get(preset).map(|p| p.id);
+        self.apply_pending_preset(notes_held, pedal_held, false);
+    }
+
+    /// Index of the pending preset, or of the current preset if nothing is pending
+    pub fn target_preset(&self) -> usize {
+        self.pending_preset
+            .and_then(|id| self.preset_index(id))
+            .unwrap_or(self.current_preset)
+    }
+
+    /// Switch to the pending preset if the current preset allows it, returns true if it switched
+    pub fn apply_pending_preset(
+        &mut self,
+        notes_held: bool,
+        pedal_held: bool,
+        at_bar: bool,
+    ) -> bool {
+        let Some(pending) = self.pending_preset else {
+            return false;
+        };
+        // The preset could have been removed while waiting
+        let Some(preset) = self.preset_index(pending) else {
+            self.pending_preset = None;
+            return false;
+        };
+        let policy = self
+            .presets
+            .get(self.current_preset)
+            .map(|p| p.switch_policy)
+            .unwrap_or_default();
+        // Without a MIDI clock there are no bars to wait for
+        let at_bar = at_bar || !self.clock_running();
+        if policy.ready(notes_held, pedal_held, at_bar) || preset == self.current_preset {
+            self.current_preset = preset;
+            self.pending_preset = None;
+            return true;
+        }
+        false
+    }
+
+    /// Check if a MIDI clock tick was received recently
+    pub fn clock_running(&self) -> bool {
+        self.last_clock
+            .is_some_and(|t| t.elapsed() < Duration::from_secs(1))
+    }
+
+    /// Entry in the setlist after (or before) the current one
+    pub fn setlist_step(&self, forward: bool) -> Option<usize> {
+        let next = match (self.setlist_position, forward) {
+            (None, _) => 0,
+            (Some(position), true) => position + 1,
+            (Some(position), false) => position.checked_sub(1)?,
+        };
+        (next < self.setlist.len()).then_some(next)
+    }
+
+    /// Go to a song in the setlist and select its preset
+    pub fn select_setlist_entry(&mut self, entry: usize) {
+        if let Some(song) = self.setlist.get(entry) {
+            self.setlist_position = Some(entry);
+            if let Some(preset) = self.preset_index(song.preset) {
+                self.current_preset = preset;
+                self.pending_preset = None;
+            }
+        }
+    }
+
+    /// All outputs that the routes of a preset send to
+    pub fn preset_ports(&self, preset: usize) -> HashSet<String> {
+        self.inputs
+            .iter()
+            .filter_map(|input| self.mapping(preset, input.id))
+            .flatten()
+            .flat_map(|route| route.ports().into_iter().cloned().collect::<Vec<_>>())
+            .collect()
+    }
+
+    pub fn add_group(&mut self) -> Id {
+        let group = PresetGroup::new(format!("Group {}", self.groups.len() + 1));
+        let id = group.id;
+        self.groups.push(group);
+        id
+    }
+
+    pub fn remove_group(&mut self, group: Id) {
+        self.groups.retain(|g| g.id != group);
+        // Variants of this group become normal presets
+        self.presets
+            .iter_mut()
+            .filter(|p| p.group == Some(group))
+            .for_each(|p| {
+                p.group = None;
+                p.overrides.clear();
+            });
+        self.sort_presets();
+    }
+
+    /// Remove a route of a group, and the overrides of the variants for it
+    pub fn remove_group_route(&mut self, group: Id, input: Id, route: usize) {
+        let Some(mapping) = self
+            .group_mut(group)
+            .and_then(|g| g.mapping.get_mut(&input))
+        else {
+            return;
+        };
+        if route >= mapping.len() {
+            return;
+        }
+        let route = mapping.remove(route).id;
+        self.presets
+            .iter_mut()
+            .filter(|p| p.group == Some(group))
+            .for_each(|p| {
+                p.overrides.remove(&route);
+            });
+    }
+
+    pub fn add_variant(&mut self, group: Id) {
+        let count = self
+            .presets
+            .iter()
+            .filter(|p| p.group == Some(group))
+            .count();
+        let mut preset = Preset::new(format!("Variant {}", count + 1));
+        preset.group = Some(group);
+        self.presets.push(preset);
+        self.sort_presets();
+    }
+
+    pub fn set_group(&mut self, preset: usize, group: Option<Id>) {
+        if let Some(preset) = self.presets.get_mut(preset) {
+            if preset.group != group {
+                preset.group = group;
+                preset.overrides.clear();
+            }
+        }
+        self.sort_presets();
+    }
+
+    /// Selected presets with their groups, to be saved in a separate file
+    pub fn export_presets(&self, selected: &HashSet<Id>) -> PresetFile {
+        let presets: Vec<_> = self
+            .presets
+            .iter()
+            .filter(|p| selected.contains(&p.id))
+            .cloned()
+            .map(|mut preset| {
+                preset.group = preset.group.filter(|g| self.group(*g).is_some());
+                preset
+            })
+            .collect();
+        let groups: Vec<_> = self
+            .groups
+            .iter()
+            .filter(|g| presets.iter().any(|p| p.group == Some(g.id)))
+            .cloned()
+            .collect();
+        // Only the inputs that are used by these presets
+        let inputs =
+            self.inputs
+                .iter()
+                .filter(|i| {
+                    presets.iter().any(|p| {
+                        p.mapping.contains_key(&i.id) || p.input_overrides.contains_key(&i.id)
+                    }) || groups
+                        .iter()
+                        .any(|g: &PresetGroup| g.mapping.contains_key(&i.id))
+                })
+                .map(|i| ExportedInput {
+                    id: i.id,
+                    port_name: i.port_name.clone(),
+                })
+                .collect();
+        PresetFile {
+            inputs,
+            groups,
+            presets,
+        }
+    }
+
+    /// Add the selected presets of another file, returns the amount of imported presets
+    pub fn import_presets(&mut self, import: &PresetImport) -> usize {
+        let mut groups = HashMap::new();
+        // New ids of the group routes, which the overrides of the variants refer to
+        let mut group_routes = HashMap::new();
+        let mut count = 0;
+        import
+            .file
+            .presets
+            .iter()
+            .filter(|p| import.selected.contains(&p.id))
+            .cloned()
+            .for_each(|mut preset| {
+                // The preset and its routes could already exist here, if it was imported before
+                preset.id = Id::random();
+                preset.clamp_times();
+                preset.mapping = import.remap_mapping(preset.mapping);
+                preset
+                    .mapping
+                    .values_mut()
+                    .flatten()
+                    .for_each(|route| route.id = Id::random());
+                preset.input_overrides = preset
+                    .input_overrides
+                    .into_iter()
+                    .filter_map(|(input, o)| Some((import.inputs.get(&input).copied()??, o)))
+                    .collect();
+                preset
+                    .ports_mut()
+                    .into_iter()
+                    .for_each(|port| import.remap_port(port));
+                // Import the group along with its first variant
+                preset.group = preset.group.and_then(|g| {
+                    if let Some(local) = groups.get(&g) {
+                        return Some(*local);
+                    }
+                    let mut group = import
+                        .file
+                        .groups
+                        .iter()
+                        .find(|group| group.id == g)?
+                        .clone();
+                    // The group could already exist here, if it was imported before
+                    group.id = Id::random();
+                    group.clamp_times();
+                    group.mapping = import.remap_mapping(group.mapping);
+                    group.mapping.values_mut().flatten().for_each(|route| {
+                        let id = Id::random();
+                        group_routes.insert(route.id, id);
+                        route.id = id;
+                    });
+                    group
+                        .ports_mut()
+                        .into_iter()
+                        .for_each(|port| import.remap_port(port));
+                    let id = group.id;
+                    self.groups.push(group);
+                    groups.insert(g, id);
+                    Some(id)
+                });
+                if preset.group.is_none() {
+                    preset.overrides.clear();
+                }
+                preset.overrides = preset
+                    .overrides
+                    .into_iter()
+                    .filter_map(|(route, mut route_override)| {
+                        let id = *group_routes.get(&route)?;
+                        if let RouteOverride::Replace(route) = &mut route_override {
+                            route.id = id;
+                        }
+                        Some((id, route_override))
+                    })
+                    .collect();
+                self.presets.push(preset);
+                count += 1;
+            });
+        self.sort_presets();
+        count
+    }
+
+    /// Keep the variants of a group together, presets without a group come first
+    pub fn sort_presets(&mut self) {
+        let current = self.presets.get(self.current_preset).map(|p| p.id);
+        let groups: HashMap<_, _> = self
+            .groups
+            .iter()
+            .enumerate()
+            .map(|(i, g)| (g.id, i + 1))
+            .collect();
+        self.presets
+            .sort_by_key(|p| p.group.and_then(|g| groups.get(&g).copied()).unwrap_or(0));
+        if let Some(current) = current.and_then(|id| self.preset_index(id)) {
+            self.current_preset = current;
         }
     }
 }
@@ -67,12 +516,23 @@ impl Default for Properties {
     fn default() -> Self {
         Self {
             inputs: vec![InputSettings::default()],
-            presets: vec![Preset::new_from_id(0)],
+            presets: vec![Preset::new_numbered(0)],
+            groups: vec![],
+            setlist: vec![],
+            setlist_keys: SetlistKeys::default(),
+            setlist_position: None,
             current_preset: 0,
+            pending_preset: None,
             transpose: 0,
+            global_settings: GlobalSettings::default(),
             shortcuts: vec![],
+            aliases: vec![],
+            feedback_output: String::new(),
             changed: false,
             saved: false,
+            loaded: false,
+            file_format: FileFormat::default(),
+            last_clock: None,
         }
     }
 }
@@ -80,27 +540,358 @@ impl Default for Properties {
 #[derive(VersionedSerialize, VersionedDeserialize, VersionedUpgrade, Clone, Debug)]
 pub enum PropertiesVersioned {
     V1(PropertiesV0_3_0),
-    V2(Properties),
+    V2(PropertiesV0_9_0),
+    V3(Properties),
 }
 
 //////////////////////////////////////////////////////////////////////
 //                      Older versions of properties                //
 //////////////////////////////////////////////////////////////////////
+
+// Before v0.10.0, presets, inputs and routes referred to each other by their index.
+// The older versions are read as JSON and get their ids while deserialising.
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(rename = "Properties")]
-pub struct PropertiesV0_3_0 {
-    pub inputs: Vec<InputSettings>,
-    pub presets: Vec<Preset>,
-    pub current_preset: usize,
+#[serde(try_from = "Value", into = "Value")]
+pub struct PropertiesV0_3_0(Properties);
+
+impl TryFrom<Value> for PropertiesV0_3_0 {
+    type Error = String;
+
+    fn try_from(data: Value) -> Result<Self, Self::Error> {
+        // These fields were required back then
+        if ["inputs", "presets", "current_preset"]
+            .iter()
+            .any(|key| data.get(key).is_none())
+        {
+            return Err("not a v0.3.0 config".to_string());
+        }
+        add_ids(data).map(Self)
+    }
+}
+
+impl From<PropertiesV0_3_0> for Value {
+    fn from(properties: PropertiesV0_3_0) -> Self {
+        serde_json::to_value(properties.0).unwrap_or_default()
+    }
+}
+
+impl Upgrade<PropertiesV0_9_0> for PropertiesV0_3_0 {
+    fn upgrade(self) -> PropertiesV0_9_0 {
+        PropertiesV0_9_0(self.0)
+    }
 }
 
-impl Upgrade<Properties> for PropertiesV0_3_0 {
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "Value", into = "Value")]
+pub struct PropertiesV0_9_0(Properties);
+
+impl TryFrom<Value> for PropertiesV0_9_0 {
+    type Error = String;
+
+    fn try_from(data: Value) -> Result<Self, Self::Error> {
+        add_ids(data).map(Self)
+    }
+}
+
+impl From<PropertiesV0_9_0> for Value {
+    fn from(properties: PropertiesV0_9_0) -> Self {
+        serde_json::to_value(properties.0).unwrap_or_default()
+    }
+}
+
+impl Upgrade<Properties> for PropertiesV0_9_0 {
     fn upgrade(self) -> Properties {
-        Properties {
-            inputs: self.inputs,
-            presets: self.presets,
-            current_preset: self.current_preset,
-            ..Properties::default()
+        self.0
+    }
+}
+
+/// Give inputs and presets an id, and key the routes of the presets by input id instead of index
+fn add_ids(mut data: Value) -> Result<Properties, String> {
+    let current_preset = data
+        .get("current_preset")
+        .and_then(Value::as_u64)
+        .unwrap_or_default() as usize;
+    shift_cc_map_channels(&mut data);
+    let input_ids = replace_ids(data.get_mut("inputs"));
+    replace_ids(data.get_mut("presets"));
+
+    if let Some(Value::Array(presets)) = data.get_mut("presets") {
+        for preset in presets {
+            if let Some(Value::Object(mapping)) = preset.get_mut("mapping") {
+                *mapping = std::mem::take(mapping)
+                    .into_iter()
+                    .filter_map(|(input, routes)| {
+                        let input = input_ids.get(input.parse::<usize>().ok()?)?;
+                        Some((input.to_string(), routes))
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    let mut properties = Properties::deserialize(data).map_err(|e| e.to_string())?;
+    properties.current_preset = current_preset.min(properties.presets.len().saturating_sub(1));
+    Ok(properties)
+}
+
+/// Before v0.10.0, CC map entries were matched on channels starting at 0, while 0 also meant any channel.
+/// Now channels start at 1, so move the entries for a specific channel up by one.
+fn shift_cc_map_channels(value: &mut Value) {
+    match value {
+        Value::Object(object) => object.iter_mut().for_each(|(key, value)| {
+            if let ("cc_map", Value::Array(entries)) = (key.as_str(), &mut *value) {
+                let last = entries.pop();
+                // Channel 16 did not exist, so these entries never matched anything
+                entries.retain(|entry| entry.get(0).and_then(Value::as_u64) != Some(16));
+                entries
+                    .iter_mut()
+                    .filter_map(|entry| entry.get_mut(0))
+                    .for_each(|channel| {
+                        if let Some(c) = channel.as_u64().filter(|c| *c > 0) {
+                            *channel = Value::from(c + 1);
+                        }
+                    });
+                entries.extend(last);
+            } else {
+                shift_cc_map_channels(value);
+            }
+        }),
+        Value::Array(array) => array.iter_mut().for_each(shift_cc_map_channels),
+        _ => {}
+    }
+}
+
+/// Give each item in the list a new id, returns the ids in order
+fn replace_ids(list: Option<&mut Value>) -> Vec<Id> {
+    let Some(Value::Array(list)) = list else {
+        return Vec::new();
+    };
+    list.iter_mut()
+        .map(|item| {
+            let id = Id::random();
+            if let Value::Object(item) = item {
+                item.insert("id".to_string(), Value::String(id.to_string()));
+            }
+            id
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::parse_properties;
+
+    #[test]
+    fn unversioned_file_gets_ids() {
+        let file = json!({
+            "inputs": [
+                {"port_name": "Keyboard", "use_program_change": false},
+                {"port_name": "Pads", "use_program_change": true},
+            ],
+            "presets": [
+                {"name": "Piano", "mapping": {"0": [{"port_name": "Synth"}]}},
+                {"name": "Drums", "mapping": {"1": [{"port_name": "Drum machine"}, {"port_name": "Synth"}]}},
+            ],
+            "current_preset": 1,
+        });
+        let properties = parse_properties(&file.to_string(), FileFormat::Json).unwrap();
+        let [keyboard, pads] = [0, 1].map(|i| properties.inputs[i].id);
+        assert_ne!(keyboard, pads);
+        assert_eq!(properties.current_preset, 1);
+
+        let ports = |preset: usize, input: Id| -> Vec<_> {
+            properties.presets[preset].mapping[&input]
+                .iter()
+                .map(|r| r.port_name.as_str())
+                .collect()
+        };
+        assert_eq!(ports(0, keyboard), ["Synth"]);
+        assert_eq!(ports(1, pads), ["Drum machine", "Synth"]);
+        assert!(!properties.presets[0].mapping.contains_key(&pads));
+        let routes = &properties.presets[1].mapping[&pads];
+        assert_ne!(routes[0].id, routes[1].id);
+    }
+
+    #[test]
+    fn version_2_file_keeps_its_references() {
+        let file = json!({
+            "version_number": 2,
+            "data": {
+                "inputs": [
+                    {"port_name": "Keyboard", "use_program_change": true},
+                    {"port_name": "Pads", "use_program_change": false},
+                ],
+                "presets": [
+                    {"id": 0, "name": "Intro", "mapping": {}},
+                    {"id": 1, "name": "Verse", "mapping": {"1": [{"port_name": "Drums"}]}},
+                    {
+                        "id": 2,
+                        "name": "Chorus",
+                        "mapping": {"0": [{"port_name": "Strings"}, {"port_name": "Pad"}]},
+                    },
+                ],
+                "transpose": 0,
+            },
+        });
+        let properties = parse_properties(&file.to_string(), FileFormat::Json).unwrap();
+        let [keyboard, pads] = [0, 1].map(|i| properties.inputs[i].id);
+        assert_ne!(keyboard, pads);
+        assert_eq!(properties.current_preset, 0);
+
+        let ids: HashSet<_> = properties.presets.iter().map(|p| p.id).collect();
+        assert_eq!(ids.len(), 3);
+        let ports = |preset: usize, input: Id| -> Vec<_> {
+            properties
+                .mapping(preset, input)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|r| r.port_name)
+                .collect()
+        };
+        assert!(properties.presets[0].mapping.is_empty());
+        assert_eq!(ports(1, pads), ["Drums"]);
+        assert_eq!(ports(2, keyboard), ["Strings", "Pad"]);
+        assert!(ports(2, pads).is_empty());
+    }
+
+    #[test]
+    fn cc_map_channels_of_old_files_start_at_one() {
+        let file = json!({
+            "version_number": 2,
+            "data": {
+                "inputs": [
+                    {
+                        "port_name": "Keyboard",
+                        "use_program_change": false,
+                        "cc_map": [
+                            [0, 7, "Ignore"],
+                            [1, 7, "Ignore"],
+                            [16, 7, "Ignore"],
+                            [0, -1, "PassThrough"],
+                        ],
+                    },
+                ],
+                "presets": [],
+            },
+        });
+        let properties = parse_properties(&file.to_string(), FileFormat::Json).unwrap();
+        let channels: Vec<_> = properties.inputs[0]
+            .cc_map
+            .iter()
+            .map(|(channel, _, _)| *channel)
+            .collect();
+        assert_eq!(channels, vec![0, 2, 0]);
+    }
+
+    #[test]
+    fn midi_learn_only_accepts_the_input_of_the_target() {
+        let [keyboard, pads] = [Id::random(), Id::random()];
+        let data = [0xB0, 7, 100];
+        let event = LiveEvent::parse(&data).unwrap();
+
+        let mut midi_learn = MidiLearn::default();
+        midi_learn.start("cc".to_string(), LearnKind::Controller, Some(keyboard));
+        assert!(!midi_learn.learn(pads, &event, &data));
+        assert!(midi_learn.learn(keyboard, &event, &data));
+        assert_eq!(midi_learn.result, Some((keyboard, data.to_vec())));
+
+        // Targets that do not belong to an input learn from all of them
+        midi_learn.start("global".to_string(), LearnKind::Controller, None);
+        assert!(midi_learn.learn(pads, &event, &data));
+    }
+
+    #[test]
+    fn references_follow_reordered_groups_and_presets() {
+        let mut properties = Properties::default();
+        let first = properties.add_group();
+        let second = properties.add_group();
+        properties.add_variant(first);
+        properties.add_variant(second);
+        let variant = properties.presets.last().unwrap().id;
+        properties.pending_preset = Some(variant);
+
+        properties.remove_group(first);
+        assert_eq!(properties.presets.len(), 3);
+        let preset = &properties.presets[properties.preset_index(variant).unwrap()];
+        assert_eq!(preset.group, Some(second));
+        assert_eq!(properties.group(second).unwrap().name, "Group 2");
+
+        // The pending preset moved when the presets were sorted
+        assert!(properties.apply_pending_preset(false, false, false));
+        assert_eq!(properties.presets[properties.current_preset].id, variant);
+    }
+
+    #[test]
+    fn presets_without_ids_get_one() {
+        let file = json!({
+            "version_number": 3,
+            "data": {
+                "inputs": [],
+                "presets": [{"name": "Piano", "mapping": {}}, {"name": "Organ", "mapping": {}}],
+                "groups": [{"name": "Song", "mapping": {}}],
+            },
+        });
+        let properties = parse_properties(&file.to_string(), FileFormat::Json).unwrap();
+        assert_ne!(properties.presets[0].id, properties.presets[1].id);
+        assert_ne!(properties.groups[0].id, Id::default());
+    }
+
+    #[test]
+    fn imported_presets_get_new_route_ids() {
+        let mut properties = Properties::default();
+        let input = properties.inputs[0].id;
+        let mut group = PresetGroup::new("Song".to_string());
+        group
+            .mapping
+            .insert(input, vec![OutputSettings::new("Strings".to_string())]);
+        let group_route = group.mapping[&input][0].id;
+        let mut preset = Preset::new("Verse".to_string());
+        preset.group = Some(group.id);
+        properties.groups.push(group);
+        preset
+            .mapping
+            .insert(input, vec![OutputSettings::new("Piano".to_string())]);
+        preset.overrides.insert(group_route, RouteOverride::Disable);
+        properties.presets.push(preset);
+
+        let file = properties.export_presets(&properties.presets.iter().map(|p| p.id).collect());
+        for _ in 0..2 {
+            let import = PresetImport::new(file.clone(), &properties.inputs, &[]);
+            properties.import_presets(&import);
         }
+
+        let route_ids: Vec<_> = properties
+            .presets
+            .iter()
+            .flat_map(|p| p.mapping.values().flatten())
+            .chain(
+                properties
+                    .groups
+                    .iter()
+                    .flat_map(|g| g.mapping.values().flatten()),
+            )
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(route_ids.len(), 6);
+        assert_eq!(
+            route_ids.iter().collect::<HashSet<_>>().len(),
+            route_ids.len()
+        );
+        let group_ids: HashSet<_> = properties.groups.iter().map(|g| g.id).collect();
+        assert_eq!(group_ids.len(), 3);
+        // The overrides of each copy refer to the routes of its own group
+        properties
+            .presets
+            .iter()
+            .filter(|p| p.name == "Verse")
+            .for_each(|preset| {
+                let group = properties.group(preset.group.unwrap()).unwrap();
+                let routes: Vec<_> = group.mapping[&input].iter().map(|r| r.id).collect();
+                assert_eq!(preset.overrides.keys().collect::<Vec<_>>(), [&routes[0]]);
+            });
     }
 }
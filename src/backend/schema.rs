@@ -0,0 +1,141 @@
+use schemars::{schema_for, JsonSchema, Schema};
+use serde_json::Value;
+
+use crate::backend::properties::Properties;
+use crate::utils::latest_version;
+
+/// Shape of a saved file: the current properties in the `PropertiesVersioned` wrapper
+#[derive(JsonSchema)]
+#[schemars(title = "Live MIDI splitter config")]
+#[allow(dead_code)] // Only used for the schema
+struct ConfigFile {
+    version_number: u64,
+    data: Properties,
+}
+
+/// JSON Schema of the current version of the config file (in JSON, TOML is converted)
+pub fn config_schema() -> Schema {
+    let mut schema = schema_for!(ConfigFile);
+    // Older versions are not described by this schema
+    if let Some(version) = schema.pointer_mut("/properties/version_number") {
+        *version = serde_json::json!({
+            "description": "Version of the settings, older versions are upgraded when loading",
+            "const": latest_version(),
+        });
+    }
+    // Ids that are missing get a random one, which should not end up in the schema
+    if let Some(Value::Object(definitions)) = schema.get_mut("$defs") {
+        definitions
+            .values_mut()
+            .filter_map(|d| d.get_mut("properties").and_then(Value::as_object_mut))
+            .flat_map(|properties| properties.values_mut())
+            .filter(|p| p.get("$ref").and_then(Value::as_str) == Some("#/$defs/Id"))
+            .filter_map(Value::as_object_mut)
+            .for_each(|property| {
+                property.remove("default");
+            });
+    }
+    schema
+}
+
+pub fn config_schema_string() -> String {
+    let value: Value = config_schema().into();
+    serde_json::to_string_pretty(&value).unwrap_or_default() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::common_settings::{CcMapping, ChannelMapping, VelocityCurve};
+    use crate::backend::device_alias::DeviceAlias;
+    use crate::backend::input_settings::{InputOverride, InputSettings};
+    use crate::backend::output_settings::OutputSettings;
+    use crate::backend::preset::{Preset, PresetGroup, RouteOverride};
+    use crate::backend::setlist::SetlistEntry;
+    use crate::utils::serialise_properties;
+
+    const PUBLISHED: &str = include_str!("../../schema/lmsc.schema.json");
+
+    #[test]
+    fn published_schema_is_up_to_date() {
+        let published: Value = serde_json::from_str(PUBLISHED).unwrap();
+        assert!(
+            published == config_schema(),
+            "The settings changed, update the schema with \
+            `live-midi-splitter --schema > schema/lmsc.schema.json`"
+        );
+    }
+
+    #[test]
+    fn saved_file_matches_published_schema() {
+        let mut properties = Properties::default();
+        let input = InputSettings::new("Keyboard".to_string());
+        let input_id = input.id;
+        properties.inputs.push(input);
+
+        // A route for every variant of the maps and velocity curves
+        let curves = [
+            VelocityCurve::Linear,
+            VelocityCurve::Fixed(100),
+            VelocityCurve::Exponential(2.0),
+            VelocityCurve::Logarithmic(2.0),
+            VelocityCurve::SCurve(0.5),
+        ];
+        let routes: Vec<_> = curves
+            .into_iter()
+            .map(|curve| {
+                let mut route = OutputSettings::new("Synth".to_string());
+                route.velocity_curve = curve;
+                route.cc_map = CcMapping::all()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, m)| (0, i as i8, m.clone()))
+                    .collect();
+                route.channel_map = ChannelMapping::all()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, m)| (i as u8, m.clone()))
+                    .collect();
+                route
+            })
+            .collect();
+
+        let mut group = PresetGroup::new("Song".to_string());
+        group.mapping.insert(input_id, routes.clone());
+        let group_id = group.id;
+        properties.groups.push(group);
+
+        let mut preset = Preset::new("Verse".to_string());
+        preset.group = Some(group_id);
+        preset.mapping.insert(input_id, routes.clone());
+        preset
+            .overrides
+            .insert(routes[0].id, RouteOverride::Replace(routes[0].clone()));
+        preset
+            .overrides
+            .insert(routes[1].id, RouteOverride::Disable);
+        preset.input_overrides.insert(
+            input_id,
+            InputOverride {
+                transpose: Some(Default::default()),
+                ..Default::default()
+            },
+        );
+        properties
+            .setlist
+            .push(SetlistEntry::new("Song".to_string(), preset.id));
+        properties.presets.push(preset);
+        properties
+            .aliases
+            .push(DeviceAlias::new("Piano".to_string()));
+
+        let published: Value = serde_json::from_str(PUBLISHED).unwrap();
+        let validator = jsonschema::validator_for(&published).unwrap();
+        let file = serialise_properties(&properties);
+        let errors: Vec<_> = validator
+            .iter_errors(&file)
+            .map(|e| e.to_string())
+            .collect();
+        assert!(errors.is_empty(), "{errors:#?}");
+    }
+}
@@ -0,0 +1,40 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::id::Id;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct SetlistEntry {
+    pub title: String,
+    /// Preset that is selected for this song
+    pub preset: Id,
+    #[serde(default)]
+    pub notes: String,
+}
+
+impl SetlistEntry {
+    pub fn new(title: String, preset: Id) -> Self {
+        Self {
+            title,
+            preset,
+            notes: String::new(),
+        }
+    }
+}
+
+/// Keyboard keys that go to the next or previous song, by their name (i.e. "PageDown")
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct SetlistKeys {
+    pub next: Option<String>,
+    pub previous: Option<String>,
+}
+
+impl Default for SetlistKeys {
+    fn default() -> Self {
+        // Page turner pedals usually send page up / down
+        Self {
+            next: Some("PageDown".to_string()),
+            previous: Some("PageUp".to_string()),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use midly::live::LiveEvent;
+use midly::MidiMessage;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::id::Id;
+use crate::backend::preset::{next_group, next_variant};
+use crate::backend::properties::Properties;
+
+/// Incoming MIDI event that switches presets
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct PresetTrigger {
+    pub event: TriggerEvent,
+    /// 1 - 16, 0 is any channel
+    pub channel: u8,
+    /// Key or controller number
+    pub number: u8,
+    pub action: TriggerAction,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TriggerEvent {
+    #[default]
+    Note,
+    Controller,
+}
+
+impl TriggerEvent {
+    pub fn all() -> &'static [TriggerEvent; 2] {
+        &[TriggerEvent::Note, TriggerEvent::Controller]
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            TriggerEvent::Note => "Note",
+            TriggerEvent::Controller => "CC",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerAction {
+    /// Select this preset
+    Preset(Id),
+    Next,
+    Previous,
+    /// Select the preset at the index of the CC value
+    ByValue,
+    /// CC values 1 - 63 select the next preset, 65 - 127 the previous one
+    Relative,
+    NextVariant,
+    PreviousVariant,
+    NextGroup,
+    PreviousGroup,
+    NextSong,
+    PreviousSong,
+}
+
+impl TriggerAction {
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            TriggerAction::Preset(_) => "Select preset",
+            TriggerAction::Next => "Next preset",
+            TriggerAction::Previous => "Previous preset",
+            TriggerAction::ByValue => "Select preset by CC value",
+            TriggerAction::Relative => "Next / previous by CC value",
+            TriggerAction::NextVariant => "Next variant",
+            TriggerAction::PreviousVariant => "Previous variant",
+            TriggerAction::NextGroup => "Next group",
+            TriggerAction::PreviousGroup => "Previous group",
+            TriggerAction::NextSong => "Next song in setlist",
+            TriggerAction::PreviousSong => "Previous song in setlist",
+        }
+    }
+
+    /// Actions that make sense for this kind of event
+    pub fn options(event: TriggerEvent) -> Vec<TriggerAction> {
+        let mut options = vec![
+            TriggerAction::Preset(Id::default()),
+            TriggerAction::Next,
+            TriggerAction::Previous,
+            TriggerAction::NextVariant,
+            TriggerAction::PreviousVariant,
+            TriggerAction::NextGroup,
+            TriggerAction::PreviousGroup,
+            TriggerAction::NextSong,
+            TriggerAction::PreviousSong,
+        ];
+        if event == TriggerEvent::Controller {
+            options.push(TriggerAction::ByValue);
+            options.push(TriggerAction::Relative);
+        }
+        options
+    }
+
+    pub fn same_kind(&self, other: &TriggerAction) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TriggerResult {
+    /// The event has nothing to do with this trigger
+    Ignore,
+    /// The event belongs to this trigger, but does not switch presets (i.e. a note-off)
+    Consume,
+    Switch(usize),
+    /// Go to this entry of the setlist
+    Setlist(usize),
+}
+
+impl PresetTrigger {
+    /// Check what this event does, given the current state
+    pub fn handle(&self, event: LiveEvent, properties: &Properties) -> TriggerResult {
+        let LiveEvent::Midi { channel, message } = event else {
+            return TriggerResult::Ignore;
+        };
+        if self.channel != 0 && self.channel != channel.as_int() + 1 {
+            return TriggerResult::Ignore;
+        }
+
+        let value = match (self.event, message) {
+            (TriggerEvent::Note, MidiMessage::NoteOn { key, vel })
+                if key.as_int() == self.number =>
+            {
+                if vel == 0 {
+                    return TriggerResult::Consume;
+                }
+                vel.as_int()
+            }
+            (
+                TriggerEvent::Note,
+                MidiMessage::NoteOff { key, .. } | MidiMessage::Aftertouch { key, .. },
+            ) if key.as_int() == self.number => return TriggerResult::Consume,
+            (TriggerEvent::Controller, MidiMessage::Controller { controller, value })
+                if controller.as_int() == self.number =>
+            {
+                value.as_int()
+            }
+            _ => return TriggerResult::Ignore,
+        };
+
+        // Step from a preset that is still waiting for its switch policy, so repeated presses add up
+        let current = properties.target_preset();
+        let presets = &properties.presets;
+        let last = presets.len().saturating_sub(1);
+        let preset = match self.action {
+            TriggerAction::ByValue => Some(value as usize),
+            TriggerAction::Relative => match value {
+                1..=63 => Some((current + 1).min(last)),
+                65..=127 => Some(current.saturating_sub(1)),
+                _ => None,
+            },
+            // Buttons send 0 when they are released
+            _ if self.event == TriggerEvent::Controller && value < 64 => None,
+            TriggerAction::Preset(preset) => presets.iter().position(|p| p.id == preset),
+            TriggerAction::Next => Some((current + 1).min(last)),
+            TriggerAction::Previous => Some(current.saturating_sub(1)),
+            TriggerAction::NextVariant => next_variant(presets, current, true),
+            TriggerAction::PreviousVariant => next_variant(presets, current, false),
+            TriggerAction::NextGroup => next_group(presets, current, true),
+            TriggerAction::PreviousGroup => next_group(presets, current, false),
+            TriggerAction::NextSong | TriggerAction::PreviousSong => {
+                let forward = self.action == TriggerAction::NextSong;
+                return match properties.setlist_step(forward) {
+                    Some(entry) => TriggerResult::Setlist(entry),
+                    None => TriggerResult::Consume,
+                };
+            }
+        };
+
+        match preset {
+            Some(preset) if preset < presets.len() => TriggerResult::Switch(preset),
+            _ => TriggerResult::Consume,
+        }
+    }
+}
+
+impl Default for PresetTrigger {
+    fn default() -> Self {
+        Self {
+            event: TriggerEvent::Note,
+            channel: 0,
+            number: 36,
+            action: TriggerAction::Preset(Id::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::num::{u4, u7};
+
+    use super::*;
+    use crate::backend::preset::{Preset, SwitchPolicy};
+
+    #[test]
+    fn next_steps_from_the_pending_preset() {
+        let mut properties = Properties::default();
+        properties.presets[0].switch_policy = SwitchPolicy::AfterRelease {
+            include_pedal: false,
+        };
+        properties.presets.push(Preset::new_numbered(1));
+        properties.presets.push(Preset::new_numbered(2));
+        let trigger = PresetTrigger {
+            action: TriggerAction::Next,
+            ..PresetTrigger::default()
+        };
+        let press = LiveEvent::Midi {
+            channel: u4::new(0),
+            message: MidiMessage::NoteOn {
+                key: u7::new(36),
+                vel: u7::new(100),
+            },
+        };
+
+        properties.request_preset(1, true, false);
+        assert_eq!(properties.current_preset, 0);
+        assert_eq!(trigger.handle(press, &properties), TriggerResult::Switch(2));
+    }
+}
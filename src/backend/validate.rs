@@ -0,0 +1,263 @@
+use std::collections::HashSet;
+
+use itertools::Itertools;
+
+use crate::backend::activation::MAX_MORPH_TIME;
+use crate::backend::common_settings::{CcMap, CommonSettings};
+use crate::backend::device_alias::resolve_aliases;
+use crate::backend::output_settings::{OutputSettings, ReleasePolicy};
+use crate::backend::preset::RouteOverride;
+use crate::backend::properties::Properties;
+use crate::backend::triggers::TriggerAction;
+use crate::backend::MidiPort;
+
+/// Problems found in a file
+#[derive(Default, Debug)]
+pub struct Report {
+    /// References that do not exist and settings that cannot work
+    pub errors: Vec<String>,
+    /// Things that may work on another machine
+    pub warnings: Vec<String>,
+}
+
+/// Check the structure of the properties. Ports are only checked if they could be listed
+pub fn validate(properties: &Properties, ports: Option<(&[MidiPort], &[MidiPort])>) -> Report {
+    let mut report = Report::default();
+    let inputs: HashSet<_> = properties.inputs.iter().map(|i| i.id).collect();
+    let presets: HashSet<_> = properties.presets.iter().map(|p| p.id).collect();
+
+    // Settings that refer to inputs, presets and groups
+    properties.presets.iter().for_each(|preset| {
+        let name = &preset.name;
+        preset
+            .mapping
+            .keys()
+            .chain(preset.input_overrides.keys())
+            .filter(|id| !inputs.contains(id))
+            .unique()
+            .for_each(|id| {
+                report
+                    .errors
+                    .push(format!("Preset {name} has settings for unknown input {id}"))
+            });
+        match preset.group.map(|g| properties.group(g)) {
+            Some(None) => report.errors.push(format!(
+                "Preset {name} is in group {}, which does not exist",
+                preset.group.unwrap_or_default()
+            )),
+            Some(Some(group)) => {
+                let routes: HashSet<_> = group.mapping.values().flatten().map(|r| r.id).collect();
+                preset
+                    .overrides
+                    .keys()
+                    .filter(|id| !routes.contains(id))
+                    .for_each(|id| {
+                        report
+                            .warnings
+                            .push(format!("Preset {name} overrides unknown route {id}"))
+                    });
+            }
+            None => {}
+        }
+        let morph_time = preset.activation.morph_time;
+        if !(0.0..=MAX_MORPH_TIME).contains(&morph_time) {
+            report.errors.push(format!(
+                "Preset {name} has morph time {morph_time}, which is not between 0 and {MAX_MORPH_TIME} seconds"
+            ));
+        }
+    });
+    properties.groups.iter().for_each(|group| {
+        group
+            .mapping
+            .keys()
+            .filter(|id| !inputs.contains(id))
+            .for_each(|id| {
+                report.errors.push(format!(
+                    "Group {} has routes for unknown input {id}",
+                    group.name
+                ))
+            });
+    });
+    properties.inputs.iter().enumerate().for_each(|(i, input)| {
+        let program_presets = input.program_map.iter().map(|m| m.preset);
+        let trigger_presets = input.triggers.iter().filter_map(|t| match t.action {
+            TriggerAction::Preset(id) => Some(id),
+            _ => None,
+        });
+        program_presets
+            .chain(trigger_presets)
+            .filter(|id| !presets.contains(id))
+            .unique()
+            .for_each(|id| {
+                report
+                    .errors
+                    .push(format!("Input {} selects unknown preset {id}", i + 1))
+            });
+    });
+    properties
+        .setlist
+        .iter()
+        .filter(|entry| !presets.contains(&entry.preset))
+        .for_each(|entry| {
+            report.errors.push(format!(
+                "Song {} in the setlist selects unknown preset {}",
+                entry.title, entry.preset
+            ))
+        });
+
+    // Device aliases that can never match
+    properties.aliases.iter().for_each(|alias| {
+        alias.patterns.iter().for_each(|pattern| {
+            if let Some(error) = pattern.error() {
+                report.errors.push(format!(
+                    "Device alias {} has invalid port name pattern {}: {error}",
+                    alias.name,
+                    pattern.source()
+                ))
+            }
+        });
+    });
+
+    // CC maps with more than one entry for the same controller
+    let mut check_cc_map = |cc_map: &CcMap, location: String| {
+        cc_map
+            .iter()
+            .map(|(channel, cc, _)| (channel, cc))
+            .duplicates()
+            .for_each(|(channel, cc)| {
+                let channel = match channel {
+                    0 => "any channel".to_string(),
+                    c => format!("channel {c}"),
+                };
+                let cc = match cc {
+                    -1 => "any CC".to_string(),
+                    c => format!("CC {c}"),
+                };
+                report.errors.push(format!(
+                    "{location} has more than one CC map entry for {cc} on {channel}"
+                ))
+            });
+    };
+    check_cc_map(
+        properties.global_settings.cc_map(),
+        "Global settings".to_string(),
+    );
+    properties.inputs.iter().enumerate().for_each(|(i, input)| {
+        check_cc_map(input.cc_map(), format!("Input {}", i + 1));
+    });
+    properties.presets.iter().for_each(|preset| {
+        let overrides = preset.overrides.values().filter_map(|o| match o {
+            RouteOverride::Replace(route) => Some(route),
+            _ => None,
+        });
+        preset
+            .mapping
+            .values()
+            .flatten()
+            .chain(overrides)
+            .for_each(|route| {
+                check_cc_map(
+                    route.cc_map(),
+                    format!("Route to {} in preset {}", route.port_name, preset.name),
+                );
+            });
+        preset
+            .input_overrides
+            .values()
+            .filter_map(|o| o.cc_map.as_ref())
+            .for_each(|cc_map| {
+                check_cc_map(cc_map, format!("Input settings of preset {}", preset.name));
+            });
+    });
+    properties.groups.iter().for_each(|group| {
+        group.mapping.values().flatten().for_each(|route| {
+            check_cc_map(
+                route.cc_map(),
+                format!("Route to {} in group {}", route.port_name, group.name),
+            );
+        });
+    });
+
+    // Fades that are longer than can be scheduled
+    let mut check_fade_time = |route: &OutputSettings, location: String| {
+        if let ReleasePolicy::FadeOut { time } = route.release {
+            if !(0.0..=MAX_MORPH_TIME).contains(&time) {
+                report.errors.push(format!(
+                    "{location} fades out in {time}, which is not between 0 and {MAX_MORPH_TIME} seconds"
+                ));
+            }
+        }
+    };
+    properties.presets.iter().for_each(|preset| {
+        let overrides = preset.overrides.values().filter_map(|o| match o {
+            RouteOverride::Replace(route) => Some(route),
+            _ => None,
+        });
+        preset
+            .mapping
+            .values()
+            .flatten()
+            .chain(overrides)
+            .for_each(|route| {
+                check_fade_time(
+                    route,
+                    format!("Route to {} in preset {}", route.port_name, preset.name),
+                );
+            });
+    });
+    properties.groups.iter().for_each(|group| {
+        group.mapping.values().flatten().for_each(|route| {
+            check_fade_time(
+                route,
+                format!("Route to {} in group {}", route.port_name, group.name),
+            );
+        });
+    });
+
+    // Ports that are not available on this machine
+    if let Some((available_inputs, available_outputs)) = ports {
+        let known = |available: &[MidiPort]| -> HashSet<String> {
+            available
+                .iter()
+                .map(|p| p.readable.clone())
+                .chain(
+                    resolve_aliases(&properties.aliases, available)
+                        .into_iter()
+                        .map(|(name, _)| name),
+                )
+                .collect()
+        };
+        let known_inputs = known(available_inputs);
+        let known_outputs = known(available_outputs);
+
+        properties
+            .inputs
+            .iter()
+            .map(|i| &i.port_name)
+            .filter(|port| !port.is_empty() && !known_inputs.contains(*port))
+            .unique()
+            .for_each(|port| {
+                report
+                    .warnings
+                    .push(format!("Input port {port} is not available"))
+            });
+        let mut properties = properties.clone();
+        let feedback = &mut properties.feedback_output;
+        properties
+            .presets
+            .iter_mut()
+            .flat_map(|p| p.ports_mut())
+            .chain(properties.groups.iter_mut().flat_map(|g| g.ports_mut()))
+            .chain([feedback])
+            .map(|port| port.clone())
+            .filter(|port| !port.is_empty() && !known_outputs.contains(port))
+            .unique()
+            .for_each(|port| {
+                report
+                    .warnings
+                    .push(format!("Output port {port} is not available"))
+            });
+    }
+
+    report
+}
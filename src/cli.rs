@@ -0,0 +1,379 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::Value;
+
+use crate::backend::available_ports;
+use crate::backend::file_format::FileFormat;
+use crate::backend::output_settings::OutputSettings;
+use crate::backend::properties::Properties;
+use crate::backend::validate::validate;
+use crate::utils::{midi_to_note, parse_properties, serialise_properties};
+
+/// Validation found problems
+const EXIT_PROBLEMS: u8 = 1;
+/// The file could not be read or is not a valid config
+const EXIT_READ_ERROR: u8 = 3;
+/// The result could not be written
+const EXIT_WRITE_ERROR: u8 = 4;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Split and map MIDI from your inputs to multiple outputs",
+    args_conflicts_with_subcommands = true,
+    after_help = "Exit codes: 0 success, 1 problems found, 2 invalid arguments, \
+        3 could not read the file, 4 could not write the file"
+)]
+pub struct Cli {
+    /// Config file to open in the window
+    pub file: Option<PathBuf>,
+    /// Print the JSON Schema of the config file format
+    #[arg(long, conflicts_with = "file")]
+    pub schema: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Check a config for references that do not exist, duplicate CC entries and unknown ports
+    Validate {
+        file: PathBuf,
+        /// Do not check if the ports are available on this machine
+        #[arg(long)]
+        no_ports: bool,
+    },
+    /// Upgrade a config from an older version to the current one
+    Migrate {
+        file: PathBuf,
+        /// Write the result here instead of replacing the file
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print the inputs, presets and their routes
+    Dump { file: PathBuf },
+    /// Save a config in another file format
+    Convert {
+        file: PathBuf,
+        output: PathBuf,
+        /// Format of the output, by default chosen by its extension, or else JSON
+        #[arg(short, long)]
+        format: Option<Format>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+impl From<Format> for FileFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Json => FileFormat::Json,
+            Format::Toml => FileFormat::Toml,
+        }
+    }
+}
+
+pub fn run(command: Command) -> ExitCode {
+    match execute(command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => ExitCode::from(code),
+    }
+}
+
+fn execute(command: Command) -> Result<(), u8> {
+    match command {
+        Command::Validate { file, no_ports } => read(&file).and_then(|properties| {
+            let ports = if no_ports { None } else { available_ports() };
+            if ports.is_none() && !no_ports {
+                eprintln!("Could not list the MIDI ports, skipping the port checks");
+            }
+            let report = validate(
+                &properties,
+                ports.as_ref().map(|(i, o)| (i.as_slice(), o.as_slice())),
+            );
+            report.errors.iter().for_each(|e| println!("error: {e}"));
+            report
+                .warnings
+                .iter()
+                .for_each(|w| println!("warning: {w}"));
+            if report.errors.is_empty() {
+                println!("{} is valid", file.display());
+                Ok(())
+            } else {
+                Err(EXIT_PROBLEMS)
+            }
+        }),
+        Command::Migrate { file, output } => read(&file).and_then(|properties| {
+            let old_version = fs::read_to_string(&file)
+                .ok()
+                .and_then(|c| FileFormat::of_file(&file, &c).parse(&c).ok())
+                .and_then(|v| v.get("version_number").and_then(Value::as_u64));
+            let new_version = version(&properties);
+            let output = output.unwrap_or(file);
+            write(&properties, &output, properties.file_format)?;
+            match old_version {
+                Some(old) if old == new_version => println!("Already at version {new_version}"),
+                Some(old) => println!("Migrated from version {old} to {new_version}"),
+                None => println!("Migrated from an unversioned config to version {new_version}"),
+            }
+            Ok(())
+        }),
+        Command::Dump { file } => read(&file).map(|properties| dump(&properties)),
+        Command::Convert {
+            file,
+            output,
+            format,
+        } => read(&file).and_then(|properties| {
+            // Configs (.lmsc) are JSON by default
+            let format = format
+                .map(FileFormat::from)
+                .or(FileFormat::from_path(&output))
+                .unwrap_or_default();
+            write(&properties, &output, format)
+        }),
+    }
+}
+
+fn read(file: &PathBuf) -> Result<Properties, u8> {
+    let contents = fs::read_to_string(file).map_err(|e| {
+        eprintln!("Could not read {}: {e}", file.display());
+        EXIT_READ_ERROR
+    })?;
+    parse_properties(&contents, FileFormat::of_file(file, &contents)).map_err(|e| {
+        eprintln!("Could not load {}: {e}", file.display());
+        EXIT_READ_ERROR
+    })
+}
+
+fn write(properties: &Properties, file: &PathBuf, format: FileFormat) -> Result<(), u8> {
+    format
+        .write(&serialise_properties(properties))
+        .and_then(|contents| fs::write(file, contents).map_err(|e| e.to_string()))
+        .map_err(|e| {
+            eprintln!("Could not write {}: {e}", file.display());
+            EXIT_WRITE_ERROR
+        })
+}
+
+fn version(properties: &Properties) -> u64 {
+    serialise_properties(properties)
+        .get("version_number")
+        .and_then(Value::as_u64)
+        .unwrap_or_default()
+}
+
+fn dump(properties: &Properties) {
+    let input_name = |i: usize| {
+        let port = &properties.inputs[i].port_name;
+        if port.is_empty() {
+            format!("Input {}", i + 1)
+        } else {
+            format!("Input {} ({port})", i + 1)
+        }
+    };
+
+    println!("Inputs:");
+    properties.inputs.iter().enumerate().for_each(|(i, input)| {
+        let mut details = vec![];
+        if input.use_program_change {
+            details.push("program change".to_string());
+        }
+        if !input.triggers.is_empty() {
+            details.push(format!("{} triggers", input.triggers.len()));
+        }
+        println!("  {}{}", input_name(i), join_details(&details));
+    });
+
+    println!("Presets:");
+    properties
+        .presets
+        .iter()
+        .enumerate()
+        .for_each(|(p, preset)| {
+            let group = preset
+                .group
+                .and_then(|g| properties.group(g))
+                .map(|g| format!(" [{}]", g.name))
+                .unwrap_or_default();
+            println!("  {}. {}{group}", p + 1, preset.name);
+            properties.inputs.iter().enumerate().for_each(|(i, input)| {
+                let routes = properties.mapping(p, input.id).unwrap_or_default();
+                if routes.is_empty() {
+                    return;
+                }
+                println!("    {}", input_name(i));
+                routes.iter().for_each(|route| {
+                    println!("      -> {}", describe_route(route));
+                });
+            });
+        });
+
+    if !properties.setlist.is_empty() {
+        println!("Setlist:");
+        properties
+            .setlist
+            .iter()
+            .enumerate()
+            .for_each(|(i, entry)| {
+                let preset = properties
+                    .preset_index(entry.preset)
+                    .map(|p| properties.presets[p].name.as_str())
+                    .unwrap_or("unknown preset");
+                println!("  {}. {} ({preset})", i + 1, entry.title);
+            });
+    }
+}
+
+fn describe_route(route: &OutputSettings) -> String {
+    let mut details = vec![];
+    if route.key_filter_enabled {
+        let (low, high) = route.key_filter;
+        details.push(format!(
+            "keys {}-{}",
+            midi_to_note(low),
+            midi_to_note(high.min(127))
+        ));
+    }
+    if route.transpose.value != 0 {
+        details.push(format!("transpose {:+}", route.transpose.value));
+    }
+    if route.ignore_global_settings {
+        details.push("ignores global settings".to_string());
+    }
+    let port = if route.port_name.is_empty() {
+        "(no output)"
+    } else {
+        &route.port_name
+    };
+    format!("{port}{}", join_details(&details))
+}
+
+fn join_details(details: &[String]) -> String {
+    if details.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", details.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::backend::device_alias::{DeviceAlias, PortPattern};
+    use crate::backend::id::Id;
+    use crate::backend::preset::Preset;
+    use crate::backend::setlist::SetlistEntry;
+
+    /// File in the temporary directory, removed when the test is done
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("live-midi-splitter-cli-{name}"));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn config(properties: &Properties) -> String {
+        serialise_properties(properties).to_string()
+    }
+
+    fn validate(file: &Path) -> Result<(), u8> {
+        execute(Command::Validate {
+            file: file.to_path_buf(),
+            no_ports: true,
+        })
+    }
+
+    #[test]
+    fn validate_exit_codes() {
+        let valid = TempFile::new("valid.lmsc", &config(&Properties::default()));
+        assert_eq!(validate(&valid.0), Ok(()));
+
+        let mut properties = Properties::default();
+        properties
+            .setlist
+            .push(SetlistEntry::new("Song".to_string(), Id::random()));
+        let invalid = TempFile::new("invalid.lmsc", &config(&properties));
+        assert_eq!(validate(&invalid.0), Err(EXIT_PROBLEMS));
+
+        let mut properties = Properties::default();
+        let mut preset = Preset::new("Morph".to_string());
+        preset.activation.morph_time = -1.0;
+        properties.presets.push(preset);
+        let negative_morph = TempFile::new("morph.lmsc", &config(&properties));
+        assert_eq!(validate(&negative_morph.0), Err(EXIT_PROBLEMS));
+
+        let mut properties = Properties::default();
+        let mut alias = DeviceAlias::new("Piano".to_string());
+        alias.patterns = vec![PortPattern::new("Piano (".to_string())];
+        properties.aliases.push(alias);
+        let invalid_alias = TempFile::new("alias.lmsc", &config(&properties));
+        assert_eq!(validate(&invalid_alias.0), Err(EXIT_PROBLEMS));
+
+        let syntax_error = TempFile::new("syntax.lmsc", "{\"version_number\": 3,");
+        assert_eq!(validate(&syntax_error.0), Err(EXIT_READ_ERROR));
+        let missing = std::env::temp_dir().join("live-midi-splitter-cli-missing.lmsc");
+        assert_eq!(validate(&missing), Err(EXIT_READ_ERROR));
+    }
+
+    #[test]
+    fn convert_exit_codes() {
+        let mut properties = Properties::default();
+        properties.presets.push(Preset::new("Piano".to_string()));
+        let input = TempFile::new(
+            "convert.toml",
+            &FileFormat::Toml
+                .write(&serialise_properties(&properties))
+                .unwrap(),
+        );
+        let output = TempFile::new("convert.lmsc", "");
+
+        let convert = |output: &Path| {
+            execute(Command::Convert {
+                file: input.0.clone(),
+                output: output.to_path_buf(),
+                format: None,
+            })
+        };
+        assert_eq!(convert(&output.0), Ok(()));
+        let contents = fs::read_to_string(&output.0).unwrap();
+        assert_eq!(FileFormat::detect(&contents), FileFormat::Json);
+        assert_eq!(
+            parse_properties(&contents, FileFormat::Json)
+                .unwrap()
+                .presets
+                .len(),
+            2
+        );
+
+        let unwritable = std::env::temp_dir()
+            .join("live-midi-splitter-cli-missing")
+            .join("out.lmsc");
+        assert_eq!(convert(&unwritable), Err(EXIT_WRITE_ERROR));
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let error = Cli::try_parse_from(["live-midi-splitter", "convert", "in.lmsc"])
+            .err()
+            .unwrap();
+        assert_eq!(error.exit_code(), 2);
+    }
+}
@@ -2,24 +2,34 @@ use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+use crate::backend::autosave::{finish, recovery};
 use crate::backend::background_functions::run_background_functions;
+use crate::backend::id::Id;
 use crate::backend::preset::Preset;
 use crate::backend::properties::Properties;
 use crate::backend::Backend;
 use crate::gui::data::RecentFiles;
-use crate::gui::keybinds::Keybinds;
+use crate::gui::keybinds::{key_shortcut, Keybinds};
 use crate::gui::state::{State, TabState};
+use crate::gui::tabs::group::group_tab;
+use crate::gui::tabs::history::history_tab;
 use crate::gui::tabs::input_settings::input_settings;
 use crate::gui::tabs::preset::preset_tab;
+use crate::gui::tabs::preset_transfer::preset_transfer_tab;
 use crate::gui::tabs::quick_start::quick_start;
 use crate::gui::tabs::recent_files::recent_files;
+use crate::gui::tabs::setlist::setlist_tab;
 use crate::gui::tabs::Tab;
+use crate::gui::widgets::file_error::{file_error_dialog, FileError};
+use crate::gui::widgets::history::history_buttons;
+use crate::gui::widgets::recovery::recovery_prompt;
 use crate::gui::widgets::save_load::{gui_load, gui_save, gui_save_as, save_load};
+use crate::gui::widgets::setlist::setlist_navigation;
 use crate::gui::widgets::transpose::transpose;
-use crate::utils::{load, shorten_str};
+use crate::utils::{load, reload, shorten_str};
 use eframe::Frame;
 use egui::panel::{Side, TopBottomSide};
-use egui::{Context, ViewportCommand};
+use egui::{Color32, Context, RichText, ViewportCommand};
 use egui_dnd::dnd;
 use egui_keybind::Bind;
 
@@ -49,6 +59,8 @@ impl Default for Gui {
         let properties = backend.properties();
         let state = backend.state();
         let ctx_reference = backend.gui_ctx();
+        // Check for unsaved changes of the last session before we start autosaving
+        state.lock().unwrap().recovery = recovery();
 
         let _ = thread::spawn(move || backend.run());
         let bg_state = Arc::clone(&state);
@@ -78,16 +90,21 @@ impl Default for Gui {
 }
 
 impl Gui {
-    pub fn with_preset(path: &String) -> Self {
+    pub fn with_preset(path: PathBuf) -> Self {
         let gui = Gui::default();
-        let path = PathBuf::from(path);
-        load(
+        match load(
             &path,
             Arc::clone(&gui.properties),
             Arc::clone(&gui.current_tab),
-        );
-        gui.state.lock().unwrap().set_file_path(path.clone());
-        gui.recent_files.lock().unwrap().add(path);
+        ) {
+            Ok(()) => {
+                gui.state.lock().unwrap().set_file_path(path.clone());
+                gui.recent_files.lock().unwrap().add(path);
+            }
+            Err(e) => {
+                gui.state.lock().unwrap().file_error = Some(FileError::load(&path, &e));
+            }
+        }
 
         gui
     }
@@ -105,6 +122,8 @@ impl eframe::App for Gui {
         let mut change_preset_to = None;
         let mut delete_preset = None;
         let mut duplicate_preset = None;
+        let mut move_preset = None;
+        let mut group_action = None;
 
         let file_path = self.state.lock().unwrap().file_path().clone();
 
@@ -135,7 +154,12 @@ impl eframe::App for Gui {
         }
         if ctx.input_mut(|i| self.keybinds.save.pressed(i)) {
             if let Some(filename) = &file_path {
-                gui_save(filename.clone(), &self.properties, &self.loading);
+                gui_save(
+                    filename.clone(),
+                    &self.properties,
+                    &self.loading,
+                    &self.state,
+                );
             } else {
                 gui_save_as(
                     &self.properties,
@@ -146,6 +170,19 @@ impl eframe::App for Gui {
             }
         }
 
+        // Check redo first, since the undo shortcut also matches with shift pressed.
+        // Text fields with focus have their own undo.
+        if !ctx.wants_keyboard_input() {
+            if ctx.input_mut(|i| self.keybinds.redo.pressed(i)) {
+                let mut properties = self.properties.lock().unwrap();
+                self.state.lock().unwrap().history.redo(&mut properties);
+            }
+            if ctx.input_mut(|i| self.keybinds.undo.pressed(i)) {
+                let mut properties = self.properties.lock().unwrap();
+                self.state.lock().unwrap().history.undo(&mut properties);
+            }
+        }
+
         {
             // Update title bar (if title changed)
             let mut state = self.state.lock().unwrap();
@@ -159,6 +196,9 @@ impl eframe::App for Gui {
             }
         }
 
+        recovery_prompt(ctx, &self.properties, &self.state, &self.current_tab);
+        file_error_dialog(ctx, &self.state, &self.recent_files);
+
         // Draw UI
         egui::TopBottomPanel::new(TopBottomSide::Top, "header").show(ctx, |ui| {
             egui::Grid::new("header-grid").show(ui, |ui| {
@@ -177,9 +217,89 @@ impl eframe::App for Gui {
                     &self.keybinds,
                 );
                 let mut properties = self.properties.lock().unwrap();
+                history_buttons(
+                    ui,
+                    &mut properties,
+                    &mut self.state.lock().unwrap(),
+                    &self.keybinds,
+                );
                 transpose(ui, &mut properties.transpose);
+                setlist_navigation(ui, &mut properties);
                 ui.end_row();
             });
+
+            // Show the preset that we will switch to once the notes are released
+            {
+                let mut properties = self.properties.lock().unwrap();
+                let pending = properties
+                    .pending_preset
+                    .and_then(|id| properties.preset_index(id))
+                    .map(|p| properties.presets[p].name.clone());
+                if let Some(name) = pending {
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{} Waiting to switch to {name}",
+                            egui_phosphor::regular::HOURGLASS
+                        ));
+                        if ui.small_button("Switch now").clicked() {
+                            properties.current_preset = properties.target_preset();
+                            properties.pending_preset = None;
+                        }
+                        if ui.small_button("X").clicked() {
+                            properties.pending_preset = None;
+                        }
+                    });
+                }
+            }
+
+            // Show program changes that did not match any preset
+            let mut state = self.state.lock().unwrap();
+            if let Some(warning) = state.program_change_warning.clone() {
+                ui.horizontal(|ui| {
+                    ui.label(
+                        RichText::new(format!("{} {warning}", egui_phosphor::regular::WARNING))
+                            .color(Color32::RED),
+                    );
+                    if ui.small_button("X").clicked() {
+                        state.program_change_warning = None;
+                    }
+                });
+            }
+
+            // Ask what to do with the open file when it was changed by another program
+            let mut reload_file = None;
+            if state.file_changed {
+                ui.horizontal(|ui| {
+                    ui.label(
+                        RichText::new(format!(
+                            "{} The file was changed on disk",
+                            egui_phosphor::regular::WARNING
+                        ))
+                        .color(Color32::RED),
+                    );
+                    if ui
+                        .small_button("Reload")
+                        .on_hover_text("Discards your unsaved changes")
+                        .clicked()
+                    {
+                        reload_file = state.file_path().clone();
+                        state.file_changed = false;
+                    }
+                    if ui
+                        .small_button("X")
+                        .on_hover_text("Keep your changes")
+                        .clicked()
+                    {
+                        state.file_changed = false;
+                    }
+                });
+            }
+            drop(state);
+            if let Some(path) = reload_file {
+                if let Err(e) = reload(&path, &self.properties) {
+                    self.state.lock().unwrap().file_error = Some(FileError::load(&path, &e));
+                }
+            }
         });
 
         egui::SidePanel::new(Side::Left, "sidebar")
@@ -191,65 +311,114 @@ impl eframe::App for Gui {
                     ui.selectable_value(&mut *current_tab, Tab::RecentFiles, "Recent files");
                     ui.selectable_value(&mut *current_tab, Tab::InputSettings, "Input settings");
                     ui.selectable_value(&mut *current_tab, Tab::QuickStart, "Quick start");
+                    ui.selectable_value(&mut *current_tab, Tab::Setlist, "Setlist");
+                    ui.selectable_value(&mut *current_tab, Tab::History, "Edit history");
+                    ui.selectable_value(&mut *current_tab, Tab::PresetTransfer, "Import / export");
                     ui.separator();
                     ui.label("Presets:");
 
                     let mut properties = self.properties.lock().unwrap();
                     let current_preset = properties.current_preset;
+                    let groups: Vec<_> = properties
+                        .groups
+                        .iter()
+                        .map(|g| (g.id, g.name.clone()))
+                        .collect();
+                    let preset_groups: Vec<_> =
+                        properties.presets.iter().map(|p| p.group).collect();
                     let presets = &mut properties.presets;
                     let drag_response =
-                        dnd(ui, "presets").show(presets.iter(), |ui, preset, handle, _| {
-                            handle.ui(ui, |ui| {
-                                let tab = Tab::Preset(preset.id);
-                                let button = ui.selectable_label(
-                                    *current_tab == tab || current_preset == preset.id,
-                                    preset.name.clone(),
-                                );
-                                if button.clicked() {
-                                    *current_tab = tab;
-                                    // Besides changing the current tab, also change the preset
-                                    change_preset_to = Some(preset.id);
+                        dnd(ui, "presets").show(presets.iter(), |ui, preset, handle, item| {
+                            ui.vertical(|ui| {
+                                // Show the group above its first variant
+                                let group = preset.group.and_then(|g| {
+                                    groups.iter().find(|(id, _)| *id == g).map(|(_, n)| (g, n))
+                                });
+                                let first_variant = item.index == 0
+                                    || preset_groups[item.index - 1] != preset.group;
+                                if let Some((group, name)) = group.filter(|_| first_variant) {
+                                    group_label(
+                                        ui,
+                                        &mut current_tab,
+                                        group,
+                                        name,
+                                        &mut group_action,
+                                    );
                                 }
-                                button.context_menu(|ui| {
-                                    if ui.button("Duplicate").clicked() {
-                                        duplicate_preset = Some(preset.id);
-                                        ui.close_menu();
-                                    }
-                                    if ui.button("Delete").clicked() {
-                                        delete_preset = Some(preset.id);
-                                        ui.close_menu();
-                                    }
+                                handle.ui(ui, |ui| {
+                                    ui.horizontal(|ui| {
+                                        if group.is_some() {
+                                            ui.add_space(10.0);
+                                        }
+                                        let index = item.index;
+                                        let tab = Tab::Preset(preset.id);
+                                        let button = ui.selectable_label(
+                                            *current_tab == tab || current_preset == index,
+                                            preset.name.clone(),
+                                        );
+                                        if button.clicked() {
+                                            *current_tab = tab;
+                                            // Besides changing the current tab, also change the preset
+                                            change_preset_to = Some(index);
+                                        }
+                                        button.context_menu(|ui| {
+                                            if ui.button("Duplicate").clicked() {
+                                                duplicate_preset = Some(index);
+                                                ui.close_menu();
+                                            }
+                                            if ui.button("Delete").clicked() {
+                                                delete_preset = Some(index);
+                                                ui.close_menu();
+                                            }
+                                            ui.menu_button("Move to group", |ui| {
+                                                if ui.button("No group").clicked() {
+                                                    move_preset = Some((index, None));
+                                                    ui.close_menu();
+                                                }
+                                                groups.iter().for_each(|(g, name)| {
+                                                    if ui.button(name).clicked() {
+                                                        move_preset = Some((index, Some(*g)));
+                                                        ui.close_menu();
+                                                    }
+                                                });
+                                            });
+                                        });
+                                    });
                                 });
                             });
                         });
 
-                    if let Some(update) = drag_response.final_update() {
-                        // Update the current preset accordingly
-                        if current_preset == update.from {
-                            let new_index = if update.to > update.from {
-                                update.to - 1
-                            } else {
-                                update.to
-                            };
-                            change_preset_to = Some(new_index)
-                        } else if current_preset > update.from && current_preset < update.to {
-                            change_preset_to = Some(current_preset - 1)
-                        } else {
-                            change_preset_to = Some(current_preset + 1)
-                        }
-                        // Change tab already (otherwise you see the previous preset at this index for one frame)
-                        if let Some(id) = change_preset_to {
-                            *current_tab = Tab::Preset(id);
-                        }
-                        // Update the vector of presets
+                    if drag_response.final_update().is_some() {
+                        // Update the vector of presets, keeping the same current preset
+                        let current = presets.get(current_preset).map(|p| p.id);
                         drag_response.update_vec(presets);
-                        // Update "internal" ids to match position in list
-                        presets.iter_mut().enumerate().for_each(|(i, p)| p.id = i);
+                        if let Some(current) = current.and_then(|id| properties.preset_index(id)) {
+                            properties.current_preset = current;
+                        }
+                        // Keep groups together and update the current preset accordingly
+                        properties.sort_presets();
+                        change_preset_to = Some(properties.current_preset);
                     }
 
-                    if ui.button("Add preset").clicked() {
-                        presets.push(Preset::new_from_id(presets.len()));
-                    }
+                    // Groups without variants are not shown in the list above
+                    groups
+                        .iter()
+                        .filter(|(g, _)| !preset_groups.contains(&Some(*g)))
+                        .for_each(|(g, name)| {
+                            group_label(ui, &mut current_tab, *g, name, &mut group_action);
+                        });
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Add preset").clicked() {
+                            let index = properties.presets.len();
+                            properties.presets.push(Preset::new_numbered(index));
+                            properties.sort_presets();
+                        }
+                        if ui.button("Add group").clicked() {
+                            let group = properties.add_group();
+                            *current_tab = Tab::Group(group);
+                        }
+                    });
                 });
             });
 
@@ -285,14 +454,42 @@ impl eframe::App for Gui {
                             &mut self.tab_state,
                         );
                     }
+                    Tab::Setlist => {
+                        setlist_tab(ui, Arc::clone(&self.properties));
+                    }
+                    Tab::History => {
+                        history_tab(ui, Arc::clone(&self.properties), Arc::clone(&self.state));
+                    }
+                    Tab::PresetTransfer => {
+                        preset_transfer_tab(
+                            ui,
+                            &self.properties,
+                            &self.state,
+                            &self.loading,
+                            &mut self.tab_state,
+                        );
+                    }
+                    Tab::Group(id) => {
+                        if self.properties.lock().unwrap().group(id).is_none() {
+                            *current_tab = Tab::InputSettings;
+                            return;
+                        }
+                        group_tab(
+                            ui,
+                            Arc::clone(&self.properties),
+                            Arc::clone(&self.state),
+                            id,
+                            &mut self.tab_state,
+                        )
+                    }
                     Tab::Preset(id) => {
                         // Handle preset change by backend
                         {
                             let properties = self.properties.lock().unwrap();
-                            let current_preset = properties.current_preset;
-                            if current_preset != id {
-                                if current_preset < properties.presets.len() {
-                                    *current_tab = Tab::Preset(current_preset)
+                            let current_preset = properties.presets.get(properties.current_preset);
+                            if current_preset.map(|p| p.id) != Some(id) {
+                                if let Some(preset) = current_preset {
+                                    *current_tab = Tab::Preset(preset.id)
                                 } else {
                                     *current_tab = Tab::InputSettings
                                 }
@@ -312,9 +509,24 @@ impl eframe::App for Gui {
             });
         });
 
+        // Handled after the UI, so a key that was just set in the setlist tab is not used as well.
+        // Keys like page up / down are left to text fields that have focus.
+        if !ctx.wants_keyboard_input() {
+            let mut properties = self.properties.lock().unwrap();
+            let keys = properties.setlist_keys.clone();
+            for (key, forward) in [(keys.next, true), (keys.previous, false)] {
+                if ctx.input_mut(|i| key_shortcut(&key).pressed(i)) {
+                    if let Some(entry) = properties.setlist_step(forward) {
+                        properties.select_setlist_entry(entry);
+                    }
+                }
+            }
+        }
+
         let mut properties = self.properties.lock().unwrap();
         if let Some(new_preset) = change_preset_to {
             properties.current_preset = new_preset;
+            properties.pending_preset = None;
         }
         if let Some(id) = delete_preset {
             properties.remove_preset(id);
@@ -322,5 +534,58 @@ impl eframe::App for Gui {
         if let Some(id) = duplicate_preset {
             properties.duplicate_preset(id);
         }
+        if let Some((id, group)) = move_preset {
+            properties.set_group(id, group);
+        }
+        match group_action {
+            Some(GroupAction::AddVariant(group)) => properties.add_variant(group),
+            Some(GroupAction::Delete(group)) => {
+                properties.remove_group(group);
+                let mut current_tab = self.current_tab.lock().unwrap();
+                if matches!(*current_tab, Tab::Group(_)) {
+                    *current_tab = Tab::InputSettings;
+                }
+            }
+            None => {}
+        }
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // Unsaved changes are kept with the other snapshots, recovery is only offered after a crash
+        let properties = self.properties.lock().unwrap();
+        let file_path = self.state.lock().unwrap().file_path().clone();
+        finish(&properties, file_path);
+    }
+}
+
+enum GroupAction {
+    AddVariant(Id),
+    Delete(Id),
+}
+
+fn group_label(
+    ui: &mut egui::Ui,
+    current_tab: &mut Tab,
+    group: Id,
+    name: &str,
+    group_action: &mut Option<GroupAction>,
+) {
+    let tab = Tab::Group(group);
+    let button = ui.selectable_label(
+        *current_tab == tab,
+        format!("{} {name}", egui_phosphor::regular::FOLDER_OPEN),
+    );
+    if button.clicked() {
+        *current_tab = tab;
+    }
+    button.context_menu(|ui| {
+        if ui.button("Add variant").clicked() {
+            *group_action = Some(GroupAction::AddVariant(group));
+            ui.close_menu();
+        }
+        if ui.button("Delete").clicked() {
+            *group_action = Some(GroupAction::Delete(group));
+            ui.close_menu();
+        }
+    });
 }
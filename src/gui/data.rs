@@ -4,7 +4,7 @@ use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
 
-const NAME: Option<&str> = option_env!("CARGO_PKG_NAME");
+use crate::utils::config_dir;
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct RecentFiles {
@@ -26,8 +26,7 @@ impl RecentFiles {
     }
 
     pub fn load() -> Option<Self> {
-        if let Some(mut location) = dirs::config_dir() {
-            location.push(NAME.unwrap_or("live-midi-splitter"));
+        if let Some(mut location) = config_dir() {
             location.push("recent_files.json");
             if let Ok(file) = File::open(location) {
                 let reader = BufReader::new(file);
@@ -40,8 +39,7 @@ impl RecentFiles {
     }
 
     fn save(&self) {
-        if let Some(mut location) = dirs::config_dir() {
-            location.push(NAME.unwrap_or("live-midi-splitter"));
+        if let Some(mut location) = config_dir() {
             // Create path if not exist
             if fs::create_dir_all(&location).is_ok() {
                 location.push("recent_files.json");
@@ -1,10 +1,12 @@
 use egui::{Button, Key, KeyboardShortcut, ModifierNames, Modifiers, Response, Ui, WidgetText};
-use egui_keybind::{Bind, Shortcut};
+use egui_keybind::{Bind, Keybind, Shortcut};
 
 pub struct Keybinds {
     pub load: Shortcut,
     pub save: Shortcut,
     pub save_as: Shortcut,
+    pub undo: Shortcut,
+    pub redo: Shortcut,
 }
 
 impl Default for Keybinds {
@@ -13,6 +15,8 @@ impl Default for Keybinds {
             load: keyboard_shortcut(Modifiers::CTRL, Key::O),
             save: keyboard_shortcut(Modifiers::CTRL, Key::S),
             save_as: keyboard_shortcut(Modifiers::CTRL | Modifiers::SHIFT, Key::S),
+            undo: keyboard_shortcut(Modifiers::CTRL, Key::Z),
+            redo: keyboard_shortcut(Modifiers::CTRL | Modifiers::SHIFT, Key::Z),
         }
     }
 }
@@ -21,6 +25,12 @@ fn keyboard_shortcut(modifiers: Modifiers, logical_key: Key) -> Shortcut {
     Shortcut::new(Some(KeyboardShortcut::new(modifiers, logical_key)), None)
 }
 
+/// Shortcut for a key that is stored by its name, without modifiers
+pub fn key_shortcut(name: &Option<String>) -> Shortcut {
+    let key = name.as_deref().and_then(Key::from_name);
+    Shortcut::new(key.map(|k| KeyboardShortcut::new(Modifiers::NONE, k)), None)
+}
+
 pub fn keybind_button(
     ui: &mut Ui,
     text: impl Into<WidgetText>,
@@ -31,3 +41,23 @@ pub fn keybind_button(
     let btn = ui.add_enabled(enabled, btn);
     btn.on_hover_text(shortcut.format(&ModifierNames::NAMES, false))
 }
+
+/// Select a key without modifiers, which is stored by its name
+pub fn key_setting(ui: &mut Ui, name: &mut Option<String>, text: &str) {
+    let mut key = name.as_deref().and_then(Key::from_name);
+    let response = ui.add(
+        Keybind::new(&mut key, egui::Id::new(text))
+            .with_text(text)
+            .with_reset_key(Some(Key::Escape)),
+    );
+    if response.changed() {
+        if let Some(key) = key {
+            // Do not also handle the key press that selected it
+            ui.input_mut(|i| i.consume_key(Modifiers::NONE, key));
+        }
+        *name = key.map(|k| k.name().to_string());
+    }
+    if name.is_some() && ui.small_button("X").on_hover_text("No key").clicked() {
+        *name = None;
+    }
+}
@@ -1,9 +1,15 @@
+use crate::backend::autosave::Snapshot;
+use crate::backend::device_alias::{resolve_aliases, DeviceAlias};
+use crate::backend::history::History;
+use crate::backend::id::Id;
 use crate::backend::pipewire_utils::{pipewire_installed, Pipewire};
+use crate::backend::preset_file::PresetImport;
 use crate::backend::properties::MidiLearn;
 use crate::backend::MidiPort;
+use crate::gui::widgets::file_error::FileError;
 use crate::gui::widgets::input_settings::InputTab;
 use crate::gui::widgets::mapping_settings::OutputTab;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
 #[derive(Default, Clone, Debug)]
@@ -13,6 +19,21 @@ pub struct State {
     pub pipewire_status: Option<Pipewire>,
     pub pipewire_error: Option<String>,
     pub midi_learn: MidiLearn,
+    /// Last program change that did not match any preset
+    pub program_change_warning: Option<String>,
+    /// Undo / redo stack of the properties
+    pub history: History,
+    /// Device aliases that refer to an available port
+    alias_inputs: Vec<(String, MidiPort)>,
+    alias_outputs: Vec<(String, MidiPort)>,
+    /// Presets from another file that are waiting for the user to confirm the import
+    pub preset_import: Option<PresetImport>,
+    /// Autosaved changes from the last session, waiting for the user to recover or discard them
+    pub recovery: Option<Snapshot>,
+    /// Shown in a dialog until the user closes it
+    pub file_error: Option<FileError>,
+    /// The open file was changed by another program while there are unsaved changes
+    pub file_changed: bool,
     file_path: Option<PathBuf>,
     pub path_changed: bool,
 }
@@ -47,10 +68,63 @@ impl State {
     pub fn file_path(&self) -> &Option<PathBuf> {
         &self.file_path
     }
+
+    pub fn resolve_aliases(&mut self, aliases: &[DeviceAlias]) {
+        self.alias_inputs = resolve_aliases(aliases, &self.available_inputs);
+        self.alias_outputs = resolve_aliases(aliases, &self.available_outputs);
+    }
+
+    /// Available input with this port name, or the one that the alias with this name refers to
+    pub fn input_port(&self, port_name: &str) -> Option<&MidiPort> {
+        find_port(port_name, &self.alias_inputs, &self.available_inputs)
+    }
+
+    /// Available output with this port name, or the one that the alias with this name refers to
+    pub fn output_port(&self, port_name: &str) -> Option<&MidiPort> {
+        find_port(port_name, &self.alias_outputs, &self.available_outputs)
+    }
+
+    /// Inputs that can be selected, the aliases first
+    pub fn input_choices(&self) -> Vec<MidiPort> {
+        port_choices(&self.alias_inputs, &self.available_inputs)
+    }
+
+    /// Outputs that can be selected, the aliases first
+    pub fn output_choices(&self) -> Vec<MidiPort> {
+        port_choices(&self.alias_outputs, &self.available_outputs)
+    }
+}
+
+fn find_port<'a>(
+    port_name: &str,
+    aliases: &'a [(String, MidiPort)],
+    ports: &'a [MidiPort],
+) -> Option<&'a MidiPort> {
+    aliases
+        .iter()
+        .find(|(name, _)| name == port_name)
+        .map(|(_, port)| port)
+        .or_else(|| ports.iter().find(|p| p.readable == port_name))
+}
+
+fn port_choices(aliases: &[(String, MidiPort)], ports: &[MidiPort]) -> Vec<MidiPort> {
+    aliases
+        .iter()
+        .map(|(name, port)| MidiPort {
+            readable: name.clone(),
+            internal: port.internal.clone(),
+        })
+        .chain(ports.iter().cloned())
+        .collect()
 }
 
 #[derive(Default)]
 pub struct TabState {
-    pub mapping_tabs: HashMap<String, OutputTab>,
-    pub input_tabs: HashMap<usize, InputTab>,
+    pub mapping_tabs: HashMap<Id, OutputTab>,
+    pub input_tabs: HashMap<Id, InputTab>,
+    pub global_tab: InputTab,
+    /// Tabs of the input overrides in the preset tab, by input id
+    pub override_tabs: HashMap<Id, InputTab>,
+    /// Presets that are selected in the export list
+    pub export_selection: HashSet<Id>,
 }
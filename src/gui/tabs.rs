@@ -1,18 +1,23 @@
+use crate::backend::id::Id;
+
+pub mod group;
+pub mod history;
 pub mod input_settings;
 pub mod preset;
+pub mod preset_transfer;
 pub mod quick_start;
 pub mod recent_files;
+pub mod setlist;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Default)]
 pub enum Tab {
+    #[default]
     RecentFiles,
     InputSettings,
     QuickStart,
-    Preset(usize),
-}
-
-impl Default for Tab {
-    fn default() -> Self {
-        Self::RecentFiles
-    }
+    Setlist,
+    History,
+    PresetTransfer,
+    Preset(Id),
+    Group(Id),
 }
@@ -0,0 +1,69 @@
+use std::sync::{Arc, Mutex};
+
+use egui::Ui;
+
+use crate::backend::id::Id;
+use crate::backend::properties::Properties;
+use crate::gui::state::{State, TabState};
+use crate::gui::widgets::routes::{input_frame, route_list};
+
+pub fn group_tab(
+    ui: &mut Ui,
+    properties: Arc<Mutex<Properties>>,
+    state: Arc<Mutex<State>>,
+    id: Id,
+    tab_state: &mut TabState,
+) {
+    let mut properties = properties.lock().unwrap();
+    let mut state = state.lock().unwrap();
+
+    let inputs = properties.inputs.clone();
+    let available_outputs = state.output_choices();
+
+    let mut remove_group = false;
+    let mut add_variant = false;
+    let mut remove_route = None;
+
+    if let Some(group) = properties.group_mut(id) {
+        ui.horizontal(|ui| {
+            egui::TextEdit::singleline(&mut group.name)
+                .desired_width(ui.available_width() - 60.0)
+                .show(ui);
+            remove_group = ui.button("Remove").clicked();
+        });
+        ui.horizontal(|ui| {
+            ui.label("Routes of this group are used by all its variants.");
+            add_variant = ui.button("Add variant").clicked();
+        });
+
+        inputs.iter().for_each(|input| {
+            input_frame(ui, |ui| {
+                ui.label(&input.port_name);
+                let mapping = group.mapping.entry(input.id).or_default();
+                if let Some(i) = route_list(
+                    ui,
+                    mapping,
+                    "group-mapping",
+                    tab_state,
+                    &mut state.midi_learn,
+                    input.id,
+                    &available_outputs,
+                ) {
+                    remove_route = Some((input.id, i));
+                }
+            });
+        });
+    } else {
+        ui.heading("Failed to load group");
+    }
+
+    if let Some((input, i)) = remove_route {
+        properties.remove_group_route(id, input, i);
+    }
+    if add_variant {
+        properties.add_variant(id);
+    }
+    if remove_group {
+        properties.remove_group(id);
+    }
+}
@@ -0,0 +1,43 @@
+use std::sync::{Arc, Mutex};
+
+use egui::{RichText, Ui};
+
+use crate::backend::properties::Properties;
+use crate::gui::state::State;
+
+pub fn history_tab(ui: &mut Ui, properties: Arc<Mutex<Properties>>, state: Arc<Mutex<State>>) {
+    ui.heading("Edit history");
+    ui.label(RichText::new("Click on a change to go back (or forward) to it").small());
+    ui.separator();
+
+    let mut properties = properties.lock().unwrap();
+    let mut state = state.lock().unwrap();
+    let history = &mut state.history;
+
+    let mut undo_steps = 0;
+    let mut redo_steps = 0;
+
+    let undo_entries = history.undo_entries();
+    undo_entries.iter().enumerate().for_each(|(i, entry)| {
+        if ui.selectable_label(false, &entry.description).clicked() {
+            // Undo this change and everything after it
+            undo_steps = undo_entries.len() - i;
+        }
+    });
+    ui.label(RichText::new("Current").strong());
+    history.redo_entries().enumerate().for_each(|(i, entry)| {
+        if ui
+            .selectable_label(false, RichText::new(&entry.description).weak())
+            .clicked()
+        {
+            redo_steps = i + 1;
+        }
+    });
+
+    (0..undo_steps).for_each(|_| {
+        history.undo(&mut properties);
+    });
+    (0..redo_steps).for_each(|_| {
+        history.redo(&mut properties);
+    });
+}
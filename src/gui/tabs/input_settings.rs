@@ -6,7 +6,10 @@ use egui::{RichText, Ui};
 use crate::backend::input_settings::InputSettings;
 use crate::backend::properties::Properties;
 use crate::gui::state::{State, TabState};
-use crate::gui::widgets::input_settings::input_mapping_settings;
+use crate::gui::widgets::device_aliases::device_aliases;
+use crate::gui::widgets::input_settings::{global_mapping_settings, input_mapping_settings};
+use crate::gui::widgets::preset_switching::preset_triggers;
+use crate::gui::widgets::program_map::program_map_settings;
 
 pub fn input_settings(
     ui: &mut Ui,
@@ -17,11 +20,26 @@ pub fn input_settings(
     ui.heading("Input settings");
 
     let mut properties = properties.lock().unwrap();
-    let state = state.lock().unwrap();
+    let mut state = state.lock().unwrap();
 
-    let available_inputs = state.available_inputs.clone();
+    let available_inputs = state.input_choices();
+    let available_outputs = state.output_choices();
+    let presets: Vec<_> = properties
+        .presets
+        .iter()
+        .map(|p| (p.id, p.name.clone()))
+        .collect();
     let mut inputs_to_remove = Vec::new();
 
+    ui.label("Global filters and maps, applied to all inputs before their own settings:");
+    global_mapping_settings(
+        ui,
+        &mut properties.global_settings,
+        tab_state,
+        &mut state.midi_learn,
+    );
+    ui.separator();
+
     properties
         .inputs
         .iter_mut()
@@ -58,8 +76,46 @@ pub fn input_settings(
                 &mut input.use_program_change,
                 "Use Program Change to switch presets",
             );
+            if input.use_program_change {
+                ui.checkbox(
+                    &mut input.forward_program_change,
+                    "Also send Program Change to the outputs",
+                );
+                if !input.forward_program_change {
+                    ui.checkbox(
+                        &mut input.consume_bank_select,
+                        "Do not send bank select to the outputs",
+                    );
+                }
+                ui.collapsing(
+                    format!("Program Change rules ({})", input.program_map.len()),
+                    |ui| {
+                        program_map_settings(
+                            ui,
+                            &mut input.program_map,
+                            &presets,
+                            format!("{i}"),
+                            &mut state.midi_learn,
+                            input.id,
+                        );
+                    },
+                );
+            }
+            ui.collapsing(
+                format!("Preset triggers ({})", input.triggers.len()),
+                |ui| {
+                    preset_triggers(
+                        ui,
+                        &mut input.triggers,
+                        &presets,
+                        format!("{i}"),
+                        &mut state.midi_learn,
+                        input.id,
+                    );
+                },
+            );
 
-            input_mapping_settings(ui, input, i, tab_state);
+            input_mapping_settings(ui, input, tab_state, &mut state.midi_learn);
 
             ui.separator();
         });
@@ -68,7 +124,49 @@ pub fn input_settings(
         properties.inputs.push(InputSettings::default());
     }
 
-    inputs_to_remove.iter().for_each(|&i| {
-        properties.inputs.remove(i);
+    // Remove from the back, so the indices stay correct
+    inputs_to_remove.iter().rev().for_each(|&i| {
+        properties.remove_input(i);
     });
+
+    ui.separator();
+    ui.label("Send preset feedback (i.e. pad lights) to:");
+    // Colour red if the selected output is not available (anymore)
+    let feedback_output = &mut properties.feedback_output;
+    let text = if feedback_output.is_empty()
+        || available_outputs
+            .iter()
+            .any(|p| p.readable == *feedback_output)
+    {
+        RichText::new(feedback_output.as_str())
+    } else {
+        RichText::new(feedback_output.as_str()).color(Rgba::from_rgb(1.0, 0.0, 0.0))
+    };
+    egui::ComboBox::from_id_source("feedback-output")
+        .selected_text(text)
+        .width(200.0)
+        .wrap(true)
+        .show_ui(ui, |ui| {
+            ui.style_mut().wrap = Some(true);
+            ui.selectable_value(feedback_output, String::new(), "None");
+            available_outputs.iter().for_each(|output_option| {
+                let name = &output_option.readable;
+                ui.selectable_value(feedback_output, name.clone(), name);
+            });
+        });
+
+    ui.separator();
+    ui.heading("Device aliases");
+    ui.label(
+        RichText::new(
+            "Names that can be selected instead of a port, so this file also works on machines where the ports are named differently",
+        )
+        .small(),
+    );
+    device_aliases(
+        ui,
+        &mut properties.aliases,
+        &state.available_inputs,
+        &state.available_outputs,
+    );
 }
@@ -1,99 +1,190 @@
 use std::sync::{Arc, Mutex};
 
-use egui::{Frame, Margin, Rgba, RichText, Rounding, Ui};
+use egui::{ComboBox, Ui};
 
+use crate::backend::id::Id;
 use crate::backend::output_settings::OutputSettings;
+use crate::backend::preset::RouteOverride;
 use crate::backend::properties::Properties;
 use crate::gui::state::{State, TabState};
+use crate::gui::widgets::activation::activation_settings;
+use crate::gui::widgets::input_settings::input_override_settings;
 use crate::gui::widgets::mapping_settings::mapping_settings;
+use crate::gui::widgets::preset_switching::{preset_feedback, switch_policy_settings};
+use crate::gui::widgets::routes::{input_frame, route_list, route_output};
 
 pub fn preset_tab(
     ui: &mut Ui,
     properties: Arc<Mutex<Properties>>,
     state: Arc<Mutex<State>>,
-    id: usize,
+    id: Id,
     tab_state: &mut TabState,
 ) {
     let mut properties = properties.lock().unwrap();
-    let state = state.lock().unwrap();
+    let mut state = state.lock().unwrap();
 
     let inputs = properties.inputs.clone();
-    let available_outputs = state.available_outputs.clone();
+    let groups = properties.groups.clone();
+    let available_outputs = state.output_choices();
+    let feedback_output = properties.feedback_output.clone();
 
     let mut remove_preset = false;
 
-    if let Some(preset) = properties.presets.get_mut(id) {
+    let index = properties.preset_index(id);
+    if let Some(preset) = index.and_then(|i| properties.presets.get_mut(i)) {
         ui.horizontal(|ui| {
             egui::TextEdit::singleline(&mut preset.name)
                 .desired_width(ui.available_width() - 60.0)
                 .show(ui);
             remove_preset = ui.button("Remove").clicked();
         });
+        let group = preset
+            .group
+            .and_then(|g| groups.iter().find(|group| group.id == g));
+        if let Some(group) = group {
+            ui.label(format!("Variant of {}", group.name));
+        }
+        switch_policy_settings(ui, &mut preset.switch_policy, format!("{id}"));
+        if !feedback_output.is_empty() {
+            preset_feedback(
+                ui,
+                &mut preset.feedback,
+                format!("{id}"),
+                &mut state.midi_learn,
+            );
+        }
+        ui.collapsing(
+            format!("Activation messages ({})", preset.activation.messages.len()),
+            |ui| {
+                activation_settings(
+                    ui,
+                    &mut preset.activation,
+                    format!("{id}"),
+                    &available_outputs,
+                );
+            },
+        );
 
         inputs.iter().enumerate().for_each(|(input_id, input)| {
-            Frame::default()
-                .stroke(ui.visuals().widgets.noninteractive.bg_stroke)
-                .rounding(Rounding::same(5.0))
-                .inner_margin(5.0)
-                .outer_margin(Margin {
-                    left: 0.0,
-                    right: 0.0,
-                    top: 5.0,
-                    bottom: 0.0,
-                })
-                .show(ui, |ui| {
-                    ui.label(&input.port_name);
-                    let mapping = preset.mapping.entry(input_id).or_default();
-                    let mut maps_to_remove = Vec::new();
+            input_frame(ui, |ui| {
+                ui.label(&input.port_name);
 
-                    mapping.iter_mut().enumerate().for_each(|(map_id, output)| {
+                // Input settings that are different in this preset
+                let mut input_override = preset
+                    .input_overrides
+                    .get(&input.id)
+                    .cloned()
+                    .unwrap_or_default();
+                input_override_settings(
+                    ui,
+                    input,
+                    &mut input_override,
+                    tab_state,
+                    &mut state.midi_learn,
+                );
+                // Only store the inputs that are changed
+                if input_override.is_empty() {
+                    preset.input_overrides.remove(&input.id);
+                } else {
+                    preset.input_overrides.insert(input.id, input_override);
+                }
+
+                // Routes of the group, which can be changed or disabled for this variant
+                let inherited = group.and_then(|g| g.mapping.get(&input.id));
+                if let Some(inherited) = inherited.filter(|m| !m.is_empty()) {
+                    inherited.iter().enumerate().for_each(|(map_id, route)| {
+                        let mut route_override =
+                            preset.overrides.get(&route.id).cloned().unwrap_or_default();
                         ui.horizontal(|ui| {
-                            if ui.button("X").clicked() {
-                                maps_to_remove.push(map_id);
+                            override_selector(
+                                ui,
+                                &mut route_override,
+                                route,
+                                format!("override-{input_id}-{map_id}"),
+                            );
+                            match &mut route_override {
+                                RouteOverride::Replace(output) => {
+                                    route_output(
+                                        ui,
+                                        output,
+                                        format!("override-output-{input_id}-{map_id}"),
+                                        &available_outputs,
+                                    );
+                                }
+                                _ => {
+                                    ui.label(format!("Group route: {}", route.port_name));
+                                }
                             }
-                            // Colour red if the selected output is not available (anymore)
-                            let text = if available_outputs
-                                .iter()
-                                .any(|p| p.readable == output.port_name)
-                            {
-                                RichText::new(&output.port_name)
-                            } else {
-                                RichText::new(&output.port_name)
-                                    .color(Rgba::from_rgb(1.0, 0.0, 0.0))
-                            };
-                            egui::ComboBox::from_id_source(format!("mapping-{input_id}-{map_id}"))
-                                .selected_text(text)
-                                .width(0.5) // FIXME width not working
-                                .wrap(true)
-                                .show_ui(ui, |ui| {
-                                    ui.style_mut().wrap = Some(true);
-                                    available_outputs.iter().for_each(|output_option| {
-                                        ui.selectable_value(
-                                            &mut output.port_name,
-                                            output_option.readable.clone(),
-                                            output_option.readable.clone(),
-                                        );
-                                    });
-                                });
                         });
-
-                        mapping_settings(ui, output, input_id, tab_state);
+                        if let RouteOverride::Replace(output) = &mut route_override {
+                            mapping_settings(
+                                ui,
+                                output,
+                                tab_state,
+                                &mut state.midi_learn,
+                                input.id,
+                                &available_outputs,
+                            );
+                        }
+                        // Only store the routes that are not inherited
+                        if let RouteOverride::Inherit = route_override {
+                            preset.overrides.remove(&route.id);
+                        } else {
+                            preset.overrides.insert(route.id, route_override);
+                        }
                     });
+                    ui.separator();
+                }
 
-                    if ui.button("Add output").clicked() {
-                        mapping.push(OutputSettings::default());
-                    }
-
-                    maps_to_remove.iter().for_each(|&i| {
-                        mapping.remove(i);
-                    });
-                });
+                let mapping = preset.mapping.entry(input.id).or_default();
+                let to_remove = route_list(
+                    ui,
+                    mapping,
+                    "mapping",
+                    tab_state,
+                    &mut state.midi_learn,
+                    input.id,
+                    &available_outputs,
+                );
+                if let Some(i) = to_remove {
+                    mapping.remove(i);
+                }
+            });
         });
     } else {
         ui.heading("Failed to load preset");
     }
 
-    if remove_preset {
-        properties.remove_preset(id);
+    if let Some(index) = index.filter(|_| remove_preset) {
+        properties.remove_preset(index);
     }
 }
+
+fn override_selector(
+    ui: &mut Ui,
+    route_override: &mut RouteOverride,
+    route: &OutputSettings,
+    id_source: String,
+) {
+    ComboBox::from_id_source(id_source)
+        .selected_text(route_override.get_description())
+        .width(60.0)
+        .show_ui(ui, |ui| {
+            let options = [
+                RouteOverride::Inherit,
+                RouteOverride::Replace(route.clone()),
+                RouteOverride::Disable,
+            ];
+            options.into_iter().for_each(|option| {
+                let selected =
+                    std::mem::discriminant(&option) == std::mem::discriminant(route_override);
+                if ui
+                    .selectable_label(selected, option.get_description())
+                    .clicked()
+                    && !selected
+                {
+                    *route_override = option;
+                }
+            });
+        });
+}
@@ -0,0 +1,187 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use eframe::epaint::Rgba;
+use egui::{ComboBox, RichText, Ui};
+
+use crate::backend::id::Id;
+use crate::backend::input_settings::InputSettings;
+use crate::backend::preset_file::PresetImport;
+use crate::backend::properties::Properties;
+use crate::backend::MidiPort;
+use crate::gui::state::{State, TabState};
+use crate::gui::widgets::file_error::FileError;
+use crate::utils::{export_presets_dialog, import_presets_dialog};
+
+pub fn preset_transfer_tab(
+    ui: &mut Ui,
+    properties: &Arc<Mutex<Properties>>,
+    state: &Arc<Mutex<State>>,
+    loading: &Arc<Mutex<bool>>,
+    tab_state: &mut TabState,
+) {
+    ui.heading("Export presets");
+    ui.label(RichText::new("Save the selected presets to a separate file").small());
+    {
+        let properties = properties.lock().unwrap();
+        let selection = &mut tab_state.export_selection;
+        selection.retain(|id| properties.preset_index(*id).is_some());
+        properties.presets.iter().for_each(|preset| {
+            let mut selected = selection.contains(&preset.id);
+            if ui.checkbox(&mut selected, &preset.name).changed() {
+                if selected {
+                    selection.insert(preset.id);
+                } else {
+                    selection.remove(&preset.id);
+                }
+            }
+        });
+        let export = ui
+            .add_enabled(!selection.is_empty(), egui::Button::new("Export"))
+            .clicked();
+        if export {
+            let presets = properties.export_presets(selection);
+            let loading = Arc::clone(loading);
+            let state = Arc::clone(state);
+            let _ = thread::spawn(move || {
+                *loading.lock().unwrap() = true;
+                if let Some(Err((file, e))) = export_presets_dialog(&presets) {
+                    state.lock().unwrap().file_error = Some(FileError::save(&file, &e));
+                }
+                *loading.lock().unwrap() = false;
+            });
+        }
+    }
+
+    ui.separator();
+    ui.heading("Import presets");
+    if ui.button("Open preset file").clicked() {
+        let properties = Arc::clone(properties);
+        let state = Arc::clone(state);
+        let loading = Arc::clone(loading);
+        let _ = thread::spawn(move || {
+            *loading.lock().unwrap() = true;
+            if let Some((location, result)) = import_presets_dialog() {
+                let inputs = properties.lock().unwrap().inputs.clone();
+                let mut state = state.lock().unwrap();
+                match result {
+                    Ok(file) => {
+                        let import = PresetImport::new(file, &inputs, &state.output_choices());
+                        state.preset_import = Some(import);
+                    }
+                    Err(e) => {
+                        state.preset_import = None;
+                        state.file_error = Some(FileError::load(&location, &e));
+                    }
+                }
+            }
+            *loading.lock().unwrap() = false;
+        });
+    }
+
+    let mut properties = properties.lock().unwrap();
+    let mut state = state.lock().unwrap();
+    let available_outputs = state.output_choices();
+    let Some(import) = &mut state.preset_import else {
+        return;
+    };
+    let mut finished = false;
+
+    ui.label("Presets:");
+    import.file.presets.iter().for_each(|preset| {
+        let mut selected = import.selected.contains(&preset.id);
+        if ui.checkbox(&mut selected, &preset.name).changed() {
+            if selected {
+                import.selected.insert(preset.id);
+            } else {
+                import.selected.remove(&preset.id);
+            }
+        }
+    });
+
+    ui.label("Use the routes of these inputs for:");
+    egui::Grid::new("import-inputs").show(ui, |ui| {
+        import
+            .file
+            .inputs
+            .iter()
+            .enumerate()
+            .for_each(|(i, input)| {
+                ui.label(format!("Input {}: {}", i + 1, input.port_name));
+                let local = import.inputs.entry(input.id).or_default();
+                input_selector(ui, local, &properties.inputs, format!("import-input-{i}"));
+                ui.end_row();
+            });
+    });
+
+    if !import.outputs.is_empty() {
+        ui.label("These outputs are not available, send to:");
+        egui::Grid::new("import-outputs").show(ui, |ui| {
+            import
+                .outputs
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, (port, new))| {
+                    ui.label(RichText::new(port.as_str()).color(Rgba::from_rgb(1.0, 0.0, 0.0)));
+                    output_selector(ui, new, port, &available_outputs, format!("import-{i}"));
+                    ui.end_row();
+                });
+        });
+    }
+
+    ui.horizontal(|ui| {
+        let import_clicked = ui
+            .add_enabled(!import.selected.is_empty(), egui::Button::new("Import"))
+            .clicked();
+        if import_clicked {
+            properties.import_presets(import);
+            finished = true;
+        }
+        if ui.button("Cancel").clicked() {
+            finished = true;
+        }
+    });
+
+    if finished {
+        state.preset_import = None;
+    }
+}
+
+fn input_selector(
+    ui: &mut Ui,
+    local: &mut Option<Id>,
+    inputs: &[InputSettings],
+    id_source: String,
+) {
+    let name = |id: &Option<Id>| match inputs.iter().position(|i| Some(i.id) == *id) {
+        Some(i) => format!("Input {}: {}", i + 1, inputs[i].port_name),
+        None => "Leave out".to_string(),
+    };
+    ComboBox::from_id_source(id_source)
+        .selected_text(name(local))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(local, None, name(&None));
+            inputs.iter().for_each(|input| {
+                ui.selectable_value(local, Some(input.id), name(&Some(input.id)));
+            });
+        });
+}
+
+fn output_selector(
+    ui: &mut Ui,
+    new: &mut String,
+    port: &str,
+    available_outputs: &[MidiPort],
+    id_source: String,
+) {
+    ComboBox::from_id_source(id_source)
+        .selected_text(new.as_str())
+        .wrap(true)
+        .show_ui(ui, |ui| {
+            ui.selectable_value(new, port.to_string(), format!("{port} (keep)"));
+            available_outputs.iter().for_each(|output_option| {
+                let name = &output_option.readable;
+                ui.selectable_value(new, name.clone(), name);
+            });
+        });
+}
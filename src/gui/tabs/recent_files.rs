@@ -2,6 +2,7 @@ use crate::backend::properties::Properties;
 use crate::gui::data::RecentFiles;
 use crate::gui::state::State;
 use crate::gui::tabs::Tab;
+use crate::gui::widgets::file_error::FileError;
 use crate::utils::load;
 use egui::{RichText, TextStyle, Ui};
 use itertools::Itertools;
@@ -45,11 +46,18 @@ pub fn recent_files(
                     let _ = thread::spawn(move || {
                         *loading.lock().unwrap() = true;
                         let mut recent_files = recent_files.lock().unwrap();
-                        if load(&location, properties, current_tab) {
-                            state.lock().unwrap().set_file_path(file);
-                            recent_files.add(location);
-                        } else {
-                            recent_files.remove(&location);
+                        match load(&location, properties, current_tab) {
+                            Ok(()) => {
+                                state.lock().unwrap().set_file_path(file);
+                                recent_files.add(location);
+                            }
+                            Err(e) => {
+                                // Let the user decide whether to keep it in the list
+                                state.lock().unwrap().file_error = Some(FileError {
+                                    recent_file: Some(location.clone()),
+                                    ..FileError::load(&location, &e)
+                                });
+                            }
                         }
                         *loading.lock().unwrap() = false;
                     });
@@ -0,0 +1,118 @@
+use std::sync::{Arc, Mutex};
+
+use egui::{RichText, TextEdit, Ui};
+use egui_dnd::dnd;
+
+use crate::backend::properties::Properties;
+use crate::backend::setlist::SetlistEntry;
+use crate::gui::keybinds::key_setting;
+use crate::gui::widgets::preset_switching::preset_selector;
+
+pub fn setlist_tab(ui: &mut Ui, properties: Arc<Mutex<Properties>>) {
+    ui.heading("Setlist");
+
+    let mut properties = properties.lock().unwrap();
+    let presets: Vec<_> = properties
+        .presets
+        .iter()
+        .map(|p| (p.id, p.name.clone()))
+        .collect();
+    let position = properties.setlist_position;
+
+    ui.horizontal(|ui| {
+        let keys = &mut properties.setlist_keys;
+        key_setting(ui, &mut keys.previous, "Previous song");
+        key_setting(ui, &mut keys.next, "Next song");
+    })
+    .response
+    .on_hover_text("Click and press a key, i.e. of a page turner pedal");
+
+    let mut select_entry = None;
+    let mut to_remove = None;
+
+    let indices: Vec<_> = (0..properties.setlist.len()).collect();
+    let setlist = &mut properties.setlist;
+    let drag_response = dnd(ui, "setlist").show(indices.into_iter(), |ui, i, handle, _| {
+        let entry = &mut setlist[i];
+        ui.horizontal(|ui| {
+            handle.ui(ui, |ui| {
+                ui.label(egui_phosphor::regular::LIST);
+            });
+            if ui
+                .selectable_label(position == Some(i), format!("{}.", i + 1))
+                .on_hover_text("Go to this song")
+                .clicked()
+            {
+                select_entry = Some(i);
+            }
+            ui.add(TextEdit::singleline(&mut entry.title).desired_width(150.0));
+            preset_selector(
+                ui,
+                &mut entry.preset,
+                &presets,
+                format!("setlist-preset-{i}"),
+            );
+            if ui.button("X").clicked() {
+                to_remove = Some(i);
+            }
+        });
+        ui.add(
+            TextEdit::multiline(&mut entry.notes)
+                .hint_text("Notes")
+                .desired_rows(1),
+        );
+    });
+
+    if let Some(update) = drag_response.final_update() {
+        // Keep the position on the same song
+        if let Some(position) = properties.setlist_position {
+            let moved = if update.to > update.from {
+                update.to - 1
+            } else {
+                update.to
+            };
+            properties.setlist_position = Some(if position == update.from {
+                moved
+            } else if position > update.from && position <= moved {
+                position - 1
+            } else if position < update.from && position >= moved {
+                position + 1
+            } else {
+                position
+            });
+        }
+        drag_response.update_vec(&mut properties.setlist);
+    }
+
+    if ui.button("Add song").clicked() {
+        let title = format!("Song {}", properties.setlist.len() + 1);
+        let preset = properties
+            .presets
+            .get(properties.current_preset)
+            .map(|p| p.id)
+            .unwrap_or_default();
+        properties.setlist.push(SetlistEntry::new(title, preset));
+    }
+
+    if let Some(i) = to_remove {
+        properties.setlist.remove(i);
+        properties.setlist_position = match properties.setlist_position {
+            Some(position) if position == i => None,
+            Some(position) if position > i => Some(position - 1),
+            position => position,
+        };
+    }
+    if let Some(i) = select_entry {
+        properties.select_setlist_entry(i);
+    }
+
+    // Notes of the current song, big enough to read on stage
+    if let Some(song) = properties
+        .setlist_position
+        .and_then(|p| properties.setlist.get(p))
+    {
+        ui.separator();
+        ui.heading(&song.title);
+        ui.label(RichText::new(&song.notes).size(20.0));
+    }
+}
@@ -1,4 +1,14 @@
+pub mod activation;
+pub mod device_aliases;
+pub mod file_error;
+pub mod history;
 pub mod input_settings;
 pub mod mapping_settings;
+pub mod midi_learn;
+pub mod preset_switching;
+pub mod program_map;
+pub mod recovery;
+pub mod routes;
 pub mod save_load;
+pub mod setlist;
 pub mod transpose;
@@ -0,0 +1,155 @@
+use eframe::epaint::Rgba;
+use egui::{ComboBox, DragValue, RichText, TextEdit, Ui};
+
+use crate::backend::activation::{
+    parse_sysex, Activation, ActivationKind, ActivationMessage, ActivationTiming, MorphFade,
+    MAX_MORPH_TIME,
+};
+use crate::backend::MidiPort;
+use crate::gui::widgets::mapping_settings::filter_value_selector;
+
+pub fn activation_settings(
+    ui: &mut Ui,
+    activation: &mut Activation,
+    unique_id: String,
+    available_outputs: &[MidiPort],
+) {
+    ui.horizontal(|ui| {
+        ui.label("Send:");
+        ActivationTiming::all().iter().for_each(|timing| {
+            ui.selectable_value(&mut activation.timing, *timing, timing.get_description());
+        });
+    });
+    ui.horizontal(|ui| {
+        ui.label("Morph from previous preset:");
+        ui.add(
+            DragValue::new(&mut activation.morph_time)
+                .speed(0.05)
+                .clamp_range(0.0..=MAX_MORPH_TIME)
+                .suffix(" s"),
+        )
+        .on_hover_text(
+            "CC messages that are in both presets are changed gradually. \
+            Routes fade in and out on the channels they have used.",
+        );
+        if activation.morph_time > 0.0 {
+            ComboBox::from_id_source(format!("morph-fade-{unique_id}"))
+                .selected_text(activation.fade.get_description())
+                .show_ui(ui, |ui| {
+                    MorphFade::all().iter().for_each(|fade| {
+                        ui.selectable_value(&mut activation.fade, *fade, fade.get_description());
+                    });
+                });
+        }
+    });
+
+    let mut to_remove = None;
+    activation
+        .messages
+        .iter_mut()
+        .enumerate()
+        .for_each(|(i, message)| {
+            let id = format!("activation-{unique_id}-{i}");
+            ui.horizontal(|ui| {
+                if ui.button("X").clicked() {
+                    to_remove = Some(i);
+                }
+                // Colour red if the selected output is not available (anymore)
+                let text = if available_outputs
+                    .iter()
+                    .any(|p| p.readable == message.port_name)
+                {
+                    RichText::new(&message.port_name)
+                } else {
+                    RichText::new(&message.port_name).color(Rgba::from_rgb(1.0, 0.0, 0.0))
+                };
+                ComboBox::from_id_source(format!("{id}-output"))
+                    .selected_text(text)
+                    .width(150.0)
+                    .wrap(true)
+                    .show_ui(ui, |ui| {
+                        ui.style_mut().wrap = Some(true);
+                        available_outputs.iter().for_each(|output_option| {
+                            ui.selectable_value(
+                                &mut message.port_name,
+                                output_option.readable.clone(),
+                                output_option.readable.clone(),
+                            );
+                        });
+                    });
+                ComboBox::from_id_source(format!("{id}-kind"))
+                    .selected_text(message.kind.get_description())
+                    .width(100.0)
+                    .show_ui(ui, |ui| {
+                        ActivationKind::all().into_iter().for_each(|option| {
+                            let selected = std::mem::discriminant(&option)
+                                == std::mem::discriminant(&message.kind);
+                            if ui
+                                .selectable_label(selected, option.get_description())
+                                .clicked()
+                                && !selected
+                            {
+                                message.kind = option;
+                            }
+                        });
+                    });
+                message_settings(ui, &mut message.kind);
+            });
+        });
+
+    if ui.button("Add message").clicked() {
+        let port_name = activation
+            .messages
+            .last()
+            .map(|m| m.port_name.clone())
+            .unwrap_or_default();
+        activation.messages.push(ActivationMessage {
+            port_name,
+            kind: ActivationKind::default(),
+        });
+    }
+    if let Some(i) = to_remove {
+        activation.messages.remove(i);
+    }
+}
+
+fn message_settings(ui: &mut Ui, kind: &mut ActivationKind) {
+    match kind {
+        ActivationKind::Program {
+            channel,
+            bank_msb,
+            bank_lsb,
+            program,
+        } => {
+            ui.label("Channel:");
+            ui.add(DragValue::new(channel).speed(0.3).clamp_range(1..=16));
+            ui.label("Bank:");
+            ui.add(filter_value_selector(bank_msb, -1.0).clamp_range(-1..=127))
+                .on_hover_text("Bank select MSB (CC 0)");
+            ui.add(filter_value_selector(bank_lsb, -1.0).clamp_range(-1..=127))
+                .on_hover_text("Bank select LSB (CC 32)");
+            ui.label("Program:");
+            ui.add(DragValue::new(program).speed(0.3).clamp_range(0..=127));
+        }
+        ActivationKind::Controller {
+            channel,
+            controller,
+            value,
+        } => {
+            ui.label("Channel:");
+            ui.add(DragValue::new(channel).speed(0.3).clamp_range(1..=16));
+            ui.label("CC:");
+            ui.add(DragValue::new(controller).speed(0.3).clamp_range(0..=127));
+            ui.label("Value:");
+            ui.add(DragValue::new(value).speed(0.3).clamp_range(0..=127));
+        }
+        ActivationKind::SysEx(hex) => {
+            // Colour red if this is not a valid SysEx message
+            if parse_sysex(hex).is_none() {
+                ui.style_mut().visuals.override_text_color =
+                    Some(Rgba::from_rgb(1.0, 0.0, 0.0).into());
+            }
+            ui.add(TextEdit::singleline(hex).hint_text("F0 ... F7"));
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use eframe::epaint::Rgba;
+use egui::{RichText, Ui};
+use itertools::Itertools;
+
+use crate::backend::device_alias::{DeviceAlias, PortPattern};
+use crate::backend::MidiPort;
+
+/// Editable list of device aliases, showing the ports they refer to on this machine
+pub fn device_aliases(
+    ui: &mut Ui,
+    aliases: &mut Vec<DeviceAlias>,
+    available_inputs: &[MidiPort],
+    available_outputs: &[MidiPort],
+) {
+    let mut to_remove = None;
+
+    aliases.iter_mut().enumerate().for_each(|(i, alias)| {
+        ui.horizontal(|ui| {
+            if ui.button("X").clicked() {
+                to_remove = Some(i);
+            }
+            egui::TextEdit::singleline(&mut alias.name)
+                .hint_text("Name")
+                .desired_width(150.0)
+                .show(ui);
+            let found: Vec<_> = [alias.find(available_inputs), alias.find(available_outputs)]
+                .into_iter()
+                .flatten()
+                .map(|p| p.readable.as_str())
+                .unique()
+                .collect();
+            if found.is_empty() {
+                ui.label(RichText::new("Not found").color(Rgba::from_rgb(1.0, 0.0, 0.0)));
+            } else {
+                ui.label(RichText::new(format!("= {}", found.join(", "))).small());
+            }
+        });
+
+        let mut pattern_to_remove = None;
+        alias
+            .patterns
+            .iter_mut()
+            .enumerate()
+            .for_each(|(j, pattern)| {
+                ui.horizontal(|ui| {
+                    ui.add_space(20.0);
+                    if ui.small_button("X").clicked() {
+                        pattern_to_remove = Some(j);
+                    }
+                    let mut source = pattern.source().to_string();
+                    let mut edit = egui::TextEdit::singleline(&mut source)
+                        .hint_text("Port name (regular expression)")
+                        .desired_width(250.0);
+                    if pattern.error().is_some() {
+                        edit = edit.text_color(Rgba::from_rgb(1.0, 0.0, 0.0).into());
+                    }
+                    let mut response = edit.show(ui).response;
+                    if let Some(error) = pattern.error() {
+                        response = response.on_hover_text(RichText::new(error).monospace());
+                    }
+                    if response.changed() {
+                        *pattern = PortPattern::new(source);
+                    }
+                });
+            });
+        if let Some(j) = pattern_to_remove {
+            alias.patterns.remove(j);
+        }
+        ui.horizontal(|ui| {
+            ui.add_space(20.0);
+            if ui.small_button("Add port name").clicked() {
+                alias.patterns.push(PortPattern::default());
+            }
+        });
+    });
+
+    if let Some(i) = to_remove {
+        aliases.remove(i);
+    }
+
+    if ui.button("Add alias").clicked() {
+        aliases.push(DeviceAlias::new(format!("Device {}", aliases.len() + 1)));
+    }
+}
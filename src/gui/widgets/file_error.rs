@@ -0,0 +1,88 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use egui::Context;
+use egui_modal::{Icon, Modal};
+
+use crate::backend::file_error::{LoadError, SaveError};
+use crate::gui::data::RecentFiles;
+use crate::gui::state::State;
+
+/// Error of opening or saving a file, shown in a dialog
+#[derive(Clone, Debug)]
+pub struct FileError {
+    pub title: String,
+    pub message: String,
+    /// Recent file that could not be opened, which can be removed from the list
+    pub recent_file: Option<PathBuf>,
+}
+
+impl FileError {
+    pub fn load(location: &Path, error: &LoadError) -> Self {
+        Self {
+            title: format!("Could not open {}", file_name(location)),
+            message: error.to_string(),
+            recent_file: None,
+        }
+    }
+
+    pub fn save(location: &Path, error: &SaveError) -> Self {
+        Self {
+            title: format!("Could not save {}", file_name(location)),
+            message: error.to_string(),
+            recent_file: None,
+        }
+    }
+}
+
+fn file_name(location: &Path) -> String {
+    location
+        .file_name()
+        .unwrap_or(location.as_os_str())
+        .to_string_lossy()
+        .to_string()
+}
+
+pub fn file_error_dialog(
+    ctx: &Context,
+    state: &Arc<Mutex<State>>,
+    recent_files: &Arc<Mutex<RecentFiles>>,
+) {
+    let mut state = state.lock().unwrap();
+    let Some(error) = &state.file_error else {
+        return;
+    };
+    let modal = Modal::new(ctx, "file-error");
+    let mut closed = false;
+    let mut remove_file = None;
+    modal.show(|ui| {
+        modal.title(ui, &error.title);
+        modal.frame(ui, |ui| {
+            modal.body_and_icon(ui, &error.message, Icon::Error);
+        });
+        modal.buttons(ui, |ui| {
+            if modal.button(ui, "Close").clicked() {
+                closed = true;
+            }
+            if let Some(file) = &error.recent_file {
+                if modal
+                    .caution_button(ui, "Remove from recent files")
+                    .clicked()
+                {
+                    remove_file = Some(file.clone());
+                    closed = true;
+                }
+            }
+        });
+    });
+
+    if closed {
+        state.file_error = None;
+    } else if !modal.is_open() {
+        modal.open();
+    }
+    drop(state);
+    if let Some(file) = remove_file {
+        recent_files.lock().unwrap().remove(&file);
+    }
+}
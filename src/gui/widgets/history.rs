@@ -0,0 +1,37 @@
+use egui::Ui;
+
+use crate::backend::properties::Properties;
+use crate::gui::keybinds::{keybind_button, Keybinds};
+use crate::gui::state::State;
+
+/// Undo / redo buttons
+pub fn history_buttons(
+    ui: &mut Ui,
+    properties: &mut Properties,
+    state: &mut State,
+    keybinds: &Keybinds,
+) {
+    ui.horizontal(|ui| {
+        let history = &mut state.history;
+        if keybind_button(
+            ui,
+            egui_phosphor::regular::ARROW_COUNTER_CLOCKWISE,
+            &keybinds.undo,
+            history.can_undo(),
+        )
+        .clicked()
+        {
+            history.undo(properties);
+        }
+        if keybind_button(
+            ui,
+            egui_phosphor::regular::ARROW_CLOCKWISE,
+            &keybinds.redo,
+            history.can_redo(),
+        )
+        .clicked()
+        {
+            history.redo(properties);
+        }
+    });
+}
@@ -1,8 +1,12 @@
-use crate::backend::common_settings::CommonSettings;
-use crate::backend::input_settings::InputSettings;
+use crate::backend::common_settings::{CommonSettings, GlobalSettings};
+use crate::backend::id::Id;
+use crate::backend::input_settings::{InputOverride, InputSettings};
+use crate::backend::properties::MidiLearn;
 use crate::gui::state::TabState;
 use crate::gui::widgets::mapping_settings::cc_map::cc_map_settings;
-use crate::gui::widgets::mapping_settings::note_filter::note_filter_settings;
+use crate::gui::widgets::mapping_settings::note_filter::{
+    note_filter_settings, transpose_settings,
+};
 use crate::gui::widgets::mapping_settings::velocity_map::velocity_map_settings;
 use egui::collapsing_header::CollapsingState;
 use egui::{RichText, TextStyle, Ui};
@@ -20,12 +24,93 @@ pub enum InputTab {
 pub fn input_mapping_settings(
     ui: &mut Ui,
     input_settings: &mut InputSettings,
-    input_id: usize,
     tab_state: &mut TabState,
+    midi_learn: &mut MidiLearn,
 ) {
-    let unique_id = format!("{}", input_id);
-    let current_tab = tab_state.input_tabs.entry(input_id).or_default();
+    let current_tab = tab_state.input_tabs.entry(input_settings.id).or_default();
+    filter_map_settings(
+        ui,
+        input_settings,
+        format!("{}", input_settings.id),
+        current_tab,
+        true,
+        midi_learn,
+        Some(input_settings.id),
+    );
+}
+
+/// Settings of the global layer, which is applied before the input settings
+pub fn global_mapping_settings(
+    ui: &mut Ui,
+    settings: &mut GlobalSettings,
+    tab_state: &mut TabState,
+    midi_learn: &mut MidiLearn,
+) {
+    filter_map_settings(
+        ui,
+        settings,
+        "global".to_string(),
+        &mut tab_state.global_tab,
+        false,
+        midi_learn,
+        None,
+    );
+}
+
+/// Settings of an input as a preset changes them
+pub fn input_override_settings(
+    ui: &mut Ui,
+    input_settings: &InputSettings,
+    input_override: &mut InputOverride,
+    tab_state: &mut TabState,
+    midi_learn: &mut MidiLearn,
+) {
+    let mut reset = false;
+    ui.horizontal(|ui| {
+        let overridden = input_override.get_descriptions();
+        let label = if overridden.is_empty() {
+            "Input settings: input default".to_string()
+        } else {
+            format!("Input settings: changed {}", overridden.join(", "))
+        };
+        ui.label(RichText::new(label).small()).on_hover_text(
+            "Program change settings and preset triggers always come from the input itself",
+        );
+        if !overridden.is_empty() {
+            reset = ui.small_button("Reset to input default").clicked();
+        }
+    });
 
+    let mut settings = input_override.apply(input_settings);
+    let current_tab = tab_state
+        .override_tabs
+        .entry(input_settings.id)
+        .or_default();
+    filter_map_settings(
+        ui,
+        &mut settings,
+        format!("override-{}", input_settings.id),
+        current_tab,
+        true,
+        midi_learn,
+        Some(input_settings.id),
+    );
+    *input_override = if reset {
+        InputOverride::default()
+    } else {
+        InputOverride::from_changes(input_settings, &settings)
+    };
+}
+
+fn filter_map_settings(
+    ui: &mut Ui,
+    settings: &mut impl CommonSettings,
+    unique_id: String,
+    current_tab: &mut InputTab,
+    show_transpose: bool,
+    midi_learn: &mut MidiLearn,
+    input: Option<Id>,
+) {
     // ui.separator();
 
     let mut header = CollapsingState::load_with_default_open(
@@ -66,13 +151,16 @@ pub fn input_mapping_settings(
                 ui.label("Hello :)");
             }
             InputTab::NoteFilter => {
-                note_filter_settings(ui, input_settings, unique_id);
+                if show_transpose {
+                    transpose_settings(ui, settings);
+                }
+                note_filter_settings(ui, settings, unique_id, midi_learn, input);
             }
             InputTab::CcMap => {
-                cc_map_settings(ui, input_settings.cc_map_mut(), unique_id);
+                cc_map_settings(ui, settings.cc_map_mut(), unique_id, midi_learn, input);
             }
             InputTab::VelocityMap => {
-                velocity_map_settings(ui, input_settings, unique_id);
+                velocity_map_settings(ui, settings, unique_id);
             }
         });
 
@@ -1,17 +1,27 @@
+use crate::backend::activation::MAX_MORPH_TIME;
 use crate::backend::common_settings::CommonSettings;
+use crate::backend::id::Id;
 use eframe::emath;
 use egui::collapsing_header::CollapsingState;
-use egui::{DragValue, RichText, TextStyle, Ui};
+use egui::{ComboBox, DragValue, RichText, TextStyle, Ui};
 
-use crate::backend::output_settings::OutputSettings;
+use crate::backend::output_settings::{OutputSettings, ReleasePolicy};
+use crate::backend::properties::MidiLearn;
+use crate::backend::MidiPort;
 use crate::gui::state::TabState;
 use crate::gui::widgets::mapping_settings::cc_map::cc_map_settings;
-use crate::gui::widgets::mapping_settings::note_filter::note_filter_settings;
+use crate::gui::widgets::mapping_settings::mpe::mpe_settings;
+use crate::gui::widgets::mapping_settings::note_filter::{
+    note_filter_settings, transpose_settings,
+};
 use crate::gui::widgets::mapping_settings::velocity_map::velocity_map_settings;
+use crate::gui::widgets::mapping_settings::voices::voice_settings;
 
 pub mod cc_map;
+pub mod mpe;
 pub mod note_filter;
 pub mod velocity_map;
+pub mod voices;
 
 #[derive(PartialEq, Eq, Default)]
 pub enum OutputTab {
@@ -21,16 +31,23 @@ pub enum OutputTab {
     NoteFilter,
     CcMap,
     Velocity,
+    Mpe,
+    Voices,
 }
 
 pub fn mapping_settings(
     ui: &mut Ui,
     output_settings: &mut OutputSettings,
-    input_id: usize,
     tab_state: &mut TabState,
+    midi_learn: &mut MidiLearn,
+    input: Id,
+    available_outputs: &[MidiPort],
 ) {
-    let unique_id = format!("{}-{}", input_id, output_settings.port_name);
-    let current_tab = tab_state.mapping_tabs.entry(unique_id.clone()).or_default();
+    let unique_id = output_settings.id.to_string();
+    let current_tab = tab_state
+        .mapping_tabs
+        .entry(output_settings.id)
+        .or_default();
 
     // ui.separator();
 
@@ -69,6 +86,16 @@ pub fn mapping_settings(
                 OutputTab::CcMap,
                 RichText::new("CC").text_style(TextStyle::Small),
             );
+            ui.selectable_value(
+                current_tab,
+                OutputTab::Mpe,
+                RichText::new("MPE").text_style(TextStyle::Small),
+            );
+            ui.selectable_value(
+                current_tab,
+                OutputTab::Voices,
+                RichText::new("Voices").text_style(TextStyle::Small),
+            );
         })
         .body(|ui| {
             match current_tab {
@@ -79,15 +106,38 @@ pub fn mapping_settings(
                         &mut output_settings.buffer_pedals,
                         RichText::new("Send pedal events after switching presets"),
                     );
+                    ui.checkbox(
+                        &mut output_settings.ignore_global_settings,
+                        RichText::new("Ignore global filters and maps"),
+                    );
+                    release_settings(ui, &mut output_settings.release, &unique_id);
                 }
                 OutputTab::NoteFilter => {
-                    note_filter_settings(ui, output_settings, unique_id);
+                    transpose_settings(ui, output_settings);
+                    note_filter_settings(ui, output_settings, unique_id, midi_learn, Some(input));
                 }
                 OutputTab::Velocity => {
                     velocity_map_settings(ui, output_settings, unique_id);
                 }
                 OutputTab::CcMap => {
-                    cc_map_settings(ui, output_settings.cc_map_mut(), unique_id);
+                    cc_map_settings(
+                        ui,
+                        output_settings.cc_map_mut(),
+                        unique_id,
+                        midi_learn,
+                        Some(input),
+                    );
+                }
+                OutputTab::Mpe => {
+                    mpe_settings(ui, &mut output_settings.mpe);
+                }
+                OutputTab::Voices => {
+                    voice_settings(
+                        ui,
+                        &mut output_settings.voices,
+                        unique_id,
+                        available_outputs,
+                    );
                 }
             }
         });
@@ -108,7 +158,10 @@ pub fn mapping_settings(
     }
 }
 
-pub fn filter_value_selector<Num: emath::Numeric>(value: &mut Num, any_value: f64) -> DragValue {
+pub fn filter_value_selector<Num: emath::Numeric>(
+    value: &mut Num,
+    any_value: f64,
+) -> DragValue<'_> {
     DragValue::new(value)
         .custom_formatter(move |v, _| {
             if v == any_value {
@@ -126,3 +179,33 @@ pub fn filter_value_selector<Num: emath::Numeric>(value: &mut Num, any_value: f6
         })
         .speed(0.3)
 }
+
+fn release_settings(ui: &mut Ui, release: &mut ReleasePolicy, unique_id: &str) {
+    ui.horizontal(|ui| {
+        ui.label("When leaving the preset:")
+            .on_hover_text("Applied when the next preset becomes active");
+        ComboBox::from_id_source(format!("release-{unique_id}"))
+            .selected_text(release.get_description())
+            .show_ui(ui, |ui| {
+                ReleasePolicy::all().iter().for_each(|option| {
+                    let selected =
+                        std::mem::discriminant(option) == std::mem::discriminant(release);
+                    if ui
+                        .selectable_label(selected, option.get_description())
+                        .clicked()
+                        && !selected
+                    {
+                        *release = *option;
+                    }
+                });
+            });
+        if let ReleasePolicy::FadeOut { time } = release {
+            ui.add(
+                DragValue::new(time)
+                    .speed(0.05)
+                    .clamp_range(0.0..=MAX_MORPH_TIME)
+                    .suffix(" s"),
+            );
+        }
+    });
+}
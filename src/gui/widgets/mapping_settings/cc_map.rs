@@ -1,11 +1,21 @@
 use crate::backend::common_settings::{CcMap, CcMapping};
+use crate::backend::id::Id;
+use crate::backend::properties::{LearnKind, MidiLearn};
 use eframe::epaint::Color32;
 use egui::{Button, ComboBox, DragValue, RichText, TextStyle, Ui};
 use egui_extras::{Column, TableBuilder};
+use midly::MidiMessage;
 
 use crate::gui::widgets::mapping_settings::filter_value_selector;
+use crate::gui::widgets::midi_learn::midi_learn_button;
 
-pub fn cc_map_settings(ui: &mut Ui, cc_map: &mut CcMap, unique_id: String) {
+pub fn cc_map_settings(
+    ui: &mut Ui,
+    cc_map: &mut CcMap,
+    unique_id: String,
+    midi_learn: &mut MidiLearn,
+    input: Option<Id>,
+) {
     let mut has_duplicates = false;
     let mut to_remove = None;
 
@@ -13,6 +23,7 @@ pub fn cc_map_settings(ui: &mut Ui, cc_map: &mut CcMap, unique_id: String) {
         .column(Column::exact(15.0))
         .column(Column::auto())
         .column(Column::auto())
+        .column(Column::auto())
         .column(Column::remainder())
         .header(13.0, |mut header| {
             header.col(|_| {});
@@ -22,6 +33,7 @@ pub fn cc_map_settings(ui: &mut Ui, cc_map: &mut CcMap, unique_id: String) {
             header.col(|ui| {
                 ui.label(RichText::new("CC").small());
             });
+            header.col(|_| {});
             header.col(|ui| {
                 ui.label(RichText::new("Target").small());
             });
@@ -67,12 +79,29 @@ pub fn cc_map_settings(ui: &mut Ui, cc_map: &mut CcMap, unique_id: String) {
                                 filter_value_selector(cc, -1.0).clamp_range(-1..=128),
                             );
                         });
+                        row.col(|ui| {
+                            if i == last_index {
+                                return;
+                            }
+                            if let Some((channel, MidiMessage::Controller { controller, .. })) =
+                                midi_learn_button(
+                                    ui,
+                                    midi_learn,
+                                    format!("cc-{unique_id}-{i}"),
+                                    LearnKind::Controller,
+                                    input,
+                                )
+                            {
+                                *ch = channel.as_int() + 1;
+                                *cc = controller.as_int() as i8;
+                            }
+                        });
                         row.col(|ui| {
                             ui.horizontal(|ui| {
                                 ComboBox::from_id_source(format!("cc-target-{unique_id}-{i}"))
                                     .selected_text(map.get_description())
                                     .show_ui(ui, |ui| {
-                                        CcMapping::all().clone().map(|option| {
+                                        CcMapping::all().iter().for_each(|option| {
                                             ui.selectable_value(
                                                 map,
                                                 option.clone(),
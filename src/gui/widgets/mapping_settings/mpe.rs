@@ -0,0 +1,47 @@
+use egui::{DragValue, RichText, Ui};
+
+use crate::backend::output_settings::{MpeSettings, MpeZone};
+
+pub fn mpe_settings(ui: &mut Ui, settings: &mut MpeSettings) {
+    ui.checkbox(&mut settings.enabled, "MPE");
+    if !settings.enabled {
+        return;
+    }
+    ui.label(
+        RichText::new("Expression on member channels follows the notes sent to this output")
+            .small(),
+    );
+
+    ui.horizontal(|ui| {
+        ui.label("Input zone:");
+        zone_selector(ui, &mut settings.zone);
+        ui.label("Member channels:");
+        ui.add(
+            DragValue::new(&mut settings.member_channels)
+                .speed(0.1)
+                .clamp_range(1..=15),
+        );
+    });
+
+    ui.checkbox(
+        &mut settings.reallocate,
+        "Reallocate member channels on the output",
+    );
+    if settings.reallocate {
+        ui.horizontal(|ui| {
+            ui.label("Output zone:");
+            zone_selector(ui, &mut settings.output_zone);
+            ui.label("Member channels:");
+            ui.add(
+                DragValue::new(&mut settings.output_member_channels)
+                    .speed(0.1)
+                    .clamp_range(1..=15),
+            );
+        });
+    }
+}
+
+fn zone_selector(ui: &mut Ui, zone: &mut MpeZone) {
+    ui.selectable_value(zone, MpeZone::Lower, "Lower");
+    ui.selectable_value(zone, MpeZone::Upper, "Upper");
+}
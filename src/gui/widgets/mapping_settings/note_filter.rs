@@ -2,13 +2,17 @@ use eframe::epaint::Color32;
 use egui::style::{Selection, Widgets};
 use egui::{Button, ComboBox, DragValue, RichText, Slider, TextStyle, Ui};
 use egui_extras::{Column, TableBuilder};
+use midly::MidiMessage;
 
 use crate::backend::common_settings::{ChannelMapping, CommonSettings};
+use crate::backend::id::Id;
+use crate::backend::properties::{LearnKind, MidiLearn};
 use crate::gui::widgets::mapping_settings::filter_value_selector;
+use crate::gui::widgets::midi_learn::midi_learn_button;
 use crate::gui::widgets::transpose::transpose;
 use crate::utils::{midi_to_note, note_to_midi};
 
-pub fn note_filter_settings(ui: &mut Ui, settings: &mut impl CommonSettings, unique_id: String) {
+pub fn transpose_settings(ui: &mut Ui, settings: &mut impl CommonSettings) {
     ui.horizontal(|ui| {
         transpose(ui, &mut settings.transpose_mut().value);
         ui.checkbox(
@@ -18,13 +22,30 @@ pub fn note_filter_settings(ui: &mut Ui, settings: &mut impl CommonSettings, uni
     });
 
     ui.separator();
+}
 
+pub fn note_filter_settings(
+    ui: &mut Ui,
+    settings: &mut impl CommonSettings,
+    unique_id: String,
+    midi_learn: &mut MidiLearn,
+    input: Option<Id>,
+) {
     ui.checkbox(
         settings.key_filter_enabled_mut(),
         RichText::new("Enable note filter"),
     );
 
-    ui.vertical(|ui| {
+    ui.horizontal(|ui| {
+        if let Some((_, MidiMessage::NoteOn { key, .. })) = midi_learn_button(
+            ui,
+            midi_learn,
+            format!("key-low-{unique_id}"),
+            LearnKind::Note,
+            input,
+        ) {
+            settings.key_filter_mut().0 = key.as_int();
+        }
         // Hacky way to fill the slider from the current value to the end:
         ui.visuals_mut().widgets.inactive.bg_fill = Selection::default().bg_fill;
         ui.visuals_mut().selection.bg_fill = Widgets::default().inactive.bg_fill;
@@ -37,13 +58,28 @@ pub fn note_filter_settings(ui: &mut Ui, settings: &mut impl CommonSettings, uni
     });
 
     let moved_high = ui
-        .add(
-            Slider::new(&mut settings.key_filter_mut().1, 0..=128)
-                .custom_formatter(|n, _| midi_to_note(n as u8))
-                .custom_parser(note_to_midi)
-                .trailing_fill(true),
-        )
-        .dragged();
+        .horizontal(|ui| {
+            let mut moved = false;
+            if let Some((_, MidiMessage::NoteOn { key, .. })) = midi_learn_button(
+                ui,
+                midi_learn,
+                format!("key-high-{unique_id}"),
+                LearnKind::Note,
+                input,
+            ) {
+                settings.key_filter_mut().1 = key.as_int();
+                moved = true;
+            }
+            moved
+                | ui.add(
+                    Slider::new(&mut settings.key_filter_mut().1, 0..=128)
+                        .custom_formatter(|n, _| midi_to_note(n as u8))
+                        .custom_parser(note_to_midi)
+                        .trailing_fill(true),
+                )
+                .dragged()
+        })
+        .inner;
 
     // Make sure that it is a valid range
     if moved_high && settings.key_filter_mut().1 < settings.key_filter_mut().0 {
@@ -99,13 +135,24 @@ pub fn note_filter_settings(ui: &mut Ui, settings: &mut impl CommonSettings, uni
                                 i != last_index,
                                 filter_value_selector(ch_in, 0.0).clamp_range(0..=16),
                             );
+                            if i != last_index {
+                                if let Some((channel, _)) = midi_learn_button(
+                                    ui,
+                                    midi_learn,
+                                    format!("ch-{unique_id}-{i}"),
+                                    LearnKind::Channel,
+                                    input,
+                                ) {
+                                    *ch_in = channel.as_int() + 1;
+                                }
+                            }
                         });
                         row.col(|ui| {
                             ui.horizontal(|ui| {
                                 ComboBox::from_id_source(format!("ch-target-{unique_id}-{i}"))
                                     .selected_text(ch_out.get_description())
                                     .show_ui(ui, |ui| {
-                                        ChannelMapping::all().clone().map(|option| {
+                                        ChannelMapping::all().iter().for_each(|option| {
                                             ui.selectable_value(
                                                 ch_out,
                                                 option.clone(),
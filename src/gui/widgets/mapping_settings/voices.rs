@@ -0,0 +1,80 @@
+use eframe::epaint::Rgba;
+use egui::{ComboBox, DragValue, RichText, Ui};
+
+use crate::backend::output_settings::{Voice, VoiceDistributor, VoiceMode};
+use crate::backend::MidiPort;
+
+pub fn voice_settings(
+    ui: &mut Ui,
+    settings: &mut VoiceDistributor,
+    unique_id: String,
+    available_outputs: &[MidiPort],
+) {
+    ui.checkbox(
+        &mut settings.enabled,
+        "Distribute notes over multiple outputs",
+    );
+    if !settings.enabled {
+        return;
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Mode:");
+        VoiceMode::all().iter().for_each(|mode| {
+            ui.selectable_value(&mut settings.mode, *mode, mode.get_description());
+        });
+    });
+
+    let mut to_remove = None;
+    settings
+        .voices
+        .iter_mut()
+        .enumerate()
+        .for_each(|(i, voice)| {
+            ui.horizontal(|ui| {
+                if ui.button("X").clicked() {
+                    to_remove = Some(i);
+                }
+                ui.label(format!("Voice {}:", i + 1));
+                // Colour red if the selected output is not available (anymore)
+                let text = if available_outputs
+                    .iter()
+                    .any(|p| p.readable == voice.port_name)
+                {
+                    RichText::new(&voice.port_name)
+                } else {
+                    RichText::new(&voice.port_name).color(Rgba::from_rgb(1.0, 0.0, 0.0))
+                };
+                ComboBox::from_id_source(format!("voice-{unique_id}-{i}"))
+                    .selected_text(text)
+                    .wrap(true)
+                    .show_ui(ui, |ui| {
+                        ui.style_mut().wrap = Some(true);
+                        available_outputs.iter().for_each(|output_option| {
+                            ui.selectable_value(
+                                &mut voice.port_name,
+                                output_option.readable.clone(),
+                                output_option.readable.clone(),
+                            );
+                        });
+                    });
+                ui.label("Channel:");
+                ui.add(
+                    DragValue::new(&mut voice.channel)
+                        .speed(0.3)
+                        .clamp_range(1..=16),
+                );
+            });
+        });
+
+    if ui.button("Add voice").clicked() {
+        let voice = settings.voices.last().cloned().unwrap_or_default();
+        settings.voices.push(Voice {
+            channel: (voice.channel % 16) + 1,
+            ..voice
+        });
+    }
+    if let Some(i) = to_remove {
+        settings.voices.remove(i);
+    }
+}
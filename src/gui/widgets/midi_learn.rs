@@ -0,0 +1,52 @@
+use egui::{RichText, Ui};
+use midly::live::LiveEvent;
+use midly::num::u4;
+use midly::MidiMessage;
+
+use crate::backend::id::Id;
+use crate::backend::properties::{LearnKind, MidiLearn};
+
+/// Button that waits for the next matching MIDI event while it is selected.
+/// Only events from `input` are learned, or from any input if it is `None`.
+/// Returns the learned event once, after it has been received.
+pub fn midi_learn_button(
+    ui: &mut Ui,
+    midi_learn: &mut MidiLearn,
+    target: String,
+    kind: LearnKind,
+    input: Option<Id>,
+) -> Option<(u4, MidiMessage)> {
+    let mut learned = None;
+    let learning = midi_learn.is_learning(&target);
+
+    if learning {
+        if let Some((source, data)) = midi_learn.result.take() {
+            match LiveEvent::parse(&data) {
+                Ok(LiveEvent::Midi { channel, message })
+                    if kind.accepts(&message, input, source) =>
+                {
+                    midi_learn.cancel();
+                    learned = Some((channel, message));
+                }
+                // Keep waiting for a message of the right kind
+                _ => {}
+            }
+        }
+    }
+
+    let button = ui
+        .selectable_label(
+            learning && learned.is_none(),
+            RichText::new(egui_phosphor::regular::EAR.to_string()).small(),
+        )
+        .on_hover_text("MIDI learn");
+    if button.clicked() {
+        if learning {
+            midi_learn.cancel();
+        } else {
+            midi_learn.start(target, kind, input);
+        }
+    }
+
+    learned
+}
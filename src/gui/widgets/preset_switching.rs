@@ -0,0 +1,198 @@
+use egui::{ComboBox, DragValue, Ui};
+use midly::MidiMessage;
+
+use crate::backend::id::Id;
+use crate::backend::preset::{PresetFeedback, SwitchPolicy};
+use crate::backend::properties::{LearnKind, MidiLearn};
+use crate::backend::triggers::{PresetTrigger, TriggerAction, TriggerEvent};
+use crate::gui::widgets::mapping_settings::filter_value_selector;
+use crate::gui::widgets::midi_learn::midi_learn_button;
+
+pub fn preset_triggers(
+    ui: &mut Ui,
+    triggers: &mut Vec<PresetTrigger>,
+    presets: &[(Id, String)],
+    unique_id: String,
+    midi_learn: &mut MidiLearn,
+    input: Id,
+) {
+    let mut to_remove = None;
+
+    triggers.iter_mut().enumerate().for_each(|(i, trigger)| {
+        let id = format!("trigger-{unique_id}-{i}");
+        ui.horizontal(|ui| {
+            if ui.button("X").clicked() {
+                to_remove = Some(i);
+            }
+            ComboBox::from_id_source(format!("{id}-event"))
+                .selected_text(trigger.event.get_description())
+                .width(50.0)
+                .show_ui(ui, |ui| {
+                    TriggerEvent::all().iter().for_each(|event| {
+                        ui.selectable_value(&mut trigger.event, *event, event.get_description());
+                    });
+                });
+            ui.label("Channel:");
+            ui.add(filter_value_selector(&mut trigger.channel, 0.0).clamp_range(0..=16));
+            ui.label(match trigger.event {
+                TriggerEvent::Note => "Key:",
+                TriggerEvent::Controller => "CC:",
+            });
+            ui.add(
+                DragValue::new(&mut trigger.number)
+                    .speed(0.3)
+                    .clamp_range(0..=127),
+            );
+            if let Some(learned) =
+                learn_button(ui, midi_learn, id.clone(), trigger.event, Some(input))
+            {
+                (trigger.channel, trigger.number) = learned;
+            }
+
+            // Actions like "select by value" only exist for CC
+            let options = TriggerAction::options(trigger.event);
+            if !options.iter().any(|o| o.same_kind(&trigger.action)) {
+                trigger.action = TriggerAction::Preset(Id::default());
+            }
+            ComboBox::from_id_source(format!("{id}-action"))
+                .selected_text(trigger.action.get_description())
+                .show_ui(ui, |ui| {
+                    options.iter().for_each(|option| {
+                        let selected = option.same_kind(&trigger.action);
+                        if ui
+                            .selectable_label(selected, option.get_description())
+                            .clicked()
+                            && !selected
+                        {
+                            trigger.action = *option;
+                        }
+                    });
+                });
+            if let TriggerAction::Preset(preset) = &mut trigger.action {
+                preset_selector(ui, preset, presets, format!("{id}-preset"));
+            }
+        });
+    });
+
+    if ui.button("Add trigger").clicked() {
+        triggers.push(PresetTrigger::default());
+    }
+    if let Some(i) = to_remove {
+        triggers.remove(i);
+    }
+}
+
+/// Select a preset by its id
+pub fn preset_selector(ui: &mut Ui, preset: &mut Id, presets: &[(Id, String)], id_source: String) {
+    let selected = presets
+        .iter()
+        .find(|(id, _)| id == preset)
+        .map(|(_, name)| name.clone())
+        .unwrap_or("Select preset".to_string());
+    ComboBox::from_id_source(id_source)
+        .selected_text(selected)
+        .show_ui(ui, |ui| {
+            presets.iter().for_each(|(id, name)| {
+                ui.selectable_value(preset, *id, name);
+            });
+        });
+}
+
+pub fn preset_feedback(
+    ui: &mut Ui,
+    feedback: &mut PresetFeedback,
+    unique_id: String,
+    midi_learn: &mut MidiLearn,
+) {
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut feedback.enabled, "Feedback");
+        if !feedback.enabled {
+            return;
+        }
+        let id = format!("feedback-{unique_id}");
+        ComboBox::from_id_source(format!("{id}-event"))
+            .selected_text(feedback.event.get_description())
+            .width(50.0)
+            .show_ui(ui, |ui| {
+                TriggerEvent::all().iter().for_each(|event| {
+                    ui.selectable_value(&mut feedback.event, *event, event.get_description());
+                });
+            });
+        ui.label("Channel:");
+        ui.add(
+            DragValue::new(&mut feedback.channel)
+                .speed(0.3)
+                .clamp_range(1..=16),
+        );
+        ui.label(match feedback.event {
+            TriggerEvent::Note => "Key:",
+            TriggerEvent::Controller => "CC:",
+        });
+        ui.add(
+            DragValue::new(&mut feedback.number)
+                .speed(0.3)
+                .clamp_range(0..=127),
+        );
+        if let Some((channel, number)) = learn_button(ui, midi_learn, id, feedback.event, None) {
+            feedback.channel = channel.max(1);
+            feedback.number = number;
+        }
+        ui.label("On:");
+        ui.add(
+            DragValue::new(&mut feedback.on_value)
+                .speed(0.3)
+                .clamp_range(0..=127),
+        );
+        ui.label("Off:");
+        ui.add(
+            DragValue::new(&mut feedback.off_value)
+                .speed(0.3)
+                .clamp_range(0..=127),
+        );
+    });
+}
+
+pub fn switch_policy_settings(ui: &mut Ui, policy: &mut SwitchPolicy, unique_id: String) {
+    ui.horizontal(|ui| {
+        ui.label("Switch away from this preset:")
+            .on_hover_text("Only applies to switching with MIDI (program changes and triggers)");
+        ComboBox::from_id_source(format!("switch-policy-{unique_id}"))
+            .selected_text(policy.get_description())
+            .show_ui(ui, |ui| {
+                SwitchPolicy::all().iter().for_each(|option| {
+                    let selected = std::mem::discriminant(option) == std::mem::discriminant(policy);
+                    if ui
+                        .selectable_label(selected, option.get_description())
+                        .clicked()
+                        && !selected
+                    {
+                        *policy = *option;
+                    }
+                });
+            });
+        if let SwitchPolicy::AfterRelease { include_pedal } = policy {
+            ui.checkbox(include_pedal, "and the sustain pedal");
+        }
+    });
+}
+
+/// Learn the (channel, key or controller) of a trigger or feedback message
+fn learn_button(
+    ui: &mut Ui,
+    midi_learn: &mut MidiLearn,
+    target: String,
+    event: TriggerEvent,
+    input: Option<Id>,
+) -> Option<(u8, u8)> {
+    let kind = match event {
+        TriggerEvent::Note => LearnKind::Note,
+        TriggerEvent::Controller => LearnKind::Controller,
+    };
+    match midi_learn_button(ui, midi_learn, target, kind, input)? {
+        (channel, MidiMessage::NoteOn { key, .. }) => Some((channel.as_int() + 1, key.as_int())),
+        (channel, MidiMessage::Controller { controller, .. }) => {
+            Some((channel.as_int() + 1, controller.as_int()))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,135 @@
+use egui::{DragValue, RichText, Ui};
+use egui_extras::{Column, TableBuilder};
+use midly::MidiMessage;
+
+use crate::backend::id::Id;
+use crate::backend::input_settings::ProgramMapping;
+use crate::backend::properties::{LearnKind, MidiLearn};
+use crate::gui::widgets::mapping_settings::filter_value_selector;
+use crate::gui::widgets::midi_learn::midi_learn_button;
+use crate::gui::widgets::preset_switching::preset_selector;
+
+pub fn program_map_settings(
+    ui: &mut Ui,
+    program_map: &mut Vec<ProgramMapping>,
+    presets: &[(Id, String)],
+    unique_id: String,
+    midi_learn: &mut MidiLearn,
+    input: Id,
+) {
+    let mut to_remove = None;
+
+    if program_map.is_empty() {
+        ui.label(
+            RichText::new(
+                "Without rules, program change 0 selects the first preset, 1 the second, etc.",
+            )
+            .small(),
+        );
+    } else {
+        // Tables need a unique id when there are multiple on one page
+        ui.push_id(format!("program-map-{unique_id}"), |ui| {
+            TableBuilder::new(ui)
+                .column(Column::exact(15.0))
+                .columns(Column::auto(), 5)
+                .column(Column::remainder())
+                .header(13.0, |mut header| {
+                    header.col(|_| {});
+                    ["Channel", "Bank MSB", "Bank LSB", "Program"]
+                        .into_iter()
+                        .for_each(|title| {
+                            header.col(|ui| {
+                                ui.label(RichText::new(title).small());
+                            });
+                        });
+                    header.col(|_| {});
+                    header.col(|ui| {
+                        ui.label(RichText::new("Preset").small());
+                    });
+                })
+                .body(|mut body| {
+                    program_map.iter_mut().enumerate().for_each(|(i, rule)| {
+                        body.row(20.0, |mut row| {
+                            row.col(|ui| {
+                                if ui.button("X").clicked() {
+                                    to_remove = Some(i);
+                                }
+                            });
+                            row.col(|ui| {
+                                ui.add(
+                                    filter_value_selector(&mut rule.channel, 0.0)
+                                        .clamp_range(0..=16),
+                                );
+                            });
+                            row.col(|ui| {
+                                ui.add(
+                                    filter_value_selector(&mut rule.bank_msb, -1.0)
+                                        .clamp_range(-1..=127),
+                                );
+                            });
+                            row.col(|ui| {
+                                ui.add(
+                                    filter_value_selector(&mut rule.bank_lsb, -1.0)
+                                        .clamp_range(-1..=127),
+                                );
+                            });
+                            row.col(|ui| {
+                                ui.add(
+                                    DragValue::new(&mut rule.program)
+                                        .speed(0.3)
+                                        .clamp_range(0..=127),
+                                );
+                            });
+                            row.col(|ui| {
+                                if let Some((channel, MidiMessage::ProgramChange { program })) =
+                                    midi_learn_button(
+                                        ui,
+                                        midi_learn,
+                                        format!("program-{unique_id}-{i}"),
+                                        LearnKind::Program,
+                                        Some(input),
+                                    )
+                                {
+                                    rule.channel = channel.as_int() + 1;
+                                    rule.program = program.as_int();
+                                }
+                            });
+                            row.col(|ui| {
+                                preset_selector(
+                                    ui,
+                                    &mut rule.preset,
+                                    presets,
+                                    format!("program-preset-{unique_id}-{i}"),
+                                );
+                            });
+                        });
+                    });
+                });
+        });
+    }
+
+    if ui.button("Add rule").clicked() {
+        let rule = program_map
+            .last()
+            .map(|last| {
+                // Continue with the next program and preset
+                let next = presets
+                    .iter()
+                    .position(|(id, _)| *id == last.preset)
+                    .and_then(|i| presets.get(i + 1));
+                ProgramMapping {
+                    program: (last.program + 1).min(127),
+                    preset: next.map(|(id, _)| *id).unwrap_or(last.preset),
+                    ..last.clone()
+                }
+            })
+            .unwrap_or_else(|| ProgramMapping {
+                preset: presets.first().map(|(id, _)| *id).unwrap_or_default(),
+                ..Default::default()
+            });
+        program_map.push(rule);
+    }
+    if let Some(i) = to_remove {
+        program_map.remove(i);
+    }
+}
@@ -0,0 +1,88 @@
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use eframe::epaint::Rgba;
+use egui::{Align2, Context, RichText};
+
+use crate::backend::autosave::autosave_dir;
+use crate::backend::file_format::FileFormat;
+use crate::backend::properties::Properties;
+use crate::gui::state::State;
+use crate::gui::tabs::Tab;
+
+/// Asks to recover the autosaved changes if an earlier session ended without saving
+pub fn recovery_prompt(
+    ctx: &Context,
+    properties: &Arc<Mutex<Properties>>,
+    state: &Arc<Mutex<State>>,
+    current_tab: &Arc<Mutex<Tab>>,
+) {
+    let Some(snapshot) = state.lock().unwrap().recovery.clone() else {
+        return;
+    };
+    let recovered = snapshot.properties();
+    let mut finished = false;
+    let mut recover = false;
+
+    egui::Window::new("Recover unsaved changes")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            let file = match &snapshot.file {
+                Some(file) => file.to_string_lossy().to_string(),
+                None => "a new file".to_string(),
+            };
+            ui.label(format!(
+                "An earlier session ended unexpectedly with unsaved changes to {file}, \
+                autosaved {} minutes ago.",
+                snapshot.age().as_secs() / 60
+            ));
+            if let Some(dir) = autosave_dir() {
+                ui.label(
+                    RichText::new(format!("Older snapshots are kept in {}", dir.display())).small(),
+                );
+            }
+            if recovered.is_none() {
+                ui.label(
+                    RichText::new("The autosaved changes could not be read")
+                        .color(Rgba::from_rgb(1.0, 0.0, 0.0)),
+                );
+            }
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(recovered.is_some(), egui::Button::new("Recover"))
+                    .clicked()
+                {
+                    recover = true;
+                    finished = true;
+                }
+                if ui.button("Discard").clicked() {
+                    finished = true;
+                }
+            });
+        });
+
+    let mut properties = properties.lock().unwrap();
+    let mut state = state.lock().unwrap();
+    if recover {
+        if let Some(recovered) = recovered {
+            // Not marked as saved, so it shows up as unsaved changes to the original file
+            *properties = recovered;
+            properties.loaded = true;
+            properties.file_format = FileFormat::default();
+            if let Some(file) = snapshot.file.clone() {
+                // Save in the format of the original file, like when it is loaded
+                properties.file_format = fs::read_to_string(&file)
+                    .map(|contents| FileFormat::of_file(&file, &contents))
+                    .unwrap_or(FileFormat::from_path(&file).unwrap_or_default());
+                state.set_file_path(file);
+            }
+            *current_tab.lock().unwrap() = Tab::QuickStart;
+        }
+    }
+    if finished {
+        snapshot.dismiss();
+        state.recovery = None;
+    }
+}
@@ -0,0 +1,97 @@
+use egui::{Frame, Margin, Rgba, RichText, Rounding, Ui};
+
+use crate::backend::id::Id;
+use crate::backend::output_settings::OutputSettings;
+use crate::backend::properties::MidiLearn;
+use crate::backend::MidiPort;
+use crate::gui::state::TabState;
+use crate::gui::widgets::mapping_settings::mapping_settings;
+
+/// Frame around the routes of one input
+pub fn input_frame(ui: &mut Ui, add_contents: impl FnOnce(&mut Ui)) {
+    Frame::default()
+        .stroke(ui.visuals().widgets.noninteractive.bg_stroke)
+        .rounding(Rounding::same(5.0))
+        .inner_margin(5.0)
+        .outer_margin(Margin {
+            left: 0.0,
+            right: 0.0,
+            top: 5.0,
+            bottom: 0.0,
+        })
+        .show(ui, add_contents);
+}
+
+/// Select the output port of a route
+pub fn route_output(
+    ui: &mut Ui,
+    output: &mut OutputSettings,
+    id_source: String,
+    available_outputs: &[MidiPort],
+) {
+    if output.voices.enabled {
+        ui.label(format!(
+            "Voice distributor ({} voices)",
+            output.voices.voices.len()
+        ));
+        return;
+    }
+    // Colour red if the selected output is not available (anymore)
+    let text = if available_outputs
+        .iter()
+        .any(|p| p.readable == output.port_name)
+    {
+        RichText::new(&output.port_name)
+    } else {
+        RichText::new(&output.port_name).color(Rgba::from_rgb(1.0, 0.0, 0.0))
+    };
+    egui::ComboBox::from_id_source(id_source)
+        .selected_text(text)
+        .width(0.5) // FIXME width not working
+        .wrap(true)
+        .show_ui(ui, |ui| {
+            ui.style_mut().wrap = Some(true);
+            available_outputs.iter().for_each(|output_option| {
+                ui.selectable_value(
+                    &mut output.port_name,
+                    output_option.readable.clone(),
+                    output_option.readable.clone(),
+                );
+            });
+        });
+}
+
+/// Editable list of routes, returns the index of the route that should be removed
+pub fn route_list(
+    ui: &mut Ui,
+    mapping: &mut Vec<OutputSettings>,
+    id_source: &str,
+    tab_state: &mut TabState,
+    midi_learn: &mut MidiLearn,
+    input: Id,
+    available_outputs: &[MidiPort],
+) -> Option<usize> {
+    let mut to_remove = None;
+
+    mapping.iter_mut().enumerate().for_each(|(map_id, output)| {
+        ui.horizontal(|ui| {
+            if ui.button("X").clicked() {
+                to_remove = Some(map_id);
+            }
+            route_output(
+                ui,
+                output,
+                format!("{id_source}-{}", output.id),
+                available_outputs,
+            );
+        });
+
+        mapping_settings(ui, output, tab_state, midi_learn, input, available_outputs);
+    });
+
+    if ui.button("Add output").clicked() {
+        mapping.push(OutputSettings::default());
+    }
+
+    to_remove
+}
@@ -3,6 +3,7 @@ use crate::gui::data::RecentFiles;
 use crate::gui::keybinds::{keybind_button, Keybinds};
 use crate::gui::state::State;
 use crate::gui::tabs::Tab;
+use crate::gui::widgets::file_error::FileError;
 use crate::utils::{load_dialog, save, save_dialog};
 use egui::Ui;
 use std::path::PathBuf;
@@ -27,7 +28,7 @@ pub fn save_load(
 
         if let Some(filename) = state.lock().unwrap().file_path().clone() {
             if keybind_button(ui, "Save", &keybinds.save, changed).clicked() {
-                gui_save(filename, properties, loading)
+                gui_save(filename, properties, loading, state)
             }
         }
 
@@ -51,10 +52,16 @@ pub fn gui_load(
     let state = Arc::clone(state);
     let _ = thread::spawn(move || {
         *loading.lock().unwrap() = true;
-        if let Some(file) = load_dialog(properties, current_tab) {
-            let mut recent_files = recent_files.lock().unwrap();
-            state.lock().unwrap().set_file_path(file.clone());
-            recent_files.add(file);
+        match load_dialog(properties, current_tab) {
+            Some((file, Ok(()))) => {
+                let mut recent_files = recent_files.lock().unwrap();
+                state.lock().unwrap().set_file_path(file.clone());
+                recent_files.add(file);
+            }
+            Some((file, Err(e))) => {
+                state.lock().unwrap().file_error = Some(FileError::load(&file, &e));
+            }
+            None => {}
         }
         *loading.lock().unwrap() = false;
     });
@@ -64,12 +71,16 @@ pub fn gui_save(
     filename: PathBuf,
     properties: &Arc<Mutex<Properties>>,
     loading: &Arc<Mutex<bool>>,
+    state: &Arc<Mutex<State>>,
 ) {
     let loading = Arc::clone(loading);
     let properties = Arc::clone(properties);
+    let state = Arc::clone(state);
     let _ = thread::spawn(move || {
         *loading.lock().unwrap() = true;
-        save(&filename, properties);
+        if let Err(e) = save(&filename, properties) {
+            state.lock().unwrap().file_error = Some(FileError::save(&filename, &e));
+        }
         *loading.lock().unwrap() = false;
     });
 }
@@ -86,10 +97,16 @@ pub fn gui_save_as(
     let state = Arc::clone(state);
     let _ = thread::spawn(move || {
         *loading.lock().unwrap() = true;
-        if let Some(file) = save_dialog(properties) {
-            let mut recent_files = recent_files.lock().unwrap();
-            state.lock().unwrap().set_file_path(file.clone());
-            recent_files.add(file);
+        match save_dialog(properties) {
+            Some(Ok(file)) => {
+                let mut recent_files = recent_files.lock().unwrap();
+                state.lock().unwrap().set_file_path(file.clone());
+                recent_files.add(file);
+            }
+            Some(Err((file, e))) => {
+                state.lock().unwrap().file_error = Some(FileError::save(&file, &e));
+            }
+            None => {}
         }
         *loading.lock().unwrap() = false;
     });
@@ -0,0 +1,46 @@
+use egui::Ui;
+
+use crate::backend::properties::Properties;
+use crate::gui::keybinds::{key_shortcut, keybind_button};
+use crate::utils::shorten_str;
+
+/// Previous / next buttons for the setlist, with the title of the current song
+pub fn setlist_navigation(ui: &mut Ui, properties: &mut Properties) {
+    if properties.setlist.is_empty() {
+        return;
+    }
+    ui.horizontal(|ui| {
+        let previous = properties.setlist_step(false);
+        if keybind_button(
+            ui,
+            egui_phosphor::regular::CARET_LEFT,
+            &key_shortcut(&properties.setlist_keys.previous),
+            previous.is_some(),
+        )
+        .clicked()
+        {
+            if let Some(entry) = previous {
+                properties.select_setlist_entry(entry);
+            }
+        }
+        let title = properties
+            .setlist_position
+            .and_then(|p| properties.setlist.get(p))
+            .map(|song| song.title.clone())
+            .unwrap_or("Setlist".to_string());
+        ui.label(shorten_str(&title, 20)).on_hover_text(title);
+        let next = properties.setlist_step(true);
+        if keybind_button(
+            ui,
+            egui_phosphor::regular::CARET_RIGHT,
+            &key_shortcut(&properties.setlist_keys.next),
+            next.is_some(),
+        )
+        .clicked()
+        {
+            if let Some(entry) = next {
+                properties.select_setlist_entry(entry);
+            }
+        }
+    });
+}
@@ -1,17 +1,29 @@
-use std::env;
+use std::process::ExitCode;
 
+use clap::Parser;
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::EnvFilter;
 
+use crate::backend::schema::config_schema_string;
+use crate::cli::Cli;
 use crate::gui::Gui;
 
 mod backend;
+mod cli;
 mod gui;
 mod utils;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let gui = if let Some(preset_path) = args.get(1) {
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if cli.schema {
+        print!("{}", config_schema_string());
+        return ExitCode::SUCCESS;
+    }
+    if let Some(command) = cli.command {
+        return cli::run(command);
+    }
+
+    let gui = if let Some(preset_path) = cli.file {
         Gui::with_preset(preset_path)
     } else {
         Gui::default()
@@ -43,4 +55,5 @@ fn main() {
         }),
     )
     .unwrap();
+    ExitCode::SUCCESS
 }
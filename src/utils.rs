@@ -1,42 +1,47 @@
+use crate::backend::file_error::{LoadError, SaveError};
+use crate::backend::file_format::FileFormat;
+use crate::backend::preset_file::{PresetFile, PresetFileVersioned};
 use crate::backend::properties::{Properties, PropertiesV0_3_0, PropertiesVersioned};
 use crate::gui::tabs::Tab;
 use egui::Context;
 use pro_serde_versioned::{VersionedDeserialize, VersionedSerialize, VersionedUpgrade};
 use rfd::FileDialog;
 use serde::Deserialize;
-use std::fs::File;
-use std::io::BufReader;
+use std::collections::HashSet;
+use std::fs;
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
-pub fn save_dialog(properties: Arc<Mutex<Properties>>) -> Option<PathBuf> {
-    // TODO error handling
-    if let Some(mut location) = FileDialog::new()
+const NAME: Option<&str> = option_env!("CARGO_PKG_NAME");
+
+pub fn save_dialog(
+    properties: Arc<Mutex<Properties>>,
+) -> Option<Result<PathBuf, (PathBuf, SaveError)>> {
+    let mut location = FileDialog::new()
         .add_filter("Live MIDI splitter config", &["lmsc"])
-        .save_file()
-    {
-        if location.extension().is_none() {
-            location.set_extension("lmsc");
-        }
-        if save(&location, properties) {
-            return Some(location);
-        }
+        .add_filter("Live MIDI splitter config (TOML)", &["toml"])
+        .save_file()?;
+    if location.extension().is_none() {
+        location.set_extension("lmsc");
     }
-    None
+    Some(match save(&location, properties) {
+        Ok(()) => Ok(location),
+        Err(e) => Err((location, e)),
+    })
 }
 
-pub fn save(location: &PathBuf, properties: Arc<Mutex<Properties>>) -> bool {
-    let Ok(file) = File::create(location) else {
-        return false;
-    };
+pub fn save(location: &PathBuf, properties: Arc<Mutex<Properties>>) -> Result<(), SaveError> {
     let mut properties = properties.lock().unwrap();
-    let serialised = serialise_properties(&properties);
+    // The format the file is read with: from its extension, or else the one it was loaded in
+    let format = FileFormat::from_path(location).unwrap_or(properties.file_format);
+    let contents = format
+        .write(&serialise_properties(&properties))
+        .map_err(SaveError::Serialise)?;
+    fs::write(location, contents)?;
     properties.saved = true;
-    if serde_json::to_writer_pretty(file, &serialised).is_ok() {
-        return true;
-    }
-    false
+    properties.file_format = format;
+    Ok(())
 }
 
 pub fn serialise_properties(properties: &Properties) -> serde_json::Value {
@@ -48,48 +53,168 @@ pub fn serialise_properties(properties: &Properties) -> serde_json::Value {
 pub fn load_dialog(
     properties: Arc<Mutex<Properties>>,
     current_tab: Arc<Mutex<Tab>>,
-) -> Option<PathBuf> {
-    // TODO error handling
-    if let Some(location) = FileDialog::new()
-        .add_filter("Live MIDI splitter config", &["lmsc"])
-        .pick_file()
-    {
-        if load(&location, properties, current_tab) {
-            return Some(location);
-        }
-    }
-    None
+) -> Option<(PathBuf, Result<(), LoadError>)> {
+    let location = FileDialog::new()
+        .add_filter("Live MIDI splitter config", &["lmsc", "toml"])
+        .pick_file()?;
+    let result = load(&location, properties, current_tab);
+    Some((location, result))
 }
 
 pub fn load(
     location: &PathBuf,
     properties: Arc<Mutex<Properties>>,
     current_tab: Arc<Mutex<Tab>>,
-) -> bool {
-    let Ok(file) = File::open(location) else {
-        return false;
+) -> Result<(), LoadError> {
+    let loaded = read_properties(location)?;
+    *properties.lock().unwrap() = loaded;
+    properties.lock().unwrap().saved = true;
+    properties.lock().unwrap().loaded = true;
+    *current_tab.lock().unwrap() = Tab::QuickStart;
+    // TODO refresh view
+    Ok(())
+}
+
+/// Load the file again after it was changed by another program, staying on the same preset
+pub fn reload(location: &PathBuf, properties: &Arc<Mutex<Properties>>) -> Result<(), LoadError> {
+    let loaded = read_properties(location)?;
+    let mut properties = properties.lock().unwrap();
+    // Find the current preset by id, or else by name
+    let current_preset = properties
+        .presets
+        .get(properties.current_preset)
+        .and_then(|current| {
+            loaded
+                .preset_index(current.id)
+                .or_else(|| loaded.presets.iter().position(|p| p.name == current.name))
+        })
+        .unwrap_or(
+            properties
+                .current_preset
+                .min(loaded.presets.len().saturating_sub(1)),
+        );
+    let setlist_position = properties
+        .setlist_position
+        .filter(|p| *p < loaded.setlist.len());
+    *properties = Properties {
+        current_preset,
+        setlist_position,
+        pending_preset: None,
+        saved: true,
+        loaded: true,
+        last_clock: properties.last_clock,
+        ..loaded
     };
-    let reader = BufReader::new(file);
-    if let Ok(data) = serde_json::from_reader(reader) {
-        *properties.lock().unwrap() =
-            match PropertiesVersioned::versioned_deserialize::<serde_json::Value>(&data) {
-                Ok(versioned_data) => versioned_data.upgrade_to_latest(),
-                Err(_) => {
-                    // Try parsing un-versioned file (i.e. before v0.4.0)
-                    if let Ok(properties_old) = PropertiesV0_3_0::deserialize(data) {
-                        PropertiesVersioned::V1(properties_old).upgrade_to_latest()
+    Ok(())
+}
+
+/// Properties stored in a file, in any of the supported formats and versions
+pub fn read_properties(location: &PathBuf) -> Result<Properties, LoadError> {
+    let contents = fs::read_to_string(location)?;
+    let mut properties = parse_properties(&contents, FileFormat::of_file(location, &contents))?;
+    // Presets in a group that does not exist (`validate` reports these) are loaded without a group
+    let groups: HashSet<_> = properties.groups.iter().map(|g| g.id).collect();
+    properties.presets.iter_mut().for_each(|p| {
+        p.group = p.group.filter(|g| groups.contains(g));
+        p.clamp_times();
+    });
+    properties.groups.iter_mut().for_each(|g| g.clamp_times());
+    Ok(properties)
+}
+
+pub fn parse_properties(contents: &str, format: FileFormat) -> Result<Properties, LoadError> {
+    let data = format.parse(contents)?;
+    let version = data
+        .get("version_number")
+        .and_then(serde_json::Value::as_u64);
+    let mut properties = match version {
+        Some(version) if data.get("data").is_some() => {
+            if version > latest_version() {
+                return Err(LoadError::UnknownVersion(version));
+            }
+            let versioned = PropertiesVersioned::versioned_deserialize::<serde_json::Value>(&data)
+                .map_err(|e| {
+                    if version == latest_version() {
+                        LoadError::Invalid(e.to_string())
                     } else {
-                        return false;
+                        LoadError::Upgrade {
+                            version: Some(version),
+                            message: e.to_string(),
+                        }
                     }
-                }
-            };
+                })?;
+            versioned.upgrade_to_latest()
+        }
+        _ => {
+            // Try parsing un-versioned file (i.e. before v0.4.0)
+            let properties_old =
+                PropertiesV0_3_0::deserialize(data).map_err(|e| LoadError::Upgrade {
+                    version: None,
+                    message: e.to_string(),
+                })?;
+            PropertiesVersioned::V1(properties_old).upgrade_to_latest()
+        }
+    };
+    properties.file_format = format;
+    Ok(properties)
+}
+
+/// Version number that files are saved with
+pub fn latest_version() -> u64 {
+    serialise_properties(&Properties::default())
+        .get("version_number")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or_default()
+}
+
+pub fn export_presets_dialog(
+    presets: &PresetFile,
+) -> Option<Result<PathBuf, (PathBuf, SaveError)>> {
+    let mut location = FileDialog::new()
+        .add_filter("Live MIDI splitter presets", &["lmsp"])
+        .add_filter("Live MIDI splitter presets (TOML)", &["toml"])
+        .save_file()?;
+    if location.extension().is_none() {
+        location.set_extension("lmsp");
+    }
+    Some(match export_presets(&location, presets) {
+        Ok(()) => Ok(location),
+        Err(e) => Err((location, e)),
+    })
+}
+
+fn export_presets(location: &PathBuf, presets: &PresetFile) -> Result<(), SaveError> {
+    let format = FileFormat::from_path(location).unwrap_or_default();
+    let versioned: PresetFileVersioned = presets.to_owned().into();
+    let serialised = versioned
+        .versioned_serialize::<serde_json::Value>()
+        .map_err(|e| SaveError::Serialise(e.to_string()))?;
+    let contents = format.write(&serialised).map_err(SaveError::Serialise)?;
+    fs::write(location, contents)?;
+    Ok(())
+}
 
-        properties.lock().unwrap().saved = true;
-        *current_tab.lock().unwrap() = Tab::QuickStart;
-        // TODO refresh view
-        return true;
+pub fn import_presets_dialog() -> Option<(PathBuf, Result<PresetFile, LoadError>)> {
+    let location = FileDialog::new()
+        .add_filter("Live MIDI splitter presets", &["lmsp", "toml"])
+        .pick_file()?;
+    let result = load_presets(&location);
+    Some((location, result))
+}
+
+fn load_presets(location: &PathBuf) -> Result<PresetFile, LoadError> {
+    let contents = fs::read_to_string(location)?;
+    let data = FileFormat::of_file(location, &contents).parse(&contents)?;
+    if data.get("version_number").is_none() {
+        // Exported before the preset files had a version
+        return PresetFile::deserialize(data).map_err(|e| LoadError::Upgrade {
+            version: None,
+            message: e.to_string(),
+        });
     }
-    false
+    PresetFileVersioned::versioned_deserialize::<serde_json::Value>(&data)
+        .map(|versioned| versioned.upgrade_to_latest())
+        .map_err(|e| LoadError::Invalid(e.to_string()))
 }
 
 const NOTE_NAMES: &[&str; 12] = &[
@@ -144,3 +269,8 @@ pub fn repaint_gui(gui_ctx: &Arc<Mutex<Option<Context>>>) {
         ctx.request_repaint();
     }
 }
+
+/// Directory for our own files, like recent files and autosaves
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(NAME.unwrap_or("live-midi-splitter")))
+}